@@ -51,7 +51,8 @@ fn run_blur(base_dir: &Path) -> Result<()> {
     sched.add_source(&input, FuncSchedule::by_row());
     sched.add_func(&blur_h, FuncSchedule::by_column());
     sched.add_func(&blur_v, FuncSchedule::by_row());
-    let graph = Graph::new("blur3x3", vec![blur_h, blur_v], sched);
+    // Return the intermediate blur_h as well, so that we can write it out
+    let graph = Graph::with_outputs("blur3x3", vec![blur_h, blur_v], &["blur_h", "blur_v"], sched).unwrap();
 
     compile_and_run(
         base_dir,
@@ -64,14 +65,16 @@ fn run_brighten(base_dir: &Path) -> Result<()> {
     let (x, y) = (Var::X, Var::Y);
     source!(input);
     param!(p);
+    // Saturate rather than wrap, so that pixels brighter than 255 - p become white
     func!(bright = input.at(x, y) + &p);
+    let bright = bright.with_store_policy(StorePolicy::Saturate);
     let mut sched = Schedule::new();
     sched.add_source(&input, FuncSchedule::by_row());
     sched.add_func(&bright, FuncSchedule::by_row());
-    let graph = Graph::new("brighten", vec![bright], sched);
+    let graph = Graph::new("brighten", vec![bright], sched).unwrap();
 
     let mut params = HashMap::new();
-    params.insert(p, 50);
+    params.insert(p, 100);
 
     compile_and_run(
         base_dir,
@@ -85,22 +88,12 @@ fn run_threshold(base_dir: &Path) -> Result<()> {
     source!(input);
     use prism::syntax::*;
 
-    // if input(x, y) > 100 { 250 } else { 0 }
-    // no syntactic sugar exists for this yet
-    let cond = Definition::Cond(
-        Condition::new(
-            Comparison::GT,
-            input.at(x, y),
-            Definition::Const(100),
-            Definition::Const(250),
-            Definition::Const(0)
-        )
-    );
-    func!(thresh = cond);
+    // Lowers to a select rather than a branch
+    func!(thresh = select(input.at(x, y).gt(100), 250, 0));
     let mut sched = Schedule::new();
     sched.add_source(&input, FuncSchedule::by_row());
     sched.add_func(&thresh, FuncSchedule::by_row());
-    let graph = Graph::new("threshold", vec![thresh], sched);
+    let graph = Graph::new("threshold", vec![thresh], sched).unwrap();
 
     compile_and_run(
         base_dir,
@@ -129,7 +122,7 @@ fn compile_and_run(
     // Run the generated code
     let (results, trace) = processor.process_with_tracing(inputs, params);
 
-    // Dump the inputs, outputs and intermediates
+    // Dump the inputs and outputs
     for func in graph.funcs() {
         println!("{}", func.pretty_print());
     }
@@ -158,7 +151,7 @@ fn create_optimised_module<'c, 'g, 'p>(
     graph: &'g Graph,
     dir: &'p Path
 ) -> Module<'c> {
-    let mut module = create_ir_module(context, &graph);
+    let mut module = create_ir_module_with_options(context, &graph, CodegenOptions::with_tracing());
 
     module.dump_to_file(dir.join(graph.name.clone() + ".original.txt")).unwrap();
     // Without this optimisation step the IR looks sensible, but compilation fails
@@ -0,0 +1,171 @@
+//!
+//! Compiles a pipeline written in the textual syntax described in `prism::syntax::parse`,
+//! runs the generated code on the provided input images, and writes its outputs to a
+//! user-provided directory. Optionally also writes the generated IR, a text trace of
+//! all reads and writes, and an animated replay of the trace.
+//!
+//! Example command line:
+//!
+//! $ cargo run --features cli --bin prism -- examples/blur.prism -i input=image.png -o /some/directory
+//!
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{create_dir_all, read_to_string, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process
+};
+use prism::{
+    syntax::*,
+    codegen::*,
+    image::*,
+    llvm::*,
+    tracing::*
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+struct Opts {
+    /// The pipeline to compile
+    #[structopt(parse(from_os_str))]
+    pipeline: PathBuf,
+    /// Input images, as name=path. The name may be omitted if the pipeline has a single input
+    #[structopt(short = "i", long = "input")]
+    inputs: Vec<String>,
+    /// Param values, as name=value
+    #[structopt(short = "p", long = "param", parse(try_from_str = "parse_param"))]
+    params: Vec<(String, i32)>,
+    /// Outputs, and any other files requested, are written to this directory
+    #[structopt(short = "o", long = "output", parse(from_os_str))]
+    output_dir: PathBuf,
+    /// Write the original and optimised IR
+    #[structopt(long = "ir")]
+    ir: bool,
+    /// Write a text trace of all reads and writes
+    #[structopt(long = "trace")]
+    trace: bool,
+    /// Write an animated gif replaying all reads and writes
+    #[structopt(long = "replay")]
+    replay: bool
+}
+
+fn parse_param(arg: &str) -> Result<(String, i32), String> {
+    match split_assignment(arg) {
+        Some((name, value)) => match value.parse() {
+            Ok(value) => Ok((name.to_string(), value)),
+            Err(_) => Err(format!("{} is not a valid i32", value))
+        },
+        None => Err(format!("Expected name=value but found {}", arg))
+    }
+}
+
+fn split_assignment(arg: &str) -> Option<(&str, &str)> {
+    let position = arg.find('=')?;
+    Some((&arg[..position], &arg[position + 1..]))
+}
+
+fn main() {
+    initialise_llvm_jit();
+    let opts = Opts::from_args();
+    if let Err(e) = run(&opts) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(opts: &Opts) -> Result<(), Box<dyn Error>> {
+    let text = read_to_string(&opts.pipeline)?;
+    let pipeline = parse_pipeline(&text)?;
+    let name = match opts.pipeline.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => String::from("pipeline")
+    };
+    let graph = pipeline.graph(&name)?;
+
+    let inputs = load_inputs(&pipeline, &opts.inputs)?;
+    let inputs: Vec<(&Source, &DynamicImage)> = inputs.iter().map(|(s, i)| (s, i)).collect();
+    let params = load_params(&pipeline, &opts.params)?;
+
+    create_dir_all(&opts.output_dir)?;
+
+    // Generate LLVM IR
+    let tracing = opts.trace || opts.replay;
+    let context = Context::new();
+    let mut module = create_ir_module_with_options(&context, &graph, CodegenOptions { tracing });
+    if opts.ir {
+        module.dump_to_file(opts.output_dir.join(name.clone() + ".original.txt"))?;
+    }
+    optimise(&mut module);
+    if opts.ir {
+        module.dump_to_file(opts.output_dir.join(name.clone() + ".optimised.txt"))?;
+    }
+
+    // Generate native code and run it
+    let processor = create_processor(module, &graph);
+    let results = if opts.replay {
+        let mut trace = Trace::new();
+        let results = processor.try_process_dynamic_with_tracer(&inputs, &params, &mut trace)?;
+        write_trace(&opts.output_dir, &trace, opts.trace)?;
+        results
+    } else if opts.trace {
+        // A text trace can be streamed straight to disk, without storing every action
+        let mut writer = TraceWriter::create(opts.output_dir.join("replay.txt"))?;
+        let results = processor.try_process_dynamic_with_tracer(&inputs, &params, &mut writer)?;
+        writer.finish()?;
+        results
+    } else {
+        processor.try_process_dynamic(&inputs, &params)?
+    };
+
+    // Outputs with element types other than u8 are clamped to [0, 255]
+    for (output, image) in &results {
+        save_to_png(&image.to_gray(), opts.output_dir.join(output.clone() + ".png"))?;
+    }
+    Ok(())
+}
+
+/// Loads the image for each input of `pipeline`, as specified by `args`, and converts
+/// it to the input's element type.
+fn load_inputs(pipeline: &Pipeline, args: &[String]) -> Result<Vec<(Source, DynamicImage)>, Box<dyn Error>> {
+    let mut inputs = vec![];
+    for arg in args {
+        let (name, path) = match split_assignment(arg) {
+            Some((name, path)) => (name.to_string(), path),
+            None if pipeline.inputs.len() == 1 => (pipeline.inputs[0].name.clone(), arg.as_str()),
+            None => return Err(format!("Expected name=path but found {}", arg).into())
+        };
+        let source = match pipeline.inputs.iter().find(|i| i.name == name) {
+            Some(source) => source.clone(),
+            None => return Err(format!("{} is not an input of this pipeline", name).into())
+        };
+        let image = DynamicImage::from_gray(&load_from_png(path)?, source.ty);
+        inputs.push((source, image));
+    }
+    Ok(inputs)
+}
+
+/// Returns the value of each param of `pipeline` given in `args`.
+fn load_params(pipeline: &Pipeline, args: &[(String, i32)]) -> Result<HashMap<Param, i32>, Box<dyn Error>> {
+    let mut params = HashMap::new();
+    for (name, value) in args {
+        let param = match pipeline.params.iter().find(|p| &p.name == name) {
+            Some(param) => param.clone(),
+            None => return Err(format!("{} is not a param of this pipeline", name).into())
+        };
+        params.insert(param, *value);
+    }
+    Ok(params)
+}
+
+fn write_trace(dir: &Path, trace: &Trace, text: bool) -> Result<(), Box<dyn Error>> {
+    if text {
+        let mut f = File::create(dir.join("replay.txt"))?;
+        for action in trace.actions.borrow().iter() {
+            writeln!(f, "{:?}", action)?;
+        }
+    }
+    write_replay_animation(dir.join("replay.gif"), trace, 60)?;
+    Ok(())
+}
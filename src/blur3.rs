@@ -131,7 +131,7 @@ pub fn blur3_tiled<F: Factory>(
     tile_width: usize,
     tile_height: usize
 ) -> F::Image {
-    assert!(image.height() % tile_width == 0);
+    assert!(image.width() % tile_width == 0);
     assert!(image.height() % tile_height == 0);
     let mut tile = factory.create_image(tile_width, tile_height + 2);
     let mut result = factory.create_image(image.width(), image.height());
@@ -157,7 +157,7 @@ fn blur3_tiled_body<I: Image<u8>>(image: &I, tile: &mut I, result: &mut I, tile_
                 let y_image = y_buffer + y_offset - 1;
 
                 for x_buffer in 0..tile.width() {
-                    continue_if_outside_range!(x_buffer + x_offset, 1, image.width());
+                    continue_if_outside_range!(x_buffer + x_offset, 1, image.width() - 2);
                     let x_image = x_buffer + x_offset;
 
                     let p = mean(
@@ -270,4 +270,15 @@ mod tests {
     bench_and_test_blur3!(blur3_split_y_5);
     bench_and_test_blur3!(blur3_split_y_2);
     bench_and_test_blur3!(blur3_tiled_5);
+
+    #[test]
+    fn test_blur3_tiled_non_square() {
+        // Tiles are 5 pixels wide and 3 high, so neither tile dimension divides
+        // the other image dimension
+        let i = image(10, 6);
+        let mut f = BufferFactory::new();
+        let actual = blur3_tiled(&mut f, &i, 5, 3);
+        let expected = blur3_reference(&mut f, i);
+        assert_eq!(actual, expected);
+    }
 }
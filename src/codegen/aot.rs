@@ -0,0 +1,242 @@
+//! Ahead of time compilation of pipelines to object files and static libraries,
+//! which can be linked into programs that do not depend on LLVM, along with a
+//! C header declaring the generated function.
+//!
+//! The JIT provides the generated code with a function for running parallel loops.
+//! Compiled code instead runs parallel loops serially, using a weak definition of
+//! `do_par_for` which programs may replace. Tracing is not supported, so modules
+//! must be generated without tracing instrumentation.
+
+use std::{
+    fs,
+    io::{Error, Result},
+    path::Path
+};
+use crate::{codegen::*, llvm::*, syntax::*};
+
+/// Definitions of the functions that the generated code expects the JIT to provide.
+const RUNTIME_STUBS: &str = "
+define weak void @do_par_for(void (i8*, i32)* %body, i8* %closure, i32 %extent) {
+entry:
+  %empty = icmp sle i32 %extent, 0
+  br i1 %empty, label %end, label %loop
+loop:
+  %i = phi i32 [ 0, %entry ], [ %next, %loop ]
+  call void %body(i8* %closure, i32 %i)
+  %next = add i32 %i, 1
+  %done = icmp eq i32 %next, %extent
+  br i1 %done, label %end, label %loop
+end:
+  ret void
+}
+";
+
+/// Links the runtime stubs into `module`, a module created by `create_ir_module`,
+/// optimises it and compiles it for the host. `initialise_llvm_native_target`
+/// must have been called first.
+fn compile(mut module: Module<'_>) -> Result<(Vec<u8>, Vec<String>)> {
+    if is_instrumented(&module) {
+        return Err(Error::other("Modules with tracing instrumentation cannot be compiled ahead of time"));
+    }
+    let machine = TargetMachine::native().map_err(Error::other)?;
+    module.link_ir(RUNTIME_STUBS);
+    machine.configure(&mut module);
+    optimise(&mut module);
+    let object = machine.emit_object(&module).map_err(Error::other)?;
+    Ok((object, module.exported_functions()))
+}
+
+/// Compiles `module`, which must have been created by `create_ir_module`, to an object file.
+pub fn write_object_file<P: AsRef<Path>>(module: Module<'_>, path: P) -> Result<()> {
+    let (object, _) = compile(module)?;
+    fs::write(path, object)
+}
+
+/// Compiles `module`, which must have been created by `create_ir_module`, to a
+/// static library in the GNU ar format.
+pub fn write_static_library<P: AsRef<Path>>(module: Module<'_>, path: P) -> Result<()> {
+    let (object, symbols) = compile(module)?;
+    fs::write(path, static_library(&object, &symbols))
+}
+
+/// Returns an archive containing `object`, with a symbol table recording
+/// that `symbols` are defined by it.
+fn static_library(object: &[u8], symbols: &[String]) -> Vec<u8> {
+    const MAGIC: &[u8] = b"!<arch>\n";
+    const HEADER_LEN: usize = 60;
+
+    let names_len: usize = symbols.iter().map(|s| s.len() + 1).sum();
+    let table_len = 4 + 4 * symbols.len() + names_len;
+    let object_offset = MAGIC.len() + HEADER_LEN + table_len + table_len % 2;
+    let mut table = vec![];
+    table.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+    for _ in symbols {
+        table.extend_from_slice(&(object_offset as u32).to_be_bytes());
+    }
+    for symbol in symbols {
+        table.extend_from_slice(symbol.as_bytes());
+        table.push(0);
+    }
+
+    let mut archive = MAGIC.to_vec();
+    append_archive_member(&mut archive, "/", &table);
+    append_archive_member(&mut archive, "pipeline.o/", object);
+    archive
+}
+
+fn append_archive_member(archive: &mut Vec<u8>, name: &str, contents: &[u8]) {
+    // Name, modification time, owner id, group id, mode and size
+    let header = format!("{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n", name, 0, 0, 0, 644, contents.len());
+    archive.extend_from_slice(header.as_bytes());
+    archive.extend_from_slice(contents);
+    // Members are aligned to even offsets
+    if contents.len() % 2 == 1 {
+        archive.push(b'\n');
+    }
+}
+
+/// The C type of the elements of a buffer.
+fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::U8 => "uint8_t",
+        Type::U16 => "uint16_t",
+        Type::I16 => "int16_t",
+        Type::I32 => "int32_t",
+        Type::F32 => "float"
+    }
+}
+
+/// Returns a C header declaring the function generated for `graph`, whose name
+/// must be a valid C identifier.
+pub fn c_header(graph: &Graph) -> String {
+    let name = &graph.name;
+    let mut lines = vec![
+        format!("// Generated by prism. Declares the function computing the {} pipeline.", name),
+        format!("#ifndef PRISM_{}_H", name.to_uppercase()),
+        format!("#define PRISM_{}_H", name.to_uppercase()),
+        String::new(),
+        String::from("#include <stdint.h>"),
+        String::new(),
+        String::from("#ifdef __cplusplus"),
+        String::from("extern \"C\" {"),
+        String::from("#endif"),
+        String::new(),
+        String::from("// Computes the outputs of the pipeline."),
+        String::from("//"),
+        String::from("// buffers, widths and heights have an entry for each input and output, in the order"),
+        String::from("// below. Buffers are passed as uint8_t pointers, whatever their element type:")
+    ];
+    for (i, buffer) in graph.input_then_outputs().iter().enumerate() {
+        let kind = if graph.inputs().contains(buffer) { "input" } else { "output" };
+        let ty = c_type(graph.element_type(buffer).unwrap());
+        lines.push(format!("//   {}: {} ({}, {})", i, buffer, kind, ty));
+    }
+    if !graph.params().is_empty() {
+        lines.push(String::from("// params has an entry for each param, in the order:"));
+        for (i, param) in graph.params().iter().enumerate() {
+            lines.push(format!("//   {}: {}", i, param));
+        }
+    }
+    lines.extend(vec![
+        String::from("//"),
+        String::from("// Images are stored in row-major order with no padding between rows. All outputs"),
+        String::from("// must have the same dimensions, usually those of the first input, and each output"),
+        String::from("// is computed over the whole of its buffer."),
+        format!(
+            "void {}(uint8_t **buffers, const int64_t *widths, const int64_t *heights, const int32_t *params);",
+            name
+        ),
+        String::new(),
+        String::from("#ifdef __cplusplus"),
+        String::from("}"),
+        String::from("#endif"),
+        String::new(),
+        String::from("#endif"),
+        String::new()
+    ]);
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashMap, process::Command};
+    use crate::{func, image::*, interpreter::*, param, source};
+
+    #[test]
+    fn test_link_static_library() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        param!(p);
+        func!(f = input.at(x, y) + input.at(x + 1, y) + &p);
+        func!(g = f.at(x, y - 1) * 2 + f.at(x, y + 1));
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row().parallel(Var::Y));
+        sched.add_func(&g, FuncSchedule::by_row());
+        // f is an output that is also read by g, so is computed over a larger region than
+        // [0, 5) x [0, 4) but only this region is written to its buffer
+        let graph = Graph::with_outputs("aot_test", vec![f, g], &["f", "g"], sched).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("prism_aot_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        initialise_llvm_native_target();
+        let context = Context::new();
+        write_static_library(create_ir_module(&context, &graph), dir.join("libaot_test.a")).unwrap();
+        fs::write(dir.join("aot_test.h"), c_header(&graph)).unwrap();
+        // Runs the pipeline on a 5x4 image and prints the values of each output
+        let program = r#"
+            #include <stdio.h>
+            #include <stdlib.h>
+            #include "aot_test.h"
+
+            int main(void) {
+                uint8_t input[20];
+                for (int i = 0; i < 20; ++i) {
+                    input[i] = (uint8_t)(7 * i);
+                }
+                int64_t widths[3] = { 5, 5, 5 }, heights[3] = { 4, 4, 4 };
+                uint8_t *buffers[3] = { input };
+                for (int i = 1; i < 3; ++i) {
+                    buffers[i] = calloc(widths[i] * heights[i], 1);
+                }
+                int32_t params[1] = { 3 };
+                aot_test(buffers, widths, heights, params);
+                for (int i = 1; i < 3; ++i) {
+                    printf("%d %d", (int)widths[i], (int)heights[i]);
+                    for (int j = 0; j < widths[i] * heights[i]; ++j) {
+                        printf(" %d", buffers[i][j]);
+                    }
+                    printf("\n");
+                }
+                return 0;
+            }
+        "#;
+        fs::write(dir.join("main.c"), program).unwrap();
+        let status = Command::new("cc")
+            .current_dir(&dir)
+            .args(&["-Wall", "-Werror", "main.c", "-L.", "-laot_test", "-o", "main"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(dir.join("main")).output().unwrap();
+        assert!(output.status.success());
+
+        let mut image = GrayImage::new(5, 4);
+        for i in 0..20 {
+            image.set(i % 5, i / 5, (7 * i) as u8);
+        }
+        let mut params = HashMap::new();
+        params.insert(p, 3);
+        let expected = interpret(&graph, &[(&input, &image)], &params).unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        for (line, output) in stdout.lines().zip(&["f", "g"]) {
+            let expected = &expected[*output];
+            let mut values = vec![expected.width(), expected.height()];
+            values.extend(expected.data().iter().map(|v| *v as usize));
+            let actual: Vec<usize> = line.split(' ').map(|v| v.parse().unwrap()).collect();
+            assert_eq!(actual, values, "{}", output);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
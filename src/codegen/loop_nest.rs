@@ -0,0 +1,1035 @@
+//! Generates the loop nests that compute each func, as specified by its schedule.
+
+use std::{collections::HashMap, ops::Range};
+use llvm_sys::prelude::*;
+use crate::{syntax::*, codegen::*, llvm::*};
+
+/// Allows bounds to be computed at runtime by generated code.
+impl BoundsArithmetic for Builder {
+    type Value = LLVMValueRef;
+
+    fn constant(&self, c: i32) -> LLVMValueRef {
+        self.const_i32(c)
+    }
+
+    fn add(&self, l: LLVMValueRef, r: LLVMValueRef) -> LLVMValueRef {
+        Builder::add(self, l, r)
+    }
+
+    fn sub(&self, l: LLVMValueRef, r: LLVMValueRef) -> LLVMValueRef {
+        Builder::sub(self, l, r)
+    }
+
+    fn mul(&self, l: LLVMValueRef, r: LLVMValueRef) -> LLVMValueRef {
+        Builder::mul(self, l, r)
+    }
+
+    fn min(&self, l: LLVMValueRef, r: LLVMValueRef) -> LLVMValueRef {
+        self.select(self.icmp_slt(l, r), l, r)
+    }
+
+    fn max(&self, l: LLVMValueRef, r: LLVMValueRef) -> LLVMValueRef {
+        self.select(self.icmp_sgt(l, r), l, r)
+    }
+}
+
+/// Name of the symbol used to store the current value of a loop variable.
+fn loop_var_symbol_name(func_name: &str, var: &LoopVar) -> String {
+    format!("{}.{}", func_name, var)
+}
+
+/// Name of the symbol used to store the number of iterations of a loop
+/// over a pure variable.
+fn loop_extent_symbol_name(func_name: &str, var: &LoopVar) -> String {
+    format!("{}.{}.extent", func_name, var)
+}
+
+/// Name of the symbol used to store the minimum value of a pure variable.
+fn loop_min_symbol_name(func_name: &str, var: &LoopVar) -> String {
+    format!("{}.{}.min", func_name, var)
+}
+
+/// Generates code to compute each func that is computed at root, along with all
+/// funcs computed within their loops. Funcs that are not read by any other func are
+/// computed over [0, width) x [0, height), and the regions of all other funcs are
+/// inferred from these. The buffers for the graph's outputs must already be present
+/// in `symbols`, each with dimensions width x height. Outputs that are also read by
+/// other funcs are computed into a scratch buffer covering the region that their
+/// consumers require, and [0, width) x [0, height) is then copied to the output.
+pub(crate) fn generate_loop_nests(
+    builder: &Builder,
+    llvm_func: LLVMValueRef,
+    graph: &Graph,
+    width: LLVMValueRef,
+    height: LLVMValueRef,
+    symbols: &mut SymbolTable
+) {
+    let one = builder.const_i32(1);
+    let output_region = Region::new(
+        Interval::new(builder.const_i32(0), builder.sub(width, one)),
+        Interval::new(builder.const_i32(0), builder.sub(height, one))
+    );
+    let regions = required_regions(builder, graph, output_region);
+
+    let enlarged: Vec<&Func> = graph.funcs()
+        .iter()
+        .filter(|f| graph.outputs().contains(&f.name) && graph.funcs().iter().any(|g| g.sources().contains(&f.name)))
+        .collect();
+    let output_buffers: Vec<LLVMValueRef> = enlarged.iter().map(|f| symbols.get(&f.name)).collect();
+    let mut stored = funcs_stored_at(graph, &LoopLevel::Root);
+    stored.extend(&enlarged);
+    let allocated = allocate_buffers(builder, &stored, &regions, symbols);
+
+    let root_funcs = graph.funcs()
+        .iter()
+        .filter(|f| graph.schedule.get_func_schedule(f).compute_level == LoopLevel::Root);
+    for func in root_funcs {
+        let region = &regions[&func.name];
+        symbols.add(&min_x_symbol_name(&func.name), region.x.min);
+        symbols.add(&min_y_symbol_name(&func.name), region.y.min);
+        generate_func_loops(builder, llvm_func, graph, func, region, symbols);
+    }
+
+    for (func, output_buffer) in enlarged.iter().zip(output_buffers) {
+        copy_to_output(builder, llvm_func, func, output_buffer, width, height, symbols);
+    }
+
+    free_buffers(builder, &allocated, symbols);
+}
+
+/// Copies [0, width) x [0, height) of the buffer for `func` to `output_buffer`,
+/// which has dimensions width x height.
+fn copy_to_output(
+    builder: &Builder,
+    llvm_func: LLVMValueRef,
+    func: &Func,
+    output_buffer: LLVMValueRef,
+    width: LLVMValueRef,
+    height: LLVMValueRef,
+    symbols: &mut SymbolTable
+) {
+    let y_name = format!("{}.copy.y", func.name);
+    let x_name = format!("{}.copy.x", func.name);
+    let align = func.ty.size() as u32;
+    generate_loop(builder, &y_name, height, llvm_func, symbols, |symbols| {
+        generate_loop(builder, &x_name, width, llvm_func, symbols, |symbols| {
+            let (x, y) = (symbols.get(&x_name), symbols.get(&y_name));
+            let offset = buffer_offset(builder, &func.name, x, y, symbols);
+            let value = builder.load(builder.in_bounds_gep(symbols.get(&func.name), offset), align);
+            let offset = builder.add(builder.mul(y, width), x);
+            builder.store(value, builder.in_bounds_gep(output_buffer, offset), align);
+        });
+    });
+}
+
+/// The funcs that are not outputs and whose storage is allocated at `level`.
+fn funcs_stored_at<'g>(graph: &'g Graph, level: &LoopLevel) -> Vec<&'g Func> {
+    graph.funcs()
+        .iter()
+        .filter(|f| {
+            !graph.outputs().contains(&f.name)
+                && graph.schedule.get_func_schedule(f).store_level() == level
+        })
+        .collect()
+}
+
+/// Allocates storage for each of `funcs` that has an entry in `regions`, adds
+/// symbols for the new buffers and returns the names of the funcs allocated.
+fn allocate_buffers(
+    builder: &Builder,
+    funcs: &[&Func],
+    regions: &HashMap<String, Region<LLVMValueRef>>,
+    symbols: &mut SymbolTable
+) -> Vec<String> {
+    let mut allocated = vec![];
+    for func in funcs {
+        let region = match regions.get(&func.name) {
+            Some(region) => region,
+            None => continue
+        };
+        let one = builder.const_i32(1);
+        let zero = builder.const_i32(0);
+        let width = builder.add(builder.sub(region.x.max, region.x.min), one);
+        let width = BoundsArithmetic::max(builder, width, zero);
+        let height = builder.add(builder.sub(region.y.max, region.y.min), one);
+        let height = BoundsArithmetic::max(builder, height, zero);
+        let size = builder.mul(
+            builder.zext(width, builder.type_i64()),
+            builder.zext(height, builder.type_i64())
+        );
+        let size = builder.mul(size, builder.const_i64(func.ty.size() as i64));
+        let buffer = builder.build_function_call(symbols.get("malloc"), &mut [size]);
+        let buffer = builder.bitcast(buffer, builder.ptr_type(element_type(builder, func.ty)));
+        symbols.add(&func.name, buffer);
+        symbols.add(&width_symbol_name(&func.name), width);
+        symbols.add(&height_symbol_name(&func.name), height);
+        symbols.add(&min_x_symbol_name(&func.name), region.x.min);
+        symbols.add(&min_y_symbol_name(&func.name), region.y.min);
+        allocated.push(func.name.clone());
+    }
+    allocated
+}
+
+fn free_buffers(builder: &Builder, names: &[String], symbols: &mut SymbolTable) {
+    for name in names {
+        let buffer = builder.bitcast(symbols.get(name), builder.type_i8_ptr());
+        builder.build_function_call(symbols.get("free"), &mut [buffer]);
+        symbols.remove(name);
+        symbols.remove(&width_symbol_name(name));
+        symbols.remove(&height_symbol_name(name));
+        symbols.remove(&min_x_symbol_name(name));
+        symbols.remove(&min_y_symbol_name(name));
+    }
+}
+
+/// Generates the loop nest specified by the schedule of `func` to compute it
+/// over `region`, along with any funcs computed within these loops.
+fn generate_func_loops(
+    builder: &Builder,
+    llvm_func: LLVMValueRef,
+    graph: &Graph,
+    func: &Func,
+    region: &Region<LLVMValueRef>,
+    symbols: &mut SymbolTable
+) {
+    let one = builder.const_i32(1);
+    for (var, interval) in &[(Var::X, region.x), (Var::Y, region.y)] {
+        let var = LoopVar::from(*var);
+        let extent = builder.add(builder.sub(interval.max, interval.min), one);
+        symbols.add(&loop_min_symbol_name(&func.name, &var), interval.min);
+        symbols.add(&loop_extent_symbol_name(&func.name, &var), extent);
+    }
+    generate_loop_level(builder, llvm_func, graph, func, 0, true, symbols);
+}
+
+/// Generates the loop over the `level`th variable in the schedule for `func`,
+/// and recursively all the loops nested inside it. If `checked` is false then
+/// the innermost loop body is generated without bounds checks.
+fn generate_loop_level(
+    builder: &Builder,
+    llvm_func: LLVMValueRef,
+    graph: &Graph,
+    func: &Func,
+    level: usize,
+    checked: bool,
+    symbols: &mut SymbolTable
+) {
+    let sched = graph.schedule.get_func_schedule(func);
+    if level == sched.variables.len() {
+        for var in &[Var::X, Var::Y] {
+            let value = pure_var_value(builder, func, sched, *var, symbols);
+            symbols.add(&var.to_string(), value);
+        }
+        lower_func(builder, llvm_func, func, checked, symbols);
+        return;
+    }
+    let var = &sched.variables[level];
+    let name = loop_var_symbol_name(&func.name, var);
+    let extent = loop_var_extent(builder, func, sched, var, symbols);
+    let factor = sched.unroll_factor(var);
+    let generate_body = |llvm_func, symbols: &mut SymbolTable, checked| {
+        let allocated = generate_producers(builder, llvm_func, graph, func, level, symbols);
+        generate_loop_level(builder, llvm_func, graph, func, level + 1, checked, symbols);
+        free_buffers(builder, &allocated, symbols);
+    };
+
+    let steady = if level + 1 == sched.variables.len() && !sched.is_parallel(var) {
+        steady_range(builder, graph, func, extent, symbols)
+    } else {
+        None
+    };
+    match steady {
+        // Partition the innermost loop into a prologue and epilogue that perform
+        // bounds checks and a steady state in which all accesses are in bounds
+        Some((start, end)) => {
+            let zero = builder.const_i32(0);
+            generate_unrolled_loop_range(builder, &name, zero..start, factor, llvm_func, symbols, |symbols| {
+                generate_body(llvm_func, symbols, true)
+            });
+            let vector_end = generate_vector_loop(builder, llvm_func, graph, func, start, end, symbols);
+            generate_unrolled_loop_range(builder, &name, vector_end..end, factor, llvm_func, symbols, |symbols| {
+                generate_body(llvm_func, symbols, false)
+            });
+            generate_unrolled_loop_range(builder, &name, end..extent, factor, llvm_func, symbols, |symbols| {
+                generate_body(llvm_func, symbols, true)
+            });
+        },
+        None if sched.is_parallel(var) => {
+            let captured = symbols_used_within(graph, func, level, symbols);
+            generate_parallel_loop(builder, &name, extent, llvm_func, &captured, symbols, |llvm_func, symbols| {
+                generate_body(llvm_func, symbols, checked)
+            });
+        },
+        None => {
+            let zero = builder.const_i32(0);
+            generate_unrolled_loop_range(builder, &name, zero..extent, factor, llvm_func, symbols, |symbols| {
+                generate_body(llvm_func, symbols, checked)
+            });
+        }
+    }
+}
+
+/// If the innermost loop of `func` is vectorized, generates a loop that computes as
+/// many groups of consecutive iterations in [start, end) of this loop as possible
+/// using vector instructions, starting from `start`. Returns the first iteration
+/// that was not computed.
+fn generate_vector_loop(
+    builder: &Builder,
+    llvm_func: LLVMValueRef,
+    graph: &Graph,
+    func: &Func,
+    start: LLVMValueRef,
+    end: LLVMValueRef,
+    symbols: &mut SymbolTable
+) -> LLVMValueRef {
+    let sched = graph.schedule.get_func_schedule(func);
+    let innermost = sched.variables.last().unwrap();
+    let (pure_var, width) = match (&sched.vectorized, innermost_pure_var(sched)) {
+        (Some((var, width)), Some(pure_var)) if var == innermost => (pure_var, *width),
+        _ => return start
+    };
+    let name = loop_var_symbol_name(&func.name, innermost);
+    let group_name = name.clone() + ".vector";
+    let width_value = builder.const_i32(width as i32);
+    let count = builder.sdiv(builder.sub(end, start), width_value);
+    generate_loop(builder, &group_name, count, llvm_func, symbols, |symbols| {
+        let group = symbols.get(&group_name);
+        symbols.add(&name, builder.add(start, builder.mul(group, width_value)));
+        for var in &[Var::X, Var::Y] {
+            let value = pure_var_value(builder, func, sched, *var, symbols);
+            symbols.add(&var.to_string(), value);
+        }
+        lower_func_vector(builder, func, pure_var, width, symbols);
+    });
+    builder.add(start, builder.mul(count, width_value))
+}
+
+/// Returns the value of `var` for the current values of the loop variables of `func`.
+fn pure_var_value(
+    builder: &Builder,
+    func: &Func,
+    sched: &FuncSchedule,
+    var: Var,
+    symbols: &SymbolTable
+) -> LLVMValueRef {
+    let var = LoopVar::from(var);
+    let min = symbols.get(&loop_min_symbol_name(&func.name, &var));
+    builder.add(min, loop_var_value(builder, func, sched, &var, symbols))
+}
+
+/// Returns the pure variable whose value increases by one on each iteration
+/// of the innermost loop of `sched`, if there is one.
+fn innermost_pure_var(sched: &FuncSchedule) -> Option<Var> {
+    let mut var = sched.variables.last()?;
+    loop {
+        if let Some(v) = var.as_var() {
+            return Some(v);
+        }
+        let split = sched.split_creating(var)?;
+        if split.inner != *var {
+            return None;
+        }
+        var = &split.var;
+    }
+}
+
+/// Returns `floor(a / k)`, for a positive constant `k`.
+fn floor_div(builder: &Builder, a: LLVMValueRef, k: i32) -> LLVMValueRef {
+    if k == 1 {
+        return a;
+    }
+    let rounded = builder.sub(a, builder.const_i32(k - 1));
+    let is_negative = builder.icmp_slt(a, builder.const_i32(0));
+    let k = builder.const_i32(k);
+    builder.select(is_negative, builder.sdiv(rounded, k), builder.sdiv(a, k))
+}
+
+/// Returns `ceil(a / k)`, for a positive constant `k`.
+fn ceil_div(builder: &Builder, a: LLVMValueRef, k: i32) -> LLVMValueRef {
+    floor_div(builder, builder.add(a, builder.const_i32(k - 1)), k)
+}
+
+/// Returns the range of iterations `[start, end)` of the innermost loop of `func`
+/// in which all accesses are within the bounds returned by `access_bounds`, or
+/// `None` if this can't be determined before entering the loop. `extent` is the
+/// number of iterations of the loop, which must not yet have been entered.
+fn steady_range(
+    builder: &Builder,
+    graph: &Graph,
+    func: &Func,
+    extent: LLVMValueRef,
+    symbols: &mut SymbolTable
+) -> Option<(LLVMValueRef, LLVMValueRef)> {
+    let sched = graph.schedule.get_func_schedule(func);
+    let var = innermost_pure_var(sched)?;
+    let innermost = sched.variables.last().unwrap();
+    // The bounds of buffers allocated inside the loop aren't known before entering it
+    let level = LoopLevel::at(func, innermost.clone());
+    let allocated_inside = graph.funcs().iter().any(|f| {
+        let producer_sched = graph.schedule.get_func_schedule(f);
+        producer_sched.compute_level == level || producer_sched.store_level() == &level
+    });
+    if allocated_inside {
+        return None;
+    }
+
+    // The value of var on the first iteration of the loop, and the value of the
+    // other pure variable, which is fixed for the duration of the loop
+    let zero = builder.const_i32(0);
+    symbols.add(&loop_var_symbol_name(&func.name, innermost), zero);
+    let base = pure_var_value(builder, func, sched, var, symbols);
+    let other = match var { Var::X => Var::Y, Var::Y => Var::X };
+    let other_value = pure_var_value(builder, func, sched, other, symbols);
+
+    // Find the range [lower, upper] of values of var for which all accesses
+    // are in bounds, and whether the constraints that don't depend on var hold
+    let mut lower: Option<LLVMValueRef> = None;
+    let mut upper: Option<LLVMValueRef> = None;
+    let mut valid: Option<LLVMValueRef> = None;
+    for access in func.definition.accesses() {
+        let bounds = access_bounds(builder, access, symbols);
+        for (expr, interval) in &[(&access.x, bounds.x), (&access.y, bounds.y)] {
+            let (a, b, c) = affine_coefficients(expr)?;
+            let (k, other_k) = match var { Var::X => (a, b), Var::Y => (b, a) };
+            // expr = k * var + r
+            let r = builder.add(
+                builder.mul(builder.const_i32(other_k), other_value),
+                builder.const_i32(c)
+            );
+            let (new_lower, new_upper) = if k == 0 {
+                let in_bounds = builder.and(
+                    builder.icmp_sge(r, interval.min),
+                    builder.icmp_sle(r, interval.max)
+                );
+                valid = Some(valid.map_or(in_bounds, |v| builder.and(v, in_bounds)));
+                continue;
+            } else if k > 0 {
+                (
+                    ceil_div(builder, builder.sub(interval.min, r), k),
+                    floor_div(builder, builder.sub(interval.max, r), k)
+                )
+            } else {
+                (
+                    ceil_div(builder, builder.sub(r, interval.max), -k),
+                    floor_div(builder, builder.sub(r, interval.min), -k)
+                )
+            };
+            lower = Some(lower.map_or(new_lower, |l| BoundsArithmetic::max(builder, l, new_lower)));
+            upper = Some(upper.map_or(new_upper, |u| BoundsArithmetic::min(builder, u, new_upper)));
+        }
+    }
+
+    // Convert to a range of iterations, clamped to [0, extent]
+    let clamp = |v, min| BoundsArithmetic::min(builder, BoundsArithmetic::max(builder, v, min), extent);
+    let start = match lower {
+        Some(lower) => clamp(builder.sub(lower, base), zero),
+        None => zero
+    };
+    let end = match upper {
+        Some(upper) => clamp(builder.add(builder.sub(upper, base), builder.const_i32(1)), start),
+        None => extent
+    };
+    match valid {
+        Some(valid) => Some((builder.select(valid, start, zero), builder.select(valid, end, zero))),
+        None => Some((start, end))
+    }
+}
+
+/// Allocates storage for the funcs stored inside the loop over the `level`th
+/// variable of `consumer` and generates code to compute the funcs computed there.
+/// Returns the names of the funcs whose storage was allocated.
+fn generate_producers(
+    builder: &Builder,
+    llvm_func: LLVMValueRef,
+    graph: &Graph,
+    consumer: &Func,
+    level: usize,
+    symbols: &mut SymbolTable
+) -> Vec<String> {
+    let sched = graph.schedule.get_func_schedule(consumer);
+    let at = LoopLevel::at(consumer, sched.variables[level].clone());
+    let stored = funcs_stored_at(graph, &at);
+    let computed: Vec<&Func> = graph.funcs()
+        .iter()
+        .filter(|f| graph.schedule.get_func_schedule(f).compute_level == at)
+        .collect();
+    if stored.is_empty() && computed.is_empty() {
+        return vec![];
+    }
+
+    // Work out the regions of each producer required by the remaining
+    // iterations of the consumer
+    let mut regions = HashMap::new();
+    regions.insert(consumer.name.clone(), partial_region(builder, consumer, sched, level, symbols));
+    let domain = domain_region(builder, &consumer.name, symbols);
+    infer_regions(builder, graph, &graph.funcs_computed_within(consumer, level), &domain, &mut regions);
+
+    let allocated = allocate_buffers(builder, &stored, &regions, symbols);
+    for producer in computed {
+        if let Some(region) = regions.get(&producer.name) {
+            generate_func_loops(builder, llvm_func, graph, producer, region, symbols);
+        }
+    }
+    allocated
+}
+
+/// Returns the region of `func` computed by the loop over the `level`th variable
+/// of its schedule for the current values of this and all enclosing loop variables.
+fn partial_region(
+    builder: &Builder,
+    func: &Func,
+    sched: &FuncSchedule,
+    level: usize,
+    symbols: &SymbolTable
+) -> Region<LLVMValueRef> {
+    let coordinate_interval = |var: Var| {
+        let var = LoopVar::from(var);
+        let min = symbols.get(&loop_min_symbol_name(&func.name, &var));
+        let interval = loop_var_interval(builder, func, sched, &var, level, symbols);
+        Interval::new(builder.add(min, interval.min), builder.add(min, interval.max))
+    };
+    Region::new(coordinate_interval(Var::X), coordinate_interval(Var::Y))
+}
+
+/// Whether the value of `var` is fixed inside the loop over the `level`th variable.
+fn is_bound(sched: &FuncSchedule, var: &LoopVar, level: usize) -> bool {
+    match sched.variables.iter().position(|v| v == var) {
+        Some(p) => p <= level,
+        None => {
+            let split = sched.split_of(var).unwrap();
+            is_bound(sched, &split.outer, level) && is_bound(sched, &split.inner, level)
+        }
+    }
+}
+
+/// Returns the range of values taken by `var` inside the loop over the `level`th
+/// variable, relative to the minimum value of `var`.
+fn loop_var_interval(
+    builder: &Builder,
+    func: &Func,
+    sched: &FuncSchedule,
+    var: &LoopVar,
+    level: usize,
+    symbols: &SymbolTable
+) -> Interval<LLVMValueRef> {
+    if is_bound(sched, var, level) {
+        return Interval::point(loop_var_value(builder, func, sched, var, symbols));
+    }
+    if let Some(split) = sched.split_of(var) {
+        if is_bound(sched, &split.outer, level) {
+            let outer = loop_var_value(builder, func, sched, &split.outer, symbols);
+            let base = builder.mul(outer, builder.const_i32(split.factor as i32));
+            let inner = loop_var_interval(builder, func, sched, &split.inner, level, symbols);
+            return Interval::new(builder.add(base, inner.min), builder.add(base, inner.max));
+        }
+    }
+    let extent = loop_var_extent(builder, func, sched, var, symbols);
+    Interval::new(builder.const_i32(0), builder.sub(extent, builder.const_i32(1)))
+}
+
+/// Returns the number of iterations of the loop over `var`. For the inner
+/// variable of a split this depends on the value of the outer variable, as
+/// the final iteration of the outer loop may cover fewer than `factor` values.
+fn loop_var_extent(
+    builder: &Builder,
+    func: &Func,
+    sched: &FuncSchedule,
+    var: &LoopVar,
+    symbols: &SymbolTable
+) -> LLVMValueRef {
+    let split = match sched.split_creating(var) {
+        Some(split) => split,
+        None => return symbols.get(&loop_extent_symbol_name(&func.name, var))
+    };
+    let parent = loop_var_extent(builder, func, sched, &split.var, symbols);
+    let factor = builder.const_i32(split.factor as i32);
+    if *var == split.outer {
+        // Round up, so that the final iteration covers any remainder
+        let rounded = builder.add(parent, builder.const_i32(split.factor as i32 - 1));
+        builder.sdiv(rounded, factor)
+    } else {
+        let outer = symbols.get(&loop_var_symbol_name(&func.name, &split.outer));
+        let remaining = builder.sub(parent, builder.mul(outer, factor));
+        BoundsArithmetic::min(builder, remaining, factor)
+    }
+}
+
+/// Returns the value of `var` relative to its minimum value. `var` must either
+/// be a loop variable or have been split into loop variables that are all
+/// defined at the current insertion point.
+fn loop_var_value(
+    builder: &Builder,
+    func: &Func,
+    sched: &FuncSchedule,
+    var: &LoopVar,
+    symbols: &SymbolTable
+) -> LLVMValueRef {
+    match sched.split_of(var) {
+        Some(split) => {
+            let outer = loop_var_value(builder, func, sched, &split.outer, symbols);
+            let inner = loop_var_value(builder, func, sched, &split.inner, symbols);
+            let factor = builder.const_i32(split.factor as i32);
+            builder.add(builder.mul(outer, factor), inner)
+        },
+        None => symbols.get(&loop_var_symbol_name(&func.name, var))
+    }
+}
+
+/// bound is the open upper bound on the loop variable's value
+fn generate_loop(
+    builder: &Builder,
+    name: &str,
+    bound: LLVMValueRef,
+    llvm_func: LLVMValueRef,
+    symbols: &mut SymbolTable,
+    generate_body: impl FnMut(&mut SymbolTable)
+) {
+    let zero = builder.const_i32(0);
+    generate_loop_range(builder, name, zero, bound, llvm_func, symbols, generate_body);
+}
+
+/// Returns the names of the symbols defined outside the loop over the `level`th
+/// variable of `func` whose values may be used inside it.
+fn symbols_used_within(graph: &Graph, func: &Func, level: usize, symbols: &SymbolTable) -> Vec<String> {
+    let sched = graph.schedule.get_func_schedule(func);
+    let mut names = graph.params().to_vec();
+    names.push(String::from("trace_context"));
+    names.extend(sched.variables[..level].iter().map(|v| loop_var_symbol_name(&func.name, v)));
+    for var in &[Var::X, Var::Y] {
+        let var = LoopVar::from(*var);
+        names.push(loop_min_symbol_name(&func.name, &var));
+        names.push(loop_extent_symbol_name(&func.name, &var));
+    }
+    let buffers = graph.inputs().iter().chain(graph.funcs().iter().map(|f| &f.name));
+    for buffer in buffers {
+        names.extend(vec![
+            buffer.clone(),
+            width_symbol_name(buffer),
+            height_symbol_name(buffer),
+            min_x_symbol_name(buffer),
+            min_y_symbol_name(buffer),
+            domain_width_symbol_name(buffer),
+            domain_height_symbol_name(buffer)
+        ]);
+    }
+    names.retain(|n| symbols.contains(n));
+    names
+}
+
+/// Generates a loop over [0, bound) whose iterations are run concurrently by
+/// `do_par_for`. The loop body is generated in a new function, which is passed
+/// a pointer to a struct holding the values of the `captured` symbols.
+fn generate_parallel_loop(
+    builder: &Builder,
+    name: &str,
+    bound: LLVMValueRef,
+    llvm_func: LLVMValueRef,
+    captured: &[String],
+    symbols: &mut SymbolTable,
+    generate_body: impl FnOnce(LLVMValueRef, &mut SymbolTable)
+) {
+    let values: Vec<LLVMValueRef> = captured.iter().map(|c| symbols.get(c)).collect();
+    let mut types: Vec<LLVMTypeRef> = values.iter().map(|v| builder.type_of(*v)).collect();
+    let closure_type = builder.type_struct(&mut types);
+    let closure = builder.entry_alloca(llvm_func, closure_type, 8);
+    for (i, value) in values.iter().enumerate() {
+        builder.store(*value, builder.struct_gep(closure, i as u32), 4);
+    }
+    let body_func = builder.add_sibling_func(
+        llvm_func,
+        &(String::from(name) + ".par_for"),
+        parallel_body_type(builder)
+    );
+    let closure = builder.bitcast(closure, builder.type_i8_ptr());
+    builder.build_function_call(symbols.get("do_par_for"), &mut [body_func, closure, bound]);
+    let after = builder.get_insert_block();
+
+    // The outlined loop body starts by loading the captured values
+    builder.new_block(body_func, "entry");
+    let params = builder.get_params(body_func);
+    let closure = builder.bitcast(params[0], builder.ptr_type(closure_type));
+    let mut body_symbols = symbols.clone();
+    for (i, name) in captured.iter().enumerate() {
+        body_symbols.add(name, builder.load(builder.struct_gep(closure, i as u32), 4));
+    }
+    body_symbols.add(name, params[1]);
+    generate_body(body_func, &mut body_symbols);
+    builder.ret_void();
+
+    builder.position_at_end(after);
+}
+
+/// Generates a loop whose variable takes the values in `range`, emitting
+/// `factor` copies of the loop body per iteration followed by a loop over any
+/// remaining values.
+fn generate_unrolled_loop_range(
+    builder: &Builder,
+    name: &str,
+    range: Range<LLVMValueRef>,
+    factor: u32,
+    llvm_func: LLVMValueRef,
+    symbols: &mut SymbolTable,
+    mut generate_body: impl FnMut(&mut SymbolTable)
+) {
+    let Range { start, end } = range;
+    if factor == 1 {
+        generate_loop_range(builder, name, start, end, llvm_func, symbols, generate_body);
+        return;
+    }
+    let group_name = String::from(name) + ".unrolled";
+    let factor_value = builder.const_i32(factor as i32);
+    let count = builder.sdiv(builder.sub(end, start), factor_value);
+    generate_loop(builder, &group_name, count, llvm_func, symbols, |symbols| {
+        let first = builder.add(start, builder.mul(symbols.get(&group_name), factor_value));
+        for i in 0..factor {
+            symbols.add(name, builder.add(first, builder.const_i32(i as i32)));
+            generate_body(symbols);
+        }
+    });
+    let remainder = builder.add(start, builder.mul(count, factor_value));
+    generate_loop_range(builder, name, remainder, end, llvm_func, symbols, generate_body);
+}
+
+/// Generates a loop whose variable takes the values in [start, end)
+fn generate_loop_range(
+    builder: &Builder,
+    name: &str,
+    start: LLVMValueRef,
+    end: LLVMValueRef,
+    llvm_func: LLVMValueRef,
+    symbols: &mut SymbolTable,
+    mut generate_body: impl FnMut(&mut SymbolTable)
+) {
+    let pre_header = builder.get_insert_block();
+
+    let header = builder.new_block(llvm_func, &(String::from(name) + ".header"));
+    let body = builder.new_block(llvm_func, &(String::from(name) + ".loopbody"));
+    let after = builder.new_block(llvm_func, &(String::from(name) + ".after"));
+
+    // Add unconditional branch from the insertion block prior to
+    // calling this function to the loop header
+    builder.position_at_end(pre_header);
+    builder.br(header);
+
+    // header:
+    builder.position_at_end(header);
+    let is_empty = builder.icmp_sge(start, end);
+    builder.cond_br(is_empty, after, body);
+
+    // body:
+    builder.position_at_end(body);
+    let loop_variable = builder.build_phi(builder.type_i32(), name);
+    symbols.add(name, loop_variable);
+    builder.add_phi_incoming(loop_variable, start, header);
+    generate_body(symbols);
+    let next = builder.add(loop_variable, builder.const_i32(1));
+    builder.add_phi_incoming(loop_variable, next, builder.get_insert_block());
+    let cont = builder.icmp_slt(next, end);
+    builder.cond_br(cont, body, after);
+
+    // after:
+    builder.position_at_end(after);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use ::test::*;
+    use crate::{func, source, image::*};
+    use super::*;
+
+    fn image(width: usize, height: usize) -> GrayImage {
+        let mut i = GrayImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                i.set(x, y, (11 * x + 7 * y) as u8);
+            }
+        }
+        i
+    }
+
+    /// 3x3 blur, treating reads outside the image as zero.
+    fn blur3_reference(image: &GrayImage) -> GrayImage {
+        let (w, h) = (image.width() as i32, image.height() as i32);
+        let get = |i: &GrayImage, x: i32, y: i32| {
+            if x < 0 || x >= w || y < 0 || y >= h { 0 } else { i.get(x as usize, y as usize) as i32 }
+        };
+        let mut blur_h = GrayImage::new(w as usize, h as usize);
+        let mut blur_v = GrayImage::new(w as usize, h as usize);
+        for y in 0..h {
+            for x in 0..w {
+                let p = (get(image, x - 1, y) + get(image, x, y) + get(image, x + 1, y)) / 3;
+                blur_h.set(x as usize, y as usize, p as u8);
+            }
+        }
+        for y in 0..h {
+            for x in 0..w {
+                let p = (get(&blur_h, x, y - 1) + get(&blur_h, x, y) + get(&blur_h, x, y + 1)) / 3;
+                blur_v.set(x as usize, y as usize, p as u8);
+            }
+        }
+        blur_v
+    }
+
+    /// Creates a 3x3 blur pipeline, using `schedules` to create the schedules
+    /// for `blur_h` and `blur_v` from the `blur_v` func.
+    fn blur3_graph(schedules: impl Fn(&Func) -> (FuncSchedule, FuncSchedule)) -> (Source, Graph) {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(blur_h = (input.at(x - 1, y) + input.at(x, y) + input.at(x + 1, y)) / 3);
+        func!(blur_v = (blur_h.at(x, y - 1) + blur_h.at(x, y) + blur_h.at(x, y + 1)) / 3);
+        let (blur_h_sched, blur_v_sched) = schedules(&blur_v);
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&blur_h, blur_h_sched);
+        sched.add_func(&blur_v, blur_v_sched);
+        (input, Graph::new("blur3x3", vec![blur_h, blur_v], sched).unwrap())
+    }
+
+    fn run_blur3(
+        schedules: impl Fn(&Func) -> (FuncSchedule, FuncSchedule),
+        image: &GrayImage
+    ) -> GrayImage {
+        initialise_llvm_jit();
+        let (input, graph) = blur3_graph(schedules);
+        let context = Context::new();
+        let mut module = create_ir_module(&context, &graph);
+        optimise(&mut module);
+        let processor = create_processor(module, &graph);
+        let mut results = processor.process(&[(&input, image)], &HashMap::new());
+        results.remove("blur_v").unwrap()
+    }
+
+    fn check_blur3(schedules: impl Fn(&Func) -> (FuncSchedule, FuncSchedule), image: &GrayImage) {
+        assert_eq!(run_blur3(schedules, image), blur3_reference(image));
+    }
+
+    #[test]
+    fn test_only_outputs_returned() {
+        initialise_llvm_jit();
+        let (input, graph) = blur3_graph(|_| (FuncSchedule::by_row(), FuncSchedule::by_row()));
+        let context = Context::new();
+        let processor = create_processor(create_ir_module(&context, &graph), &graph);
+        let image = image(9, 10);
+        let results = processor.process(&[(&input, &image)], &HashMap::new());
+        assert_eq!(results.keys().collect::<Vec<_>>(), vec!["blur_v"]);
+        assert_eq!(results["blur_v"], blur3_reference(&image));
+    }
+
+    #[test]
+    fn test_output_read_by_other_func() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(blur_h = (input.at(x - 1, y) + input.at(x, y) + input.at(x + 1, y)) / 3);
+        func!(blur_v = (blur_h.at(x, y - 1) + blur_h.at(x, y) + blur_h.at(x, y + 1)) / 3);
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&blur_h, FuncSchedule::by_row());
+        sched.add_func(&blur_v, FuncSchedule::by_row());
+        let graph = Graph::with_outputs("blur3x3", vec![blur_h, blur_v], &["blur_h", "blur_v"], sched).unwrap();
+        let context = Context::new();
+        let processor = create_processor(create_ir_module(&context, &graph), &graph);
+        let image = image(9, 10);
+        let results = processor.process(&[(&input, &image)], &HashMap::new());
+        // blur_h is computed over rows -1 to 10 so that blur_v can read it, but
+        // only the region covered by the input is returned
+        let blur_h = &results["blur_h"];
+        assert_eq!((blur_h.width(), blur_h.height()), (9, 10));
+        assert_eq!(blur_h.get(0, 0), (0 + 11) / 3);
+        assert_eq!(blur_h.get(2, 1), (18 + 29 + 40) / 3);
+        // (input(7, 9) + input(8, 9)) / 3
+        assert_eq!(blur_h.get(8, 9), 97);
+        assert_eq!(results["blur_v"], blur3_reference(&image));
+    }
+
+    #[test]
+    fn test_split() {
+        let split = || FuncSchedule::by_row().split(Var::Y, "yo", "yi", 4);
+        check_blur3(|_| (split(), split()), &image(9, 10));
+    }
+
+    #[test]
+    fn test_nested_split() {
+        let split = || FuncSchedule::by_column()
+            .split(Var::X, "xo", "xi", 4)
+            .split("xi", "xio", "xii", 3);
+        check_blur3(|_| (split(), split()), &image(9, 10));
+    }
+
+    #[test]
+    fn test_tile() {
+        let tile = || FuncSchedule::by_row().tile(Var::X, Var::Y, "xo", "yo", "xi", "yi", 4, 3);
+        check_blur3(|_| (tile(), tile()), &image(11, 7));
+    }
+
+    #[test]
+    fn test_compute_at() {
+        for var in &[Var::X, Var::Y] {
+            check_blur3(
+                |blur_v| (FuncSchedule::by_row().compute_at(blur_v, *var), FuncSchedule::by_row()),
+                &image(9, 10)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compute_at_split() {
+        check_blur3(
+            |blur_v| (
+                FuncSchedule::by_row().compute_at(blur_v, "yo"),
+                FuncSchedule::by_row().split(Var::Y, "yo", "yi", 4)
+            ),
+            &image(9, 10)
+        );
+    }
+
+    #[test]
+    fn test_compute_at_tile() {
+        check_blur3(
+            |blur_v| (
+                FuncSchedule::by_column().compute_at(blur_v, "xo"),
+                FuncSchedule::by_row().tile(Var::X, Var::Y, "xo", "yo", "xi", "yi", 4, 3)
+            ),
+            &image(11, 7)
+        );
+    }
+
+    #[test]
+    fn test_store_root_compute_at() {
+        check_blur3(
+            |blur_v| (
+                FuncSchedule::by_row().store_root().compute_at(blur_v, Var::X),
+                FuncSchedule::by_row()
+            ),
+            &image(9, 10)
+        );
+    }
+
+    #[test]
+    fn test_store_at_compute_at() {
+        check_blur3(
+            |blur_v| (
+                FuncSchedule::by_row().store_at(blur_v, Var::Y).compute_at(blur_v, Var::X),
+                FuncSchedule::by_row()
+            ),
+            &image(9, 10)
+        );
+    }
+
+    #[test]
+    fn test_compute_inline() {
+        check_blur3(|_| (FuncSchedule::by_row().compute_inline(), FuncSchedule::by_row()), &image(9, 10));
+    }
+
+    #[test]
+    fn test_vectorize() {
+        let by_row = || FuncSchedule::by_row().vectorize(Var::X, 8);
+        check_blur3(|_| (by_row(), by_row()), &image(37, 10));
+        let by_column = || FuncSchedule::by_column().vectorize(Var::Y, 4);
+        check_blur3(|_| (by_column(), by_column()), &image(9, 23));
+    }
+
+    #[test]
+    fn test_vectorize_split() {
+        let split = || FuncSchedule::by_row().split(Var::X, "xo", "xi", 16).vectorize("xi", 4);
+        check_blur3(|_| (split(), split()), &image(37, 10));
+    }
+
+    #[test]
+    fn test_parallel() {
+        let parallel = || FuncSchedule::by_row().parallel(Var::Y);
+        check_blur3(|_| (parallel(), parallel()), &image(9, 23));
+        let innermost = || FuncSchedule::by_row().parallel(Var::X);
+        check_blur3(|_| (innermost(), innermost()), &image(9, 23));
+    }
+
+    #[test]
+    fn test_parallel_compute_at() {
+        check_blur3(|blur_v| (
+            FuncSchedule::by_row().compute_at(blur_v, "yo"),
+            FuncSchedule::by_row().split(Var::Y, "yo", "yi", 4).parallel("yo").vectorize(Var::X, 4)
+        ), &image(19, 23));
+    }
+
+    #[test]
+    fn test_nested_parallel() {
+        check_blur3(|blur_v| (
+            FuncSchedule::by_row().compute_at(blur_v, "xo").parallel(Var::Y),
+            FuncSchedule::by_row()
+                .tile(Var::X, Var::Y, "xo", "yo", "xi", "yi", 5, 4)
+                .parallel("yo")
+                .parallel("xo")
+        ), &image(19, 23));
+    }
+
+    #[test]
+    fn test_unroll() {
+        let unrolled = || FuncSchedule::by_row().unroll(Var::X, 3).unroll(Var::Y, 2);
+        check_blur3(|_| (unrolled(), unrolled()), &image(10, 7));
+        let vectorized = || FuncSchedule::by_row().vectorize(Var::X, 4).unroll(Var::X, 2);
+        check_blur3(|_| (vectorized(), vectorized()), &image(19, 7));
+    }
+
+    #[test]
+    fn test_unroll_split() {
+        check_blur3(|blur_v| (
+            FuncSchedule::by_row().compute_at(blur_v, "yo").unroll(Var::X, 4),
+            FuncSchedule::by_row().split(Var::Y, "yo", "yi", 3).unroll("yi", 3)
+        ), &image(11, 10));
+    }
+
+    // Benchmarks for comparison with the handwritten functions in blur3.rs
+
+    fn bench_blur3(b: &mut Bencher, schedules: impl Fn(&Func) -> (FuncSchedule, FuncSchedule)) {
+        initialise_llvm_jit();
+        let (input, graph) = blur3_graph(schedules);
+        let context = Context::new();
+        let mut module = create_ir_module(&context, &graph);
+        optimise(&mut module);
+        let processor = create_processor(module, &graph);
+        let image = black_box(image(180, 180));
+        let params = HashMap::new();
+        b.iter(|| black_box(processor.process(&[(&input, &image)], &params)));
+    }
+
+    #[bench]
+    fn bench_generated_blur3_inline(b: &mut Bencher) {
+        bench_blur3(b, |_| (FuncSchedule::by_row().compute_inline(), FuncSchedule::by_row()));
+    }
+
+    #[bench]
+    fn bench_generated_blur3_intermediate(b: &mut Bencher) {
+        bench_blur3(b, |_| (FuncSchedule::by_row(), FuncSchedule::by_row()));
+    }
+
+    #[bench]
+    fn bench_generated_blur3_split_y_5(b: &mut Bencher) {
+        bench_blur3(b, |blur_v| (
+            FuncSchedule::by_row().compute_at(blur_v, "yo"),
+            FuncSchedule::by_row().split(Var::Y, "yo", "yi", 5)
+        ));
+    }
+
+    #[bench]
+    fn bench_generated_blur3_tiled_5(b: &mut Bencher) {
+        bench_blur3(b, |blur_v| (
+            FuncSchedule::by_row().compute_at(blur_v, "xo"),
+            FuncSchedule::by_row().tile(Var::X, Var::Y, "xo", "yo", "xi", "yi", 5, 5)
+        ));
+    }
+
+    #[bench]
+    fn bench_generated_blur3_vectorized(b: &mut Bencher) {
+        let vectorized = || FuncSchedule::by_row().vectorize(Var::X, 16);
+        bench_blur3(b, |_| (vectorized(), vectorized()));
+    }
+
+    #[bench]
+    fn bench_generated_blur3_parallel_split_y_16(b: &mut Bencher) {
+        bench_blur3(b, |blur_v| (
+            FuncSchedule::by_row().compute_at(blur_v, "yo").vectorize(Var::X, 16),
+            FuncSchedule::by_row().split(Var::Y, "yo", "yi", 16).parallel("yo").vectorize(Var::X, 16)
+        ));
+    }
+}
@@ -21,27 +21,56 @@ pub fn lower_var_expr(
 }
 
 /// Return value is the value of the specified image at the given location,
-/// sign extended to an i32, or 0i32 if the access is out of bounds.
-/// Width and height are of type i32.
+/// converted to its value type as described by `convert_from_element`. Reads outside the bounds of the source are handled
+/// according to the access's boundary condition, or return 0i32 if it has none.
+/// If `checked` is false then the access must be within the bounds returned by
+/// `access_bounds`, and no bounds checks are generated.
+/// Width, height and minimum coordinates are of type i32.
 pub fn lower_access(
     builder: &Builder,
     llvm_func: LLVMValueRef,
     access: &Access,
+    checked: bool,
     symbols: &mut SymbolTable
 ) -> LLVMValueRef {
     let input = symbols.get(&access.source);
-    let width = symbols.get(&width_symbol_name(&access.source));
-    let height = symbols.get(&height_symbol_name(&access.source));
-
-    let x = symbols.get("x");
-    let y = symbols.get("y");
-    let log_read = symbols.get("log_read");
-    let source = symbols.get(&global_buffer_string_name(&access.source));
+    let (x, y) = (symbols.get("x"), symbols.get("y"));
     let (x, y) = (
         lower_var_expr(builder, &access.x, x, y),
         lower_var_expr(builder, &access.y, x, y)
     );
-    let result = builder.alloca(builder.type_i32(), 4);
+    let load = |symbols: &SymbolTable, x, y| {
+        let offset = buffer_offset(builder, &access.source, x, y, symbols);
+        let ptr = builder.in_bounds_gep(input, offset);
+        let val = builder.load(ptr, access.ty.size() as u32);
+        if let Ok(log_read) = symbols.try_get("log_read") {
+            let context = symbols.get("trace_context");
+            let source = symbols.get(&trace_id_symbol_name(&access.source));
+            builder.build_function_call(
+                log_read,
+                &mut[context, source, x, y]);
+        }
+        convert_from_element(builder, val, access.ty)
+    };
+    if !checked {
+        return load(symbols, x, y);
+    }
+
+    let bounds = access_bounds(builder, access, symbols);
+    let default = match access.boundary {
+        Some(Boundary::Constant(c)) => c,
+        _ => 0
+    };
+    let value_type = access.ty.value_type();
+    let default = convert_value(builder, builder.const_i32(default), Type::I32, value_type);
+    let (x, y) = match access.boundary {
+        Some(boundary) => (
+            apply_boundary(builder, boundary, x, &bounds.x),
+            apply_boundary(builder, boundary, y, &bounds.y)
+        ),
+        None => (x, y)
+    };
+    let result = builder.alloca(element_type(builder, value_type), 4);
 
     generate_if_then_else(
         builder,
@@ -49,65 +78,119 @@ pub fn lower_access(
         symbols,
         // if
         |_| {
-            let x_positive = builder.icmp_sge(x, builder.const_i32(0));
-            let x_lt_width = builder.icmp_slt(x, width);
-            let y_positive = builder.icmp_sge(y, builder.const_i32(0));
-            let y_lt_height = builder.icmp_slt(y, height);
-            let x_valid = builder.and(x_positive, x_lt_width);
-            let y_valid = builder.and(y_positive, y_lt_height);
+            let x_ge_min = builder.icmp_sge(x, bounds.x.min);
+            let x_le_max = builder.icmp_sle(x, bounds.x.max);
+            let y_ge_min = builder.icmp_sge(y, bounds.y.min);
+            let y_le_max = builder.icmp_sle(y, bounds.y.max);
+            let x_valid = builder.and(x_ge_min, x_le_max);
+            let y_valid = builder.and(y_ge_min, y_le_max);
             builder.and(x_valid, y_valid)
         },
         // then
-        |_| {
-            let offset = builder.add(builder.mul(y, width), x);
-            let ptr = builder.in_bounds_gep(input, offset);
-            let val = builder.load(ptr, 1);
-            builder.build_function_call(
-                log_read,
-                &mut[source, x, y]);
-            let ext = builder.zext(val, builder.type_i32());
-            builder.store(ext, result, 4);
+        |symbols| {
+            builder.store(load(symbols, x, y), result, 4);
         },
         // else
         |_| {
-            builder.store(builder.const_i32(0), result, 4);
+            builder.store(default, result, 4);
         });
 
     builder.load(result, 4)
 }
 
-/// Return value has type i32
+/// Returns the region within which `access` reads directly from its source.
+/// Accesses with a boundary condition are bounded by the domain of their source,
+/// and accesses without one by the region stored in the source's buffer.
+pub(crate) fn access_bounds(builder: &Builder, access: &Access, symbols: &SymbolTable) -> Region<LLVMValueRef> {
+    match access.boundary {
+        None => buffer_region(builder, &access.source, symbols),
+        Some(_) => domain_region(builder, &access.source, symbols)
+    }
+}
+
+/// Maps a coordinate outside `bounds` to one inside it, as specified by `boundary`.
+/// Coordinates inside `bounds` are unchanged, as are all coordinates for boundary
+/// conditions that don't remap coordinates.
+fn apply_boundary(
+    builder: &Builder,
+    boundary: Boundary,
+    c: LLVMValueRef,
+    bounds: &Interval<LLVMValueRef>
+) -> LLVMValueRef {
+    let one = builder.const_i32(1);
+    // Avoid dividing by zero if the bounds are empty. Any value
+    // we return will then fail the subsequent bounds check
+    let extent = builder.add(builder.sub(bounds.max, bounds.min), one);
+    let extent = BoundsArithmetic::max(builder, extent, one);
+    let offset = builder.sub(c, bounds.min);
+    let offset = match boundary {
+        Boundary::Zero | Boundary::Constant(_) => return c,
+        Boundary::Clamp => {
+            let offset = BoundsArithmetic::max(builder, offset, builder.const_i32(0));
+            BoundsArithmetic::min(builder, offset, builder.sub(extent, one))
+        },
+        Boundary::Wrap => positive_remainder(builder, offset, extent),
+        Boundary::Mirror => {
+            let period = builder.mul(extent, builder.const_i32(2));
+            let r = positive_remainder(builder, offset, period);
+            let reflected = builder.sub(builder.sub(period, one), r);
+            builder.select(builder.icmp_slt(r, extent), r, reflected)
+        }
+    };
+    builder.add(bounds.min, offset)
+}
+
+/// Returns the value of `c` modulo `n`, in the range [0, n). `n` must be positive.
+fn positive_remainder(builder: &Builder, c: LLVMValueRef, n: LLVMValueRef) -> LLVMValueRef {
+    let r = builder.srem(c, n);
+    let is_negative = builder.icmp_slt(r, builder.const_i32(0));
+    builder.select(is_negative, builder.add(r, n), r)
+}
+
+/// Return value has the type given by `definition.value_type()`. `checked` is as for `lower_access`.
 pub fn lower_definition(
     builder: &Builder,
     llvm_func: LLVMValueRef,
     definition: &Definition,
+    checked: bool,
     symbols: &mut SymbolTable
 ) -> LLVMValueRef {
-    let mut recurse = |v| lower_definition(builder, llvm_func, v, symbols);
+    // Lowers a subexpression and converts it to the given value type
+    let mut recurse = |v: &Definition, ty: Type| {
+        let value = lower_definition(builder, llvm_func, v, checked, symbols);
+        convert_value(builder, value, v.value_type(), ty)
+    };
+    let ty = definition.value_type();
     match definition {
-        Definition::Access(a) => lower_access(builder, llvm_func, a, symbols),
+        Definition::Access(a) => lower_access(builder, llvm_func, a, checked, symbols),
         Definition::Const(c) => builder.const_i32(*c),
+        Definition::FloatConst(c) => builder.const_f32(*c),
         Definition::Param(p) => symbols.get(&p),
+        Definition::Cast(cast_type, d) => {
+            let value = recurse(d, d.value_type());
+            let stored = convert_to_element(builder, value, d.value_type(), *cast_type);
+            convert_from_element(builder, stored, *cast_type)
+        },
+        Definition::SaturatingCast(cast_type, d) => {
+            saturate_value(builder, recurse(d, d.value_type()), d.value_type(), *cast_type)
+        },
+        Definition::Clamp(v, lo, hi) => {
+            let (v, lo, hi) = (recurse(v, ty), recurse(lo, ty), recurse(hi, ty));
+            clamp_value(builder, ty, v, lo, hi)
+        },
         Definition::Cond(c) => {
-            let left = recurse(&*c.lhs);
-            let right = recurse(&*c.rhs);
-            let if_true = recurse(&*c.if_true);
-            let if_false = recurse(&*c.if_false);
-            let result = builder.alloca(builder.type_i32(), 4);
+            let cmp_type = common_value_type(&c.lhs, &c.rhs);
+            let left = recurse(&c.lhs, cmp_type);
+            let right = recurse(&c.rhs, cmp_type);
+            let if_true = recurse(&c.if_true, ty);
+            let if_false = recurse(&c.if_false, ty);
+            let result = builder.alloca(element_type(builder, ty), 4);
             generate_if_then_else(
                 builder,
                 llvm_func,
                 symbols,
                 // if
-                |_| {
-                    match c.cmp {
-                        Comparison::EQ => builder.icmp_eq(left, right),
-                        Comparison::GT => builder.icmp_sgt(left, right),
-                        Comparison::GTE => builder.icmp_sge(left, right),
-                        Comparison::LT => builder.icmp_slt(left, right),
-                        Comparison::LTE => builder.icmp_sle(left, right)
-                    }
-                },
+                |_| lower_comparison(builder, c.cmp, cmp_type, left, right),
                 // then
                 |_| { builder.store(if_true, result, 4); },
                 // else
@@ -115,59 +198,403 @@ pub fn lower_definition(
 
             builder.load(result, 4)
         }
-        Definition::Add(l, r) => builder.add(recurse(l), recurse(r)),
-        Definition::Mul(l, r) => builder.mul(recurse(l), recurse(r)),
-        Definition::Sub(l, r) => builder.sub(recurse(l), recurse(r)),
-        Definition::Div(l, r) => builder.sdiv(recurse(l), recurse(r))
+        Definition::Add(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            if ty.is_float() { builder.fadd(l, r) } else { builder.add(l, r) }
+        },
+        Definition::Mul(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            if ty.is_float() { builder.fmul(l, r) } else { builder.mul(l, r) }
+        },
+        Definition::Sub(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            if ty.is_float() { builder.fsub(l, r) } else { builder.sub(l, r) }
+        },
+        Definition::Div(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            lower_div(builder, ty, l, r)
+        },
+        Definition::Abs(d) => lower_abs(builder, ty, recurse(d, ty)),
+        Definition::Binary(op, l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            lower_binary_op(builder, *op, ty, l, r)
+        },
+        Definition::Select(c, t, f) => {
+            let cond = lower_bool_expr(builder, c, &mut recurse);
+            let (t, f) = (recurse(t, ty), recurse(f, ty));
+            builder.select(cond, t, f)
+        }
+    }
+}
+
+/// Returns an i1, or vector of i1s, holding the value of `expr`. `lower` lowers a
+/// definition and converts it to the given value type. No branches are generated.
+pub(crate) fn lower_bool_expr(
+    builder: &Builder,
+    expr: &BoolExpr,
+    lower: &mut dyn FnMut(&Definition, Type) -> LLVMValueRef
+) -> LLVMValueRef {
+    match expr {
+        BoolExpr::Compare(cmp, l, r) => {
+            let ty = common_value_type(l, r);
+            let (l, r) = (lower(l, ty), lower(r, ty));
+            lower_comparison(builder, *cmp, ty, l, r)
+        },
+        BoolExpr::And(l, r) => {
+            let l = lower_bool_expr(builder, l, lower);
+            builder.and(l, lower_bool_expr(builder, r, lower))
+        },
+        BoolExpr::Or(l, r) => {
+            let l = lower_bool_expr(builder, l, lower);
+            builder.or(l, lower_bool_expr(builder, r, lower))
+        },
+        BoolExpr::Not(e) => builder.not(lower_bool_expr(builder, e, lower))
+    }
+}
+
+/// Calls the overloaded LLVM intrinsic `name` whose arguments and return value
+/// all have the type of `args[0]`, other than any trailing flags.
+fn call_overloaded_intrinsic(builder: &Builder, name: &str, args: &mut [LLVMValueRef]) -> LLVMValueRef {
+    let ty = builder.type_of(args[0]);
+    let name = format!("{}.{}", name, builder.intrinsic_type_suffix(ty));
+    builder.call_intrinsic(&name, ty, args)
+}
+
+/// Returns the absolute value of a value, or vector of values, of value type `ty`.
+pub(crate) fn lower_abs(builder: &Builder, ty: Type, value: LLVMValueRef) -> LLVMValueRef {
+    if ty.is_float() {
+        call_overloaded_intrinsic(builder, "llvm.fabs", &mut [value])
+    } else {
+        // The flag states whether the result is poison for i32::MIN
+        let min_is_poison = builder.const_null(builder.type_i1());
+        call_overloaded_intrinsic(builder, "llvm.abs", &mut [value, min_is_poison])
+    }
+}
+
+/// Divides two values, or vectors of values, of value type `ty`. sdiv is undefined
+/// for a divisor of zero and for i32::MIN / -1, so integer division by zero gives
+/// zero and division by -1 is lowered as a wrapping negation.
+pub(crate) fn lower_div(builder: &Builder, ty: Type, left: LLVMValueRef, right: LLVMValueRef) -> LLVMValueRef {
+    if ty.is_float() {
+        return builder.fdiv(left, right);
+    }
+    let constant = |c| builder.splat_like(right, builder.const_i32(c));
+    let (zero, one, minus_one) = (constant(0), constant(1), constant(-1));
+    let is_zero = lower_comparison(builder, Comparison::EQ, ty, right, zero);
+    let is_minus_one = lower_comparison(builder, Comparison::EQ, ty, right, minus_one);
+    let quotient = builder.sdiv(left, builder.select(builder.or(is_zero, is_minus_one), one, right));
+    let negated = builder.sub(zero, left);
+    builder.select(is_zero, zero, builder.select(is_minus_one, negated, quotient))
+}
+
+/// Applies `op` to two values, or vectors of values, of value type `ty`. No
+/// branches are generated, so that the result can be computed for each lane
+/// of a vector independently.
+pub(crate) fn lower_binary_op(
+    builder: &Builder,
+    op: BinaryOp,
+    ty: Type,
+    left: LLVMValueRef,
+    right: LLVMValueRef
+) -> LLVMValueRef {
+    let min = |l, r| {
+        let name = if ty.is_float() { "llvm.minnum" } else { "llvm.smin" };
+        call_overloaded_intrinsic(builder, name, &mut [l, r])
+    };
+    let max = |l, r| {
+        let name = if ty.is_float() { "llvm.maxnum" } else { "llvm.smax" };
+        call_overloaded_intrinsic(builder, name, &mut [l, r])
+    };
+    // LLVM shifts by 32 or more bits are poison, so the shift is taken modulo 32
+    let shift_amount = |r| builder.and(r, builder.splat_like(r, builder.const_i32(31)));
+    match op {
+        BinaryOp::Min => min(left, right),
+        BinaryOp::Max => max(left, right),
+        BinaryOp::AbsDiff if ty.is_float() => lower_abs(builder, ty, builder.fsub(left, right)),
+        BinaryOp::AbsDiff => builder.sub(max(left, right), min(left, right)),
+        BinaryOp::Rem if ty.is_float() => builder.frem(left, right),
+        BinaryOp::Rem => {
+            // srem is undefined for a divisor of zero, and for i32::MIN % -1. Both
+            // have a remainder of zero, as does division by one
+            let constant = |c| builder.splat_like(right, builder.const_i32(c));
+            let (zero, one, minus_one) = (constant(0), constant(1), constant(-1));
+            let is_zero = lower_comparison(builder, Comparison::EQ, ty, right, zero);
+            let is_minus_one = lower_comparison(builder, Comparison::EQ, ty, right, minus_one);
+            builder.srem(left, builder.select(builder.or(is_zero, is_minus_one), one, right))
+        },
+        BinaryOp::BitAnd => builder.and(left, right),
+        BinaryOp::BitOr => builder.or(left, right),
+        BinaryOp::BitXor => builder.xor(left, right),
+        BinaryOp::Shl => builder.shl(left, shift_amount(right)),
+        BinaryOp::Shr => builder.ashr(left, shift_amount(right))
+    }
+}
+
+/// Compares two values, or vectors of values, of value type `ty`.
+/// Comparisons involving a NaN are false.
+pub(crate) fn lower_comparison(
+    builder: &Builder,
+    cmp: Comparison,
+    ty: Type,
+    left: LLVMValueRef,
+    right: LLVMValueRef
+) -> LLVMValueRef {
+    match (cmp, ty.is_float()) {
+        (Comparison::EQ, false) => builder.icmp_eq(left, right),
+        (Comparison::GT, false) => builder.icmp_sgt(left, right),
+        (Comparison::GTE, false) => builder.icmp_sge(left, right),
+        (Comparison::LT, false) => builder.icmp_slt(left, right),
+        (Comparison::LTE, false) => builder.icmp_sle(left, right),
+        (Comparison::EQ, true) => builder.fcmp_oeq(left, right),
+        (Comparison::GT, true) => builder.fcmp_ogt(left, right),
+        (Comparison::GTE, true) => builder.fcmp_oge(left, right),
+        (Comparison::LT, true) => builder.fcmp_olt(left, right),
+        (Comparison::LTE, true) => builder.fcmp_ole(left, right)
     }
 }
 
 /// width and height are of type i32. symbols must contain entries for
-/// all mentioned images and variables.
+/// all mentioned images and variables. `checked` is as for `lower_access`.
 pub fn lower_func(
     builder: &Builder,
     llvm_func: LLVMValueRef,
     func: &Func,
+    checked: bool,
     symbols: &mut SymbolTable
 ) {
-    let val = lower_definition(builder, llvm_func, &func.definition, symbols);
+    let val = lower_definition(builder, llvm_func, &func.definition, checked, symbols);
+    let (val, value_type) = apply_store_policy(builder, val, func.definition.value_type(), func);
     let (x, y) = (symbols.get("x"), symbols.get("y"));
-    let width = symbols.get(&width_symbol_name(&func.name));
-    let offset = builder.add(builder.mul(y, width), x);
+    let offset = buffer_offset(builder, &func.name, x, y, symbols);
     let ptr = builder.in_bounds_gep(symbols.get(&func.name), offset);
-    let trunc = builder.trunc(val, builder.type_i8());
-    let log_write = symbols.get("log_write");
-    let name = symbols.get(&global_buffer_string_name(&func.name));
-    builder.build_function_call(
-        log_write,
-        &mut[name, x, y, trunc]);
-    builder.store(trunc, ptr, 1);
+    if let Ok(log_write) = symbols.try_get("log_write") {
+        let context = symbols.get("trace_context");
+        let id = symbols.get(&trace_id_symbol_name(&func.name));
+        builder.build_function_call(
+            log_write,
+            &mut[context, id, x, y, trace_value(builder, val, value_type, func.ty)]);
+    }
+    let val = convert_to_element(builder, val, value_type, func.ty);
+    builder.store(val, ptr, func.ty.size() as u32);
+}
+
+/// Clamps a value, or vector of values, of value type `ty` to lie between `lo` and `hi`.
+/// Uses selects rather than branches, so that it can be applied to vectors.
+pub(crate) fn clamp_value(
+    builder: &Builder,
+    ty: Type,
+    value: LLVMValueRef,
+    lo: LLVMValueRef,
+    hi: LLVMValueRef
+) -> LLVMValueRef {
+    let below = lower_comparison(builder, Comparison::LT, ty, value, lo);
+    let above = lower_comparison(builder, Comparison::GT, ty, value, hi);
+    builder.select(below, lo, builder.select(above, hi, value))
+}
+
+/// Converts a value, or vector of values, of value type `value_type` to the value type
+/// of `ty`, clamping it to the range of `ty`. Floats are rounded towards zero when
+/// converted to integers, and NaN is converted to zero.
+pub(crate) fn saturate_value(builder: &Builder, value: LLVMValueRef, value_type: Type, ty: Type) -> LLVMValueRef {
+    let (min, max) = match ty.int_range() {
+        Some(range) => range,
+        None => return convert_value(builder, value, value_type, ty)
+    };
+    let value = convert_value(builder, value, value_type, Type::I32);
+    if ty == Type::I32 {
+        return value;
+    }
+    let (min, max) = (builder.const_i32(min), builder.const_i32(max));
+    clamp_value(builder, Type::I32, value, builder.splat_like(value, min), builder.splat_like(value, max))
+}
+
+/// Applies the store policy of `func` to a value, or vector of values, of value type
+/// `value_type` computed for it. Returns the value to store and its value type.
+pub(crate) fn apply_store_policy(
+    builder: &Builder,
+    value: LLVMValueRef,
+    value_type: Type,
+    func: &Func
+) -> (LLVMValueRef, Type) {
+    match func.store_policy {
+        StorePolicy::Wrap => (value, value_type),
+        StorePolicy::Saturate => (saturate_value(builder, value, value_type, func.ty), func.ty.value_type())
+    }
+}
+
+/// The LLVM type of the elements of a buffer with element type `ty`.
+pub(crate) fn element_type(builder: &Builder, ty: Type) -> LLVMTypeRef {
+    match ty {
+        Type::U8 => builder.type_i8(),
+        Type::U16 | Type::I16 => builder.type_i16(),
+        Type::I32 => builder.type_i32(),
+        Type::F32 => builder.type_f32()
+    }
+}
+
+/// Converts a value, or vector of values, of value type `from` to value type `to`.
+/// Floats are rounded towards zero when converted to integers, values outside the
+/// range of i32 are clamped to it and NaN is converted to zero, as by `f as i32`.
+pub(crate) fn convert_value(builder: &Builder, value: LLVMValueRef, from: Type, to: Type) -> LLVMValueRef {
+    match (from.is_float(), to.is_float()) {
+        (false, true) => builder.sitofp(value, builder.scalar_or_vector_type(value, builder.type_f32())),
+        (true, false) => {
+            // A plain fptosi returns poison for NaN and out of range values
+            let i32_type = builder.scalar_or_vector_type(value, builder.type_i32());
+            let name = format!(
+                "llvm.fptosi.sat.{}.{}",
+                builder.intrinsic_type_suffix(i32_type),
+                builder.intrinsic_type_suffix(builder.type_of(value))
+            );
+            builder.call_intrinsic(&name, i32_type, &mut [value])
+        },
+        _ => value
+    }
+}
+
+/// Converts a value, or vector of values, read from a buffer with element type `ty`
+/// to the value type of `ty`. Integers are zero or sign extended as appropriate.
+pub(crate) fn convert_from_element(builder: &Builder, value: LLVMValueRef, ty: Type) -> LLVMValueRef {
+    let i32_type = builder.scalar_or_vector_type(value, builder.type_i32());
+    match ty {
+        Type::U8 | Type::U16 => builder.zext(value, i32_type),
+        Type::I16 => builder.sext(value, i32_type),
+        Type::I32 | Type::F32 => value
+    }
+}
+
+/// Converts a value, or vector of values, of value type `value_type` to the
+/// representation used to store it in a buffer with element type `ty`.
+/// Integers are truncated.
+pub(crate) fn convert_to_element(
+    builder: &Builder,
+    value: LLVMValueRef,
+    value_type: Type,
+    ty: Type
+) -> LLVMValueRef {
+    let value = convert_value(builder, value, value_type, ty.value_type());
+    match ty {
+        Type::U8 | Type::U16 | Type::I16 => {
+            builder.trunc(value, builder.scalar_or_vector_type(value, element_type(builder, ty)))
+        },
+        Type::I32 | Type::F32 => value
+    }
+}
+
+/// The i8 value passed to `log_write` when storing `value`, of value type `value_type`,
+/// to a buffer with element type `ty`. Traces only hold u8s, so values stored in buffers
+/// of any other type are clamped to [0, 255].
+pub(crate) fn trace_value(builder: &Builder, value: LLVMValueRef, value_type: Type, ty: Type) -> LLVMValueRef {
+    let value = convert_value(builder, value, value_type, Type::I32);
+    if ty == Type::U8 {
+        return builder.trunc(value, builder.type_i8());
+    }
+    let (zero, max) = (builder.const_i32(0), builder.const_i32(255));
+    let value = builder.select(builder.icmp_slt(value, zero), zero, value);
+    let value = builder.select(builder.icmp_sgt(value, max), max, value);
+    builder.trunc(value, builder.type_i8())
+}
+
+/// Returns the offset of the pixel at (x, y) from the start of the given buffer.
+pub(crate) fn buffer_offset(
+    builder: &Builder,
+    buffer_name: &str,
+    x: LLVMValueRef,
+    y: LLVMValueRef,
+    symbols: &SymbolTable
+) -> LLVMValueRef {
+    let width = symbols.get(&width_symbol_name(buffer_name));
+    let x = builder.sub(x, symbols.get(&min_x_symbol_name(buffer_name)));
+    let y = builder.sub(y, symbols.get(&min_y_symbol_name(buffer_name)));
+    builder.add(builder.mul(y, width), x)
+}
+
+/// Returns the region stored in the given buffer.
+pub(crate) fn buffer_region(builder: &Builder, buffer_name: &str, symbols: &SymbolTable) -> Region<LLVMValueRef> {
+    let one = builder.const_i32(1);
+    let (min_x, min_y) = (
+        symbols.get(&min_x_symbol_name(buffer_name)),
+        symbols.get(&min_y_symbol_name(buffer_name))
+    );
+    let (width, height) = (
+        symbols.get(&width_symbol_name(buffer_name)),
+        symbols.get(&height_symbol_name(buffer_name))
+    );
+    Region::new(
+        Interval::new(min_x, builder.sub(builder.add(min_x, width), one)),
+        Interval::new(min_y, builder.sub(builder.add(min_y, height), one))
+    )
 }
 
-/// Name of the global variable used to store the given buffer name.
-fn global_buffer_string_name(name: &str) -> String {
-    String::from(name) + "_name"
+/// Returns the region that accesses with a boundary condition treat as the bounds
+/// of the given source. For inputs this is the input image and for funcs it's the
+/// region computed for the pipeline's output.
+pub(crate) fn domain_region(builder: &Builder, source: &str, symbols: &SymbolTable) -> Region<LLVMValueRef> {
+    let (zero, one) = (builder.const_i32(0), builder.const_i32(1));
+    let width = symbols.get(&domain_width_symbol_name(source));
+    let height = symbols.get(&domain_height_symbol_name(source));
+    Region::new(
+        Interval::new(zero, builder.sub(width, one)),
+        Interval::new(zero, builder.sub(height, one))
+    )
 }
 
-/// Name of the symbol used to store the width of a given buffer.
-fn width_symbol_name(buffer_name: &str) -> String {
+/// Name of the symbol for the id used to identify the given buffer when tracing.
+pub(crate) fn trace_id_symbol_name(name: &str) -> String {
+    String::from(name) + ".trace_id"
+}
+
+pub(crate) fn width_symbol_name(buffer_name: &str) -> String {
     String::from(buffer_name) + "_width"
 }
 
 /// Name of the symbol used to store the height of a given buffer.
-fn height_symbol_name(buffer_name: &str) -> String {
+pub(crate) fn height_symbol_name(buffer_name: &str) -> String {
     String::from(buffer_name) + "_height"
 }
 
+/// Name of the symbol used to store the x-coordinate of the left column of a given buffer.
+pub(crate) fn min_x_symbol_name(buffer_name: &str) -> String {
+    String::from(buffer_name) + "_min_x"
+}
+
+/// Name of the symbol used to store the y-coordinate of the top row of a given buffer.
+pub(crate) fn min_y_symbol_name(buffer_name: &str) -> String {
+    String::from(buffer_name) + "_min_y"
+}
+
+/// Name of the symbol used to store the width of the domain of a given input or func.
+pub(crate) fn domain_width_symbol_name(source: &str) -> String {
+    String::from(source) + "_domain_width"
+}
+
+/// Name of the symbol used to store the height of the domain of a given input or func.
+pub(crate) fn domain_height_symbol_name(source: &str) -> String {
+    String::from(source) + "_domain_height"
+}
+
+/// Add symbols for malloc and free, used to allocate storage for funcs
+/// that are not outputs, and add these functions to `module`.
+fn register_memory_functions(builder: &Builder, module: &Module<'_>) -> (LLVMValueRef, LLVMValueRef) {
+    let malloc_type = builder.func_type(builder.type_i8_ptr(), &mut [builder.type_i64()]);
+    let free_type = builder.func_type(builder.type_void(), &mut [builder.type_i8_ptr()]);
+    builder.add_symbol("malloc", libc::malloc as *const());
+    builder.add_symbol("free", libc::free as *const());
+    let malloc = builder.add_func(module, "malloc", malloc_type);
+    let free = builder.add_func(module, "free", free_type);
+    (malloc, free)
+}
+
 /// Add symbols for the static log_read and log_write functions and add these functions to `module`.
+/// Both take the trace context, buffer id and coordinates of the pixel accessed.
 fn register_trace_functions(builder: &Builder, module: &Module<'_>) -> (LLVMValueRef, LLVMValueRef) {
     let log_read_type = builder.func_type(
         builder.type_void(),
-        &mut [builder.type_i8_ptr(), builder.type_i32(), builder.type_i32()]
+        &mut [builder.type_i8_ptr(), builder.type_i32(), builder.type_i32(), builder.type_i32()]
     );
     let log_write_type = builder.func_type(
         builder.type_void(),
-        &mut [builder.type_i8_ptr(), builder.type_i32(), builder.type_i32(), builder.type_i8()]
+        &mut [builder.type_i8_ptr(), builder.type_i32(), builder.type_i32(), builder.type_i32(), builder.type_i8()]
     );
     builder.add_symbol("log_read", log_read as *const());
     builder.add_symbol("log_write", log_write as *const());
@@ -176,14 +603,33 @@ fn register_trace_functions(builder: &Builder, module: &Module<'_>) -> (LLVMValu
     (log_read, log_write)
 }
 
-/// Creates the type of the generated function and adds it to `module`.
-fn construct_func(builder: &Builder, module: &Module<'_>, graph: &Graph) -> LLVMValueRef {
+/// The type of the functions generated to run a single iteration of a parallel loop.
+pub(crate) fn parallel_body_type(builder: &Builder) -> LLVMTypeRef {
+    builder.func_type(builder.type_void(), &mut [builder.type_i8_ptr(), builder.type_i32()])
+}
+
+/// Add a symbol for the do_par_for function, used to run parallel loops, and add it to `module`.
+fn register_parallel_functions(builder: &Builder, module: &Module<'_>) -> LLVMValueRef {
+    let do_par_for_type = builder.func_type(
+        builder.type_void(),
+        &mut [builder.ptr_type(parallel_body_type(builder)), builder.type_i8_ptr(), builder.type_i32()]
+    );
+    builder.add_symbol("do_par_for", do_par_for as *const());
+    builder.add_func(module, "do_par_for", do_par_for_type)
+}
+
+/// Creates the type of the generated function and adds it to `module`. Functions
+/// with tracing instrumentation take an additional trace context parameter.
+fn construct_func(builder: &Builder, module: &Module<'_>, graph: &Graph, options: CodegenOptions) -> LLVMValueRef {
     let mut llvm_func_params = vec![
         builder.ptr_type(builder.type_i8_ptr()), // buffers
         builder.ptr_type(builder.type_i64()),    // widths
         builder.ptr_type(builder.type_i64()),    // heights
         builder.ptr_type(builder.type_i32())     // params
     ];
+    if options.tracing {
+        llvm_func_params.push(builder.type_i8_ptr()); // trace context
+    }
     let llvm_func_type = builder.func_type(builder.type_void(), &mut llvm_func_params);
     builder.add_func(&module, &graph.name, llvm_func_type)
 }
@@ -197,17 +643,20 @@ struct ProcessingParams {
     // i64*
     heights: LLVMValueRef,
     // i32*
-    params: LLVMValueRef
+    params: LLVMValueRef,
+    // i8*, only present if tracing
+    trace_context: Option<LLVMValueRef>
 }
 
 impl ProcessingParams {
     fn new(params: Vec<LLVMValueRef>) -> ProcessingParams {
-        assert_eq!(params.len(), 4);
+        assert!(params.len() == 4 || params.len() == 5);
         ProcessingParams {
             buffers: params[0],
             widths: params[1],
             heights: params[2],
-            params: params[3]
+            params: params[3],
+            trace_context: params.get(4).cloned()
         }
     }
 
@@ -225,21 +674,78 @@ impl ProcessingParams {
     }
 }
 
+/// Options controlling the code generated by `create_ir_module_with_options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CodegenOptions {
+    /// Whether to call `log_read` and `log_write` on every pixel access, so that
+    /// `Processor::process_with_tracing` can record a trace. This makes the generated
+    /// code considerably slower, so it's off by default.
+    pub tracing: bool
+}
+
+impl CodegenOptions {
+    /// Options for generating code that can be traced.
+    pub fn with_tracing() -> CodegenOptions {
+        CodegenOptions { tracing: true }
+    }
+}
+
+/// Returns the names of the buffers that are traced by instrumented code, in order of
+/// their ids: the graph's inputs followed by its funcs.
+pub fn traced_buffers(graph: &Graph) -> Vec<String> {
+    graph.inputs()
+        .iter()
+        .cloned()
+        .chain(graph.funcs().iter().map(|f| f.name.clone()))
+        .collect()
+}
+
+/// Returns true if `module` was created with tracing instrumentation.
+pub fn is_instrumented(module: &Module<'_>) -> bool {
+    module.contains_function("log_write")
+}
+
+/// Creates a module containing uninstrumented code for `graph`.
 pub fn create_ir_module<'c, 'g>(context: &'c Context, graph: &'g Graph) -> Module<'c> {
+    create_ir_module_with_options(context, graph, CodegenOptions::default())
+}
+
+pub fn create_ir_module_with_options<'c>(
+    context: &'c Context,
+    graph: &Graph,
+    options: CodegenOptions
+) -> Module<'c> {
     assert!(graph.funcs().len() > 0);
 
     let module = context.new_module(&graph.name);
     let builder = Builder::new(context);
     let mut symbols = SymbolTable::new();
 
-    // Set up tracing
-    let (log_read, log_write) = register_trace_functions(&builder, &module);
-    symbols.add("log_read", log_read);
-    symbols.add("log_write", log_write);
+    // Set up tracing. Accesses are only logged if these symbols are defined
+    if options.tracing {
+        let (log_read, log_write) = register_trace_functions(&builder, &module);
+        symbols.add("log_read", log_read);
+        symbols.add("log_write", log_write);
+    }
+
+    // Set up allocation of storage for funcs that aren't outputs
+    let (malloc, free) = register_memory_functions(&builder, &module);
+    symbols.add("malloc", malloc);
+    symbols.add("free", free);
+
+    // Set up running loops in parallel
+    let do_par_for = register_parallel_functions(&builder, &module);
+    symbols.add("do_par_for", do_par_for);
 
     // Construct the LLVM object for the generated function
-    let llvm_func = construct_func(&builder, &module, &graph);
+    let llvm_func = construct_func(&builder, &module, &graph, options);
     let params = ProcessingParams::new(builder.get_params(llvm_func));
+    if let Some(context) = params.trace_context {
+        symbols.add("trace_context", context);
+        for (i, b) in traced_buffers(graph).iter().enumerate() {
+            symbols.add(&trace_id_symbol_name(b), builder.const_i32(i as i32));
+        }
+    }
 
     // Create first basic block in generated function and start writing to it
     let entry = builder.new_block(llvm_func, "entry");
@@ -247,95 +753,52 @@ pub fn create_ir_module<'c, 'g>(context: &'c Context, graph: &'g Graph) -> Modul
     
     // Add expressions for each buffer and param to the symbol table.
     for (i, b) in graph.input_then_outputs().iter().enumerate() {
-        // Global variable holding the name of this buffer, to use when tracing
-        symbols.add(&global_buffer_string_name(b), builder.global_string(b, b));
         // Construct expressions for accessing the nth buffer
         let (buffer, buffer_width, buffer_height) = params.nth_buffer(&builder, i);
-        symbols.add(b, buffer);
+        let ty = graph.element_type(b).unwrap();
+        symbols.add(b, builder.bitcast(buffer, builder.ptr_type(element_type(&builder, ty))));
         let width = builder.trunc(buffer_width, builder.type_i32());
         let height = builder.trunc(buffer_height, builder.type_i32());
         symbols.add(&width_symbol_name(b), width);
         symbols.add(&height_symbol_name(b), height);
+        // Inputs start at the origin. The minimum coordinates of the buffers for
+        // outputs are determined by bounds inference
+        if graph.inputs().contains(b) {
+            symbols.add(&min_x_symbol_name(b), builder.const_i32(0));
+            symbols.add(&min_y_symbol_name(b), builder.const_i32(0));
+            symbols.add(&domain_width_symbol_name(b), width);
+            symbols.add(&domain_height_symbol_name(b), height);
+        }
     }
     for (i, p) in graph.params().iter().enumerate() {
         let param = params.nth_param(&builder, i);
         symbols.add(p, param);
     }
 
-    // TODO: need to switch from processing a graph to computing a single
-    // TODO: designated output image, and compute loop bounds by working
-    // TODO: backwards from it
-    let final_func_name = &graph.funcs().iter().last().unwrap().name;
-    let y_max = symbols.get(&height_symbol_name(final_func_name));
-    let x_max = symbols.get(&width_symbol_name(final_func_name));
-
+    // Outputs are computed over the region [0, width) x [0, height) of the
+    // buffer of the first output that isn't read by any other func
+    let sink = graph.outputs()
+        .iter()
+        .find(|o| graph.funcs().iter().all(|f| !f.sources().contains(o)))
+        .unwrap();
+    let width = symbols.get(&width_symbol_name(sink));
+    let height = symbols.get(&height_symbol_name(sink));
     for func in graph.funcs() {
-        let sched = graph.schedule.get_func_schedule(func);
-        // Hack hack hack
-        let y_outer = sched.variables[0] == Var::Y;
-        let (outer_variable, outer_max, inner_variable, inner_max) = if y_outer {
-            ("y", y_max, "x", x_max)
-        } else {
-            ("x", x_max, "y", y_max)
-        };
-        let generate_inner_body = |symbols: &mut SymbolTable| {
-            lower_func(&builder, llvm_func, func, &mut *symbols);
-        };
-        let generate_outer_body = |symbols| {
-            generate_loop(&builder, inner_variable, inner_max, llvm_func, symbols, generate_inner_body);
-        };
-        generate_loop(&builder, outer_variable, outer_max, llvm_func, &mut symbols, generate_outer_body);
+        symbols.add(&domain_width_symbol_name(&func.name), width);
+        symbols.add(&domain_height_symbol_name(&func.name), height);
     }
 
+    generate_loop_nests(&builder, llvm_func, graph, width, height, &mut symbols);
+
     builder.ret_void();
     module
 }
 
-/// bound is the open upper bound on the loop variable's value
-fn generate_loop<'s>(
-    builder: &Builder,
-    name: &str,
-    bound: LLVMValueRef,
-    llvm_func: LLVMValueRef,
-    symbols: &'s mut SymbolTable,
-    mut generate_body: impl FnMut(&'s mut SymbolTable)
-) {
-    let pre_header = builder.get_insert_block();
-
-    let header = builder.new_block(llvm_func, &(String::from(name) + ".header"));
-    let body = builder.new_block(llvm_func, &(String::from(name) + ".loopbody"));
-    let after = builder.new_block(llvm_func, &(String::from(name) + ".after"));
-
-    // Add unconditional branch from the insertion block prior to
-    // calling this function to the loop header
-    builder.position_at_end(pre_header);
-    builder.br(header);
-
-    // header:
-    builder.position_at_end(header);
-    let is_empty = builder.icmp_eq(bound, builder.const_i32(0));
-    builder.cond_br(is_empty, after, body);
-
-    // body:
-    builder.position_at_end(body);
-    let loop_variable = builder.build_phi(builder.type_i32(), name);
-    symbols.add(name, loop_variable);
-    builder.add_phi_incoming(loop_variable, builder.const_i32(0), header);
-    generate_body(symbols);
-    let next = builder.add(loop_variable, builder.const_i32(1));
-    builder.add_phi_incoming(loop_variable, next, builder.get_insert_block());
-    let cont = builder.icmp_slt(next, bound);
-    builder.cond_br(cont, body, after);
-
-    // after:
-    builder.position_at_end(after);
-}
-
 // The only way to call this function is to inline the closures
 // directly into the call site - if the closures are first assigned
 // to variables then the type system can't invent suitable types/borrow
 // checker can't choose correct lifetimes. That's a bit sad...
-fn generate_if_then_else(
+pub(crate) fn generate_if_then_else(
     builder: &Builder,
     llvm_func: LLVMValueRef,
     symbols: & mut SymbolTable,
@@ -369,3 +832,478 @@ fn generate_if_then_else(
     builder.position_at_end(after_block);
 }
 
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::{func, source, param, error::PrismError, image::*, interpreter::interpret_dynamic};
+    use super::*;
+
+    fn apply_boundary_reference(image: &GrayImage, boundary: Boundary, x: i32, y: i32) -> u8 {
+        let remap = |c: i32, n: i32| match boundary {
+            Boundary::Zero | Boundary::Constant(_) => c,
+            Boundary::Clamp => std::cmp::min(std::cmp::max(c, 0), n - 1),
+            Boundary::Wrap => c.rem_euclid(n),
+            Boundary::Mirror => {
+                let m = c.rem_euclid(2 * n);
+                if m < n { m } else { 2 * n - 1 - m }
+            }
+        };
+        let (w, h) = (image.width() as i32, image.height() as i32);
+        let (x, y) = (remap(x, w), remap(y, h));
+        if x >= 0 && x < w && y >= 0 && y < h {
+            image.get(x as usize, y as usize)
+        } else if let Boundary::Constant(c) = boundary {
+            c as u8
+        } else {
+            0
+        }
+    }
+
+    #[test]
+    fn test_boundary_conditions() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let mut image = GrayImage::new(5, 4);
+        for y in 0..4 {
+            for x in 0..5 {
+                image.set(x, y, (10 * y + x + 1) as u8);
+            }
+        }
+        let boundaries = [
+            Boundary::Zero,
+            Boundary::Constant(7),
+            Boundary::Clamp,
+            Boundary::Mirror,
+            Boundary::Wrap
+        ];
+        for boundary in &boundaries {
+            source!(input);
+            func!(f = input.at_with_boundary(3 * x - 7, y + 3, *boundary));
+            let mut sched = Schedule::new();
+            sched.add_source(&input, FuncSchedule::by_row());
+            sched.add_func(&f, FuncSchedule::by_row());
+            let graph = Graph::new("boundary", vec![f], sched).unwrap();
+
+            let context = Context::new();
+            let module = create_ir_module(&context, &graph);
+            let processor = create_processor(module, &graph);
+            let actual = processor.process(&[(&input, &image)], &HashMap::new()).remove("f").unwrap();
+
+            for y in 0..4 {
+                for x in 0..5 {
+                    let expected = apply_boundary_reference(&image, *boundary, 3 * x - 7, y + 3);
+                    assert_eq!(actual.get(x as usize, y as usize), expected, "{:?} at ({}, {})", boundary, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_func_boundary_condition() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let mut image = GrayImage::new(3, 4);
+        for y in 0..4 {
+            for x in 0..3 {
+                image.set(x, y, (10 * y + x + 1) as u8);
+            }
+        }
+        source!(input);
+        func!(f = input.at(x, y) + 1);
+        func!(g = f.at_with_boundary(x, y + 1, Boundary::Clamp));
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        sched.add_func(&g, FuncSchedule::by_row());
+        let graph = Graph::new("func_boundary", vec![f, g], sched).unwrap();
+
+        let context = Context::new();
+        let module = create_ir_module(&context, &graph);
+        let processor = create_processor(module, &graph);
+        let actual = processor.process(&[(&input, &image)], &HashMap::new()).remove("g").unwrap();
+
+        for y in 0..4 {
+            for x in 0..3 {
+                let expected = image.get(x, std::cmp::min(y + 1, 3)) + 1;
+                assert_eq!(actual.get(x, y), expected);
+            }
+        }
+    }
+
+    /// Creates a gray image whose value at (x, y) is `value(x, y)` modulo 256.
+    fn gray_image(width: usize, height: usize, value: impl Fn(usize, usize) -> usize) -> DynamicImage {
+        let mut image = GrayImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set(x, y, value(x, y) as u8);
+            }
+        }
+        DynamicImage::from(image)
+    }
+
+    /// A graph which computes each of `sources` by row and each of `funcs` using `sched`.
+    fn graph_with_schedule(name: &str, sources: &[&Source], funcs: &[Func], sched: &FuncSchedule) -> Graph {
+        let mut schedule = Schedule::new();
+        for source in sources {
+            schedule.add_source(source, FuncSchedule::by_row());
+        }
+        for func in funcs {
+            schedule.add_func(func, sched.clone());
+        }
+        Graph::new(name, funcs.to_vec(), schedule).unwrap()
+    }
+
+    /// Checks that the code generated for `graph_fn(sched)`, with and without tracing, produces
+    /// the same outputs as the interpreter when `sched` computes funcs by row, and when it also
+    /// vectorizes them. Returns the outputs.
+    fn assert_matches_interpreter(
+        graph_fn: impl Fn(&FuncSchedule) -> Graph,
+        inputs: &[(&Source, &DynamicImage)],
+        params: &[(&Param, i32)]
+    ) -> HashMap<String, DynamicImage> {
+        let params: HashMap<Param, i32> = params.iter().map(|(p, v)| ((*p).clone(), *v)).collect();
+        let mut outputs = vec![];
+        for sched in &[FuncSchedule::by_row(), FuncSchedule::by_row().vectorize(Var::X, 4)] {
+            let graph = graph_fn(sched);
+            let context = Context::new();
+            let processor = create_traceable_processor(&context, &graph);
+            let actual = processor.process_dynamic(inputs, &params);
+            let expected = interpret_dynamic(&graph, inputs, &params).unwrap();
+            assert_eq!(actual, expected, "{:?}", sched);
+            let mut tracer = CountingTracer::new();
+            let traced = processor.try_process_dynamic_with_tracer(inputs, &params, &mut tracer).unwrap();
+            assert_eq!(traced, expected, "{:?}", sched);
+            outputs.push(actual);
+        }
+        outputs.remove(0)
+    }
+
+    #[test]
+    fn test_element_types() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let mut image = ImageBuffer::<i16>::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                image.set(x, y, -50 * (10 * y + x) as i16);
+            }
+        }
+        let image = DynamicImage::from(image);
+        let input = Source::new("input").with_type(Type::I16);
+        // Intermediate values exceed the range of the input type
+        func!(f = input.at(x, y) * 300);
+        let f = f.with_type(Type::I32);
+        func!(g = f.at(x, y) / 100 + 40000);
+        let g = g.with_type(Type::U16);
+        func!(h = g.at(x, y) - g.at(x + 1, y));
+        let h = h.with_type(Type::F32);
+        let funcs = vec![f, g, h];
+        let graph = |sched: &FuncSchedule| graph_with_schedule("element_types", &[&input], &funcs, sched);
+
+        let actual = assert_matches_interpreter(graph, &[(&input, &image)], &[]);
+        // input(1, 0) * 300 / 100 + 40000 - (input(2, 0) * 300 / 100 + 40000)
+        assert_eq!(actual["h"].as_buffer::<f32>().unwrap().get(1, 0), 150.0);
+
+        let graph = graph(&FuncSchedule::by_row());
+        let context = Context::new();
+        let processor = create_traceable_processor(&context, &graph);
+        let gray = GrayImage::new(8, 4);
+        match processor.try_process(&[(&input, &gray)], &HashMap::new()) {
+            Err(PrismError::TypeMismatch { name, .. }) => assert_eq!(name, "h"),
+            _ => panic!("Expected a type mismatch")
+        }
+    }
+
+    #[test]
+    fn test_float_arithmetic() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let image = gray_image(8, 4, |x, y| 37 * y + 13 * x);
+        source!(input);
+        let right = input.at_with_boundary(x + 2, y, Boundary::Clamp);
+        func!(f = input.at(x, y) * 0.25 + input.at(x + 1, y) * 0.5 + right * 0.25);
+        let f = f.with_type(Type::F32);
+        let cond = Condition::new(
+            Comparison::GTE,
+            f.at(x, y),
+            Definition::FloatConst(60.5),
+            f.at(x, y) * f.at(x, y) / 255.0,
+            (f.at(x, y).to_int() * 2).to_float() + 0.5
+        );
+        func!(g = Definition::Cond(cond));
+        let funcs = vec![f, g];
+        let graph = |sched: &FuncSchedule| graph_with_schedule("float_arithmetic", &[&input], &funcs, sched);
+
+        let actual = assert_matches_interpreter(graph, &[(&input, &image)], &[]);
+        // f(0, 0) = 13 * 0.5 + 26 * 0.25 = 13, so g(0, 0) = 2 * 13 + 0.5 rounded towards zero
+        assert_eq!(actual["g"].as_buffer::<u8>().unwrap().get(0, 0), 26);
+    }
+
+    #[test]
+    fn test_float_to_int_conversion() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let image = gray_image(8, 4, |x, y| if (x, y) == (1, 0) { 200 } else { 0 });
+        source!(input);
+        // Out of range values are clamped to the range of i32, and NaN is converted to zero
+        func!(f = (input.at(x, y).to_float() * 1e10).to_int());
+        let f = f.with_type(Type::I32);
+        func!(g = (input.at(x, y).to_float() * f32::NAN).to_int());
+        let g = g.with_type(Type::I32);
+        let funcs = vec![f, g];
+        let graph = |sched: &FuncSchedule| graph_with_schedule("float_to_int", &[&input], &funcs, sched);
+
+        let actual = assert_matches_interpreter(graph, &[(&input, &image)], &[]);
+        assert_eq!(actual["f"].as_buffer::<i32>().unwrap().get(1, 0), i32::MAX);
+        assert_eq!(actual["g"].as_buffer::<i32>().unwrap().get(1, 0), 0);
+    }
+
+    #[test]
+    fn test_saturation() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let image = gray_image(8, 4, |x, y| 60 * y + 7 * x);
+        source!(input);
+        param!(p);
+        func!(f = input.at(x, y) + &p);
+        let f = f.with_store_policy(StorePolicy::Saturate);
+        func!(g = input.at(x, y).to_float() * 300.5 - 20000.0);
+        let g = g.with_type(Type::I16).with_store_policy(StorePolicy::Saturate);
+        func!(h = (g.at(x, y) / 100).clamp(-50, &p).cast::<u8>() + (f.at(x, y) * 2).saturating_cast::<u8>());
+        let h = h.with_type(Type::I32);
+        let graph = |s: &FuncSchedule| {
+            let mut sched = Schedule::new();
+            sched.add_source(&input, FuncSchedule::by_row());
+            sched.add_func(&f, s.clone());
+            sched.add_func(&g, FuncSchedule::by_row().compute_inline());
+            sched.add_func(&h, s.clone());
+            Graph::with_outputs("saturation", vec![f.clone(), g.clone(), h.clone()], &["f", "h"], sched).unwrap()
+        };
+
+        let inputs = [(&input, &image)];
+        let actual = assert_matches_interpreter(graph, &inputs, &[(&p, 100)]);
+        // input(7, 3) + 100 = 301 is clamped to 255, and g(7, 3) = 32767
+        let f_values = actual["f"].as_buffer::<u8>().unwrap();
+        assert_eq!(f_values.get(7, 3), 255);
+        assert_eq!(actual["h"].as_buffer::<i32>().unwrap().get(7, 3), 100 + 255);
+
+        // Traces record the saturated values
+        let graph = graph(&FuncSchedule::by_row());
+        let context = Context::new();
+        let processor = create_traceable_processor(&context, &graph);
+        let params: HashMap<Param, i32> = vec![(p.clone(), 100)].into_iter().collect();
+        let mut trace = Trace::new();
+        processor.try_process_dynamic_with_tracer(&inputs, &params, &mut trace).unwrap();
+        let writes: Vec<(usize, usize, u8)> = trace.actions.borrow().iter()
+            .filter_map(|(_, action)| match action {
+                Action::Write(id, x, y, c) if id.0 == 1 => Some((*x, *y, *c)),
+                _ => None
+            })
+            .collect();
+        assert_eq!(writes.len(), 32);
+        for (x, y, c) in writes {
+            assert_eq!(c, f_values.get(x, y));
+        }
+    }
+
+    #[test]
+    fn test_binary_ops() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let image = gray_image(8, 4, |x, y| 71 * y + 29 * x);
+        source!(input);
+        param!(p);
+        let at = |dx: i32, dy: i32| input.at_with_boundary(x + dx, y + dy, Boundary::Clamp);
+        // Erode, dilate and the magnitude of a Sobel filter
+        func!(erode = at(-1, 0).min(at(0, 0)).min(at(1, 0)).min(at(0, -1)).min(at(0, 1)));
+        func!(dilate = at(-1, 0).max(at(0, 0)).max(at(1, 0)).max(at(0, -1)).max(at(0, 1)));
+        let dx = at(1, -1) + 2 * at(1, 0) + at(1, 1) - at(-1, -1) - 2 * at(-1, 0) - at(-1, 1);
+        let dy = at(-1, 1) + 2 * at(0, 1) + at(1, 1) - at(-1, -1) - 2 * at(0, -1) - at(1, -1);
+        func!(sobel = dx.abs() + dy.abs());
+        let sobel = sobel.with_type(Type::I32);
+        func!(bits = ((at(0, 0) & 0xf0) | (at(1, 0) >> 4)) ^ (at(0, 0) << &p) % 7 - (at(0, 0) - 200).absd(at(1, 0)));
+        let bits = bits.with_type(Type::I32);
+        let f = at(0, 0) * 0.5 - 60.0;
+        func!(floats = f.clone().abs().min(at(1, 0)).max(f.clone() % 7.5) - f.absd(at(0, 1) * 0.25));
+        let floats = floats.with_type(Type::F32);
+        // Integer quotients and remainders by zero are zero, i32::MIN / -1 wraps and i32::MIN % -1 is zero
+        let minus_one = || Definition::from(&p) - 36;
+        func!(rems = at(1, 0) % at(0, 0) + Definition::from(i32::MIN) % minus_one());
+        let rems = rems.with_type(Type::I32);
+        func!(quotients = at(1, 0) / at(0, 0) + Definition::from(i32::MIN) / minus_one());
+        let quotients = quotients.with_type(Type::I32);
+        let funcs = vec![erode, dilate, sobel, bits, floats, rems, quotients];
+        let graph = |sched: &FuncSchedule| graph_with_schedule("binary_ops", &[&input], &funcs, sched);
+
+        // Shifts are taken modulo 32
+        let actual = assert_matches_interpreter(graph, &[(&input, &image)], &[(&p, 35)]);
+        // input(0, 0) = 0 and input(1, 0) = 29
+        assert_eq!(actual["erode"].as_buffer::<u8>().unwrap().get(0, 0), 0);
+        assert_eq!(actual["dilate"].as_buffer::<u8>().unwrap().get(0, 0), 71);
+        assert_eq!(actual["bits"].as_buffer::<i32>().unwrap().get(0, 0), 1 ^ -229);
+        assert_eq!(actual["rems"].as_buffer::<i32>().unwrap().get(0, 0), 0);
+        assert_eq!(actual["quotients"].as_buffer::<i32>().unwrap().get(0, 0), i32::MIN);
+    }
+
+    #[test]
+    fn test_select() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        let image = gray_image(8, 4, |x, y| 53 * y + 31 * x);
+        source!(input);
+        param!(p);
+        let a = input.at(x, y);
+        func!(thresh = select(a.clone().gt(&p), 250, 0));
+        let in_band = a.clone().ge(50).and(a.clone().lt(150));
+        func!(band = select(in_band.or(!a.clone().not_equals(0)), a.clone() * 0.5, a.clone() + 1));
+        let band = band.with_type(Type::F32);
+        func!(nested = select(a.clone().le(input.at_with_boundary(x + 1, y, Boundary::Clamp)), select((a.clone() % 3).equals(0), -1, a.clone()), 2 * a));
+        let nested = nested.with_type(Type::I32);
+        let funcs = vec![thresh, band, nested];
+        let graph = |sched: &FuncSchedule| graph_with_schedule("select", &[&input], &funcs, sched);
+
+        let actual = assert_matches_interpreter(graph, &[(&input, &image)], &[(&p, 100)]);
+        // input(0, 0) = 0 and input(1, 1) = 84
+        assert_eq!(actual["thresh"].as_buffer::<u8>().unwrap().get(0, 0), 0);
+        assert_eq!(actual["band"].as_buffer::<f32>().unwrap().get(0, 0), 0.0);
+        assert_eq!(actual["band"].as_buffer::<f32>().unwrap().get(1, 1), 42.0);
+        assert_eq!(actual["nested"].as_buffer::<i32>().unwrap().get(1, 1), -1);
+    }
+
+    #[test]
+    fn test_missing_inputs() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        param!(p);
+        func!(f = input.at(x, y) + &p);
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        let graph = Graph::new("missing_inputs", vec![f], sched).unwrap();
+
+        let context = Context::new();
+        let module = create_ir_module(&context, &graph);
+        let processor = create_processor(module, &graph);
+        let image = GrayImage::new(3, 4);
+        let error = processor.try_process(&[(&input, &image)], &HashMap::new()).err();
+        assert_eq!(error, Some(PrismError::MissingParam("p".to_string())));
+        let error = processor.try_process(&[], &HashMap::new()).err();
+        assert_eq!(error, Some(PrismError::MissingSource("input".to_string())));
+    }
+
+    #[test]
+    fn test_no_inputs() {
+        initialise_llvm_jit();
+        param!(p);
+        func!(f = Definition::from(&p) * 2);
+        let mut sched = Schedule::new();
+        sched.add_func(&f, FuncSchedule::by_row());
+        let graph = Graph::new("no_inputs", vec![f], sched).unwrap();
+
+        let context = Context::new();
+        let processor = create_processor(create_ir_module(&context, &graph), &graph);
+        let params: HashMap<Param, i32> = vec![(p, 3)].into_iter().collect();
+        let error = processor.try_process(&[], &params).err();
+        assert_eq!(error, Some(PrismError::NoInputs));
+    }
+
+    #[test]
+    fn test_output_size_from_first_input() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        source!(a);
+        source!(b);
+        func!(f = a.at(x, y) + b.at(x, y));
+        let mut sched = Schedule::new();
+        sched.add_source(&a, FuncSchedule::by_row());
+        sched.add_source(&b, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        let graph = Graph::new("input_order", vec![f], sched).unwrap();
+
+        let context = Context::new();
+        let processor = create_processor(create_ir_module(&context, &graph), &graph);
+        let (small, large) = (GrayImage::new(3, 2), GrayImage::new(5, 4));
+        // Outputs are the size of a, the first input alphabetically, in either order
+        for inputs in &[[(&a, &small), (&b, &large)], [(&b, &large), (&a, &small)]] {
+            let result = processor.process(inputs, &HashMap::new());
+            assert_eq!(result["f"].dimensions(), (3, 2));
+        }
+    }
+
+    #[test]
+    fn test_tracing_instrumentation() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) + input.at(x, y + 1));
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row().vectorize(Var::X, 4));
+        let graph = Graph::new("instrumentation", vec![f], sched).unwrap();
+        let image = GrayImage::new(8, 4);
+        let inputs = [(&input, &image)];
+
+        let context = Context::new();
+        let module = create_ir_module(&context, &graph);
+        assert!(!is_instrumented(&module));
+        assert!(!module.dump_to_string().contains("log_read"));
+        let processor = create_processor(module, &graph);
+        assert!(!processor.supports_tracing());
+        let error = processor.try_process_with_tracing(&inputs, &HashMap::new()).err();
+        assert_eq!(error, Some(PrismError::NotInstrumented));
+        let expected = processor.process(&inputs, &HashMap::new());
+
+        let processor = create_traceable_processor(&context, &graph);
+        assert_eq!(processor.process(&inputs, &HashMap::new()), expected);
+        let (actual, trace) = processor.process_with_tracing(&inputs, &HashMap::new());
+        assert_eq!(actual, expected);
+        // Each pixel of f is written once and reads two pixels of input,
+        // except in the last row, where reads below the image aren't traced
+        assert_eq!(trace.actions.borrow().len(), 3 * 8 * 4 - 8);
+        let mut counter = CountingTracer::new();
+        processor.process_with_tracer(&inputs, &HashMap::new(), &mut counter);
+        assert_eq!(counter.total(), ActionCounts { reads: 2 * 8 * 4 - 8, writes: 8 * 4, clears: 0 });
+    }
+
+    #[test]
+    fn test_concurrent_tracing() {
+        initialise_llvm_jit();
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) * 2);
+        func!(g = f.at(x, y) + f.at(x + 1, y));
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        sched.add_func(&g, FuncSchedule::by_row().parallel(Var::Y));
+        let graph = Graph::new("concurrent", vec![f, g], sched).unwrap();
+
+        // Each thread traces the pipeline on a different image, and the traces must not interfere
+        let traces: Vec<Trace> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4u8)
+                .map(|i| {
+                    let (graph, input) = (&graph, &input);
+                    scope.spawn(move || {
+                        let context = Context::new();
+                        let processor = create_traceable_processor(&context, graph);
+                        let image = GrayImage::from_raw(3, 2, vec![i; 6]);
+                        processor.process_with_tracing(&[(input, &image)], &HashMap::new()).1
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for (i, trace) in traces.iter().enumerate() {
+            assert_eq!(trace.initial_images.borrow()[0].get(0, 0), i as u8);
+            let writes = trace.actions
+                .borrow()
+                .iter()
+                .filter(|(_, a)| matches!(a, Action::Write(..)))
+                .count();
+            // f and g each have 3 x 2 pixels
+            assert_eq!(writes, 12);
+        }
+    }
+}
@@ -1,10 +1,18 @@
 //! Handles compilation of pipeline definitions.
 //! Uses the LLVM wrappers provided by the llvm module.
 
+pub use self::aot::*;
 pub use self::lower::*;
+pub(crate) use self::loop_nest::*;
 pub use self::processor::*;
 pub use self::symbol_table::*;
+pub use self::thread_pool::*;
+pub(crate) use self::vector::*;
 
+mod aot;
 mod lower;
+mod loop_nest;
 mod processor;
 mod symbol_table;
+mod thread_pool;
+mod vector;
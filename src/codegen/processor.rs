@@ -2,140 +2,354 @@
 //! but for now this lets us experiment with image chains.
 
 use std::collections::HashMap;
-use std::mem;
-use crate::{image::*, syntax::*, llvm::*, tracing::*};
+use std::{mem, ptr};
+use libc::c_void;
+use crate::{codegen::*, error::PrismError, image::*, syntax::*, llvm::*, tracing::*};
 
 pub struct Processor<'c> {
+    /// Code without tracing instrumentation, used by `process`.
+    untraced: Option<Compiled<'c>>,
+    /// Code with tracing instrumentation, used by `process_with_tracing`,
+    /// and by `process` if no uninstrumented code is available.
+    traced: Option<Compiled<'c>>,
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    /// Used to infer the sizes of output buffers, and to
+    /// create trace ids for funcs whose storage is allocated by
+    /// the generated function.
+    graph: Graph
+}
+
+/// A single compiled variant of the generated function.
+struct Compiled<'c> {
     /// This fields exists solely to ensure the engine
     /// isn't dropped while we're still using it.
     /// We could have a reference instead, but then this class
     /// would have two lifetimes - one for the reference to the
     /// engine and one for the context.
     _engine: ExecutionEngine<'c>,
-    function_pointer: u64,
-    inputs: Vec<String>,
-    outputs: Vec<String>
+    function_pointer: u64
 }
 
-/// Compile IR and return an object which supports calling the generated function
+impl<'c> Compiled<'c> {
+    fn new(engine: ExecutionEngine<'c>, graph: &Graph) -> Compiled<'c> {
+        let function_pointer = unsafe { engine.get_func_addr(&graph.name) };
+        Compiled { _engine: engine, function_pointer }
+    }
+}
+
+/// Compile IR and return an object which supports calling the generated function.
+/// The processor supports tracing only if `module` was created with tracing enabled.
 pub fn create_processor<'c, 'g>(module: Module<'c>, graph: &'g Graph) -> Processor<'c> {
+    let options = CodegenOptions { tracing: is_instrumented(&module) };
     let engine = ExecutionEngine::new(module);
-    Processor::new(engine, &graph)
+    Processor::new(engine, &graph, options)
+}
+
+/// Generates, optimises and compiles both instrumented and uninstrumented code for
+/// `graph`, so that `process` runs at full speed and `process_with_tracing` is supported.
+pub fn create_traceable_processor<'c>(context: &'c Context, graph: &Graph) -> Processor<'c> {
+    let compile = |options| {
+        let mut module = create_ir_module_with_options(context, graph, options);
+        optimise(&mut module);
+        Compiled::new(ExecutionEngine::new(module), graph)
+    };
+    let untraced = compile(CodegenOptions::default());
+    let traced = compile(CodegenOptions::with_tracing());
+    Processor::from_variants(Some(untraced), Some(traced), graph)
 }
 
 impl<'c> Processor<'c> {
-    pub fn new<'d>(engine: ExecutionEngine<'d>, graph: &Graph) -> Processor<'d> {
-        let function_pointer = unsafe { engine.get_func_addr(&graph.name) };
+    /// `options` must be those used to generate the code compiled by `engine`.
+    pub fn new<'d>(engine: ExecutionEngine<'d>, graph: &Graph, options: CodegenOptions) -> Processor<'d> {
+        let compiled = Compiled::new(engine, graph);
+        if options.tracing {
+            Processor::from_variants(None, Some(compiled), graph)
+        } else {
+            Processor::from_variants(Some(compiled), None, graph)
+        }
+    }
+
+    fn from_variants<'d>(
+        untraced: Option<Compiled<'d>>,
+        traced: Option<Compiled<'d>>,
+        graph: &Graph
+    ) -> Processor<'d> {
         let inputs = graph.inputs().to_vec();
         let outputs = graph.outputs().to_vec();
-        Processor { _engine: engine, function_pointer, inputs, outputs }
+        let graph = graph.clone();
+        Processor { untraced, traced, inputs, outputs, graph }
+    }
+
+    /// Returns true if this processor supports `process_with_tracing`.
+    pub fn supports_tracing(&self) -> bool {
+        self.traced.is_some()
     }
 
+    /// Runs the generated function, returning the values of each of the graph's outputs
+    /// over the region covered by its first input, i.e. the input whose name is first
+    /// alphabetically. Panics if any required source or param is not provided, if any
+    /// input or output has an element type other than u8, or if the graph has no inputs.
     pub fn process(
         &self,
         inputs: &[(&Source, &GrayImage)],
         params: &HashMap<Param, i32>
     ) -> HashMap<String, GrayImage> {
-        self.process_impl(inputs, params, false).0
+        self.try_process(inputs, params).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As for `process`, but returns an error rather than panicking.
+    pub fn try_process(
+        &self,
+        inputs: &[(&Source, &GrayImage)],
+        params: &HashMap<Param, i32>
+    ) -> Result<HashMap<String, GrayImage>, PrismError> {
+        self.check_gray_outputs()?;
+        Ok(to_gray_outputs(self.process_impl(inputs, params, None)?))
     }
 
-    /// Uses horrible global state for tracing.
+    /// Runs the generated function on inputs of any element type, returning the values
+    /// of each of the graph's outputs. Panics if any required source or param is not
+    /// provided, or if any input does not have the element type the graph expects.
+    pub fn process_dynamic(
+        &self,
+        inputs: &[(&Source, &DynamicImage)],
+        params: &HashMap<Param, i32>
+    ) -> HashMap<String, DynamicImage> {
+        self.try_process_dynamic(inputs, params).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_process_dynamic(
+        &self,
+        inputs: &[(&Source, &DynamicImage)],
+        params: &HashMap<Param, i32>
+    ) -> Result<HashMap<String, DynamicImage>, PrismError> {
+        self.process_impl(inputs, params, None)
+    }
+
+    /// Runs the generated function, recording all reads and writes that it makes.
+    /// Panics if this processor was compiled without tracing instrumentation.
     pub fn process_with_tracing(
         &self,
         inputs: &[(&Source, &GrayImage)],
         params: &HashMap<Param, i32>
     ) -> (HashMap<String, GrayImage>, Trace) {
-        let r = self.process_impl(inputs, params, true);
-        (r.0, r.1.unwrap())
+        self.try_process_with_tracing(inputs, params).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_process_with_tracing(
+        &self,
+        inputs: &[(&Source, &GrayImage)],
+        params: &HashMap<Param, i32>
+    ) -> Result<(HashMap<String, GrayImage>, Trace), PrismError> {
+        let mut trace = Trace::new();
+        let results = self.try_process_with_tracer(inputs, params, &mut trace)?;
+        Ok((results, trace))
     }
 
-    fn process_impl(
+    /// Runs the generated function, reporting all reads and writes that it makes to `tracer`.
+    /// Panics if this processor was compiled without tracing instrumentation.
+    pub fn process_with_tracer<T: Tracer + Send>(
         &self,
         inputs: &[(&Source, &GrayImage)],
         params: &HashMap<Param, i32>,
-        trace: bool
-    ) -> (HashMap<String, GrayImage>, Option<Trace>) {
-        // Assume that all images are the same size for now. This will not be true in general
-        let (w, h) = inputs[0].1.dimensions();
-
-        // Initialise trace, set up the mapping from buffer names to trace ids
-        if trace {
-            let mut ids = HashMap::new();
-            let tr = Trace::new();
-
-            for input in inputs {
-                let name = input.0.name.clone();
-                let image = input.1;
-                ids.insert(name, tr.create_trace_id(image));
-            }
-            for output in &self.outputs {
-                ids.insert(output.to_string(), tr.create_trace_id(&GrayImage::new(w, h)));
-            }
+        tracer: &mut T
+    ) -> HashMap<String, GrayImage> {
+        self.try_process_with_tracer(inputs, params, tracer).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-            unsafe { set_global_trace(ids, tr); }
+    pub fn try_process_with_tracer<T: Tracer + Send>(
+        &self,
+        inputs: &[(&Source, &GrayImage)],
+        params: &HashMap<Param, i32>,
+        tracer: &mut T
+    ) -> Result<HashMap<String, GrayImage>, PrismError> {
+        self.check_gray_outputs()?;
+        Ok(to_gray_outputs(self.process_impl(inputs, params, Some(tracer))?))
+    }
+
+    /// As for `try_process_with_tracer`, but accepting inputs of any element type. Values
+    /// written to buffers whose element type is not u8 are clamped to [0, 255] in the trace.
+    pub fn try_process_dynamic_with_tracer<T: Tracer + Send>(
+        &self,
+        inputs: &[(&Source, &DynamicImage)],
+        params: &HashMap<Param, i32>,
+        tracer: &mut T
+    ) -> Result<HashMap<String, DynamicImage>, PrismError> {
+        self.process_impl(inputs, params, Some(tracer))
+    }
+
+    /// Returns an error if any output has an element type other than u8.
+    fn check_gray_outputs(&self) -> Result<(), PrismError> {
+        for output in &self.outputs {
+            let ty = self.graph.element_type(output).unwrap();
+            if ty != Type::U8 {
+                return Err(PrismError::TypeMismatch { name: output.clone(), expected: Type::U8, actual: ty });
+            }
         }
+        Ok(())
+    }
 
-        // Check that all required inputs have been provided
+    fn process_impl<I: InputImage>(
+        &self,
+        inputs: &[(&Source, &I)],
+        params: &HashMap<Param, i32>,
+        tracer: Option<&mut (dyn Tracer + Send)>
+    ) -> Result<HashMap<String, DynamicImage>, PrismError> {
+        let (compiled, instrumented) = match (tracer.is_some(), &self.untraced, &self.traced) {
+            (false, Some(compiled), _) => (compiled, false),
+            (_, _, Some(compiled)) => (compiled, true),
+            _ => return Err(PrismError::NotInstrumented)
+        };
+        // Find the image for each required input, in the order expected by the generated function
+        let mut input_images = vec![];
         for source in &self.inputs {
-            match inputs.iter().find(|i| &i.0.name == source) {
-                None => panic!(
-                    "Required source {} is not calculated and is not provided as an input",
-                    source
-                ),
-                _ => { }
+            let image = match inputs.iter().find(|i| &i.0.name == source) {
+                Some(input) => input.1,
+                None => return Err(PrismError::MissingSource(source.clone()))
+            };
+            let expected = self.graph.element_type(source).unwrap();
+            if image.element_type() != expected {
+                return Err(PrismError::TypeMismatch {
+                    name: source.clone(),
+                    expected,
+                    actual: image.element_type()
+                });
             }
+            input_images.push(image);
         }
+        // The generated function expects params to be sorted by name, as in the graph
+        let mut param_values = vec![];
+        for name in self.graph.params() {
+            match params.iter().find(|p| &p.0.name == name) {
+                Some(p) => param_values.push(*p.1),
+                None => return Err(PrismError::MissingParam(name.clone()))
+            }
+        }
+
+        // Outputs are computed over the region covered by the graph's first input, whatever
+        // the order in which the caller lists the inputs
+        let (w, h) = match input_images.first() {
+            Some(image) => image.dimensions(),
+            None => return Err(PrismError::NoInputs)
+        };
 
-        // Allocate intermediate and result buffers
-        let calculated_images: Vec<(String, GrayImage)> = self.outputs
+        // Register each buffer with the tracer. Buffer ids are assigned
+        // to inputs and then funcs, as by `traced_buffers`
+        let context = tracer.map(|tracer| {
+            let mut images: Vec<GrayImage> = input_images.iter().map(|i| i.to_gray()).collect();
+            images.extend(self.graph.funcs().iter().map(|_| GrayImage::new(w, h)));
+            let images: Vec<&GrayImage> = images.iter().collect();
+            TraceContext::new(tracer, &images)
+        });
+
+        // Allocate output buffers. Outputs are computed over the same region as the first input
+        let calculated_images: Vec<(String, DynamicImage)> = self.outputs
             .iter()
-            .map(|name| (name.clone(), GrayImage::new(w, h)))
+            .map(|name| (name.clone(), DynamicImage::new(self.graph.element_type(name).unwrap(), w, h)))
             .collect();
 
         let mut buffers = vec![];
         let mut widths = vec![];
         let mut heights = vec![];
 
-        for input in inputs {
-            let image = input.1;
-            buffers.push(image.buffer.as_ptr());
-            widths.push(image.width());
-            heights.push(image.height());
+        for image in input_images {
+            buffers.push(image.as_ptr());
+            widths.push(image.dimensions().0);
+            heights.push(image.dimensions().1);
         }
         for calculated in &calculated_images {
             let image = &calculated.1;
-            buffers.push(image.buffer.as_ptr());
-            widths.push(image.width());
-            heights.push(image.height());
+            buffers.push(image.as_ptr());
+            widths.push(image.dimensions().0);
+            heights.push(image.dimensions().1);
         }
-
-        // Sort params by name
-        let mut params: Vec<(Param, i32)> = params.iter().map(|e| (e.0.clone(), *e.1)).collect();
-        params.sort_by_key(|e| e.0.name.clone());
-        let params: Vec<i32> = params.iter().map(|e| e.1).collect();
-
         // The generated function takes a single array containing all buffers,
         // both inputs and outputs. We claim all the pointers are const here, but
         // the output buffers are actually mutable.
-        let f: extern "C" fn(
-            *const *const u8, // buffers
-            *const usize,     // widths
-            *const usize,     // heights
-            *const i32        // params
-        ) = unsafe { mem::transmute(self.function_pointer) };
-
-        f(
-            buffers.as_ptr(),
-            widths.as_ptr(),
-            heights.as_ptr(),
-            params.as_ptr()
-        );
-
-        let tr = unsafe { get_global_trace() };
-        if trace {
-            unsafe { clear_global_trace(); }
+        if instrumented {
+            let f: extern "C" fn(
+                *const *const u8, // buffers
+                *const usize,     // widths
+                *const usize,     // heights
+                *const i32,       // params
+                *const c_void     // trace context, which may be null
+            ) = unsafe { mem::transmute(compiled.function_pointer) };
+
+            f(
+                buffers.as_ptr(),
+                widths.as_ptr(),
+                heights.as_ptr(),
+                param_values.as_ptr(),
+                context.as_ref().map_or(ptr::null(), |c| c.as_ptr())
+            );
+        } else {
+            let f: extern "C" fn(
+                *const *const u8, // buffers
+                *const usize,     // widths
+                *const usize,     // heights
+                *const i32        // params
+            ) = unsafe { mem::transmute(compiled.function_pointer) };
+
+            f(
+                buffers.as_ptr(),
+                widths.as_ptr(),
+                heights.as_ptr(),
+                param_values.as_ptr()
+            );
         }
 
-        (calculated_images.into_iter().collect(), tr)
+        Ok(calculated_images.into_iter().collect())
+    }
+}
+
+/// Converts the results of a graph whose outputs all have element type u8.
+fn to_gray_outputs(results: HashMap<String, DynamicImage>) -> HashMap<String, GrayImage> {
+    results.into_iter().map(|(name, image)| (name, image.into_buffer().unwrap())).collect()
+}
+
+/// The images that can be passed as inputs to the generated function.
+trait InputImage {
+    fn element_type(&self) -> Type;
+    fn dimensions(&self) -> (usize, usize);
+    /// A pointer to the start of the image data.
+    fn as_ptr(&self) -> *const u8;
+    /// The initial contents of the image in a trace.
+    fn to_gray(&self) -> GrayImage;
+}
+
+impl InputImage for GrayImage {
+    fn element_type(&self) -> Type {
+        Type::U8
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        Image::dimensions(self)
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        self.buffer.as_ptr()
+    }
+
+    fn to_gray(&self) -> GrayImage {
+        self.clone()
+    }
+}
+
+impl InputImage for DynamicImage {
+    fn element_type(&self) -> Type {
+        DynamicImage::element_type(self)
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        DynamicImage::dimensions(self)
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        DynamicImage::as_ptr(self)
+    }
+
+    fn to_gray(&self) -> GrayImage {
+        DynamicImage::to_gray(self)
     }
 }
@@ -2,8 +2,9 @@
 
 use std::collections::HashMap;
 use llvm_sys::prelude::LLVMValueRef;
+use crate::error::PrismError;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SymbolTable {
     symbols: HashMap<String, LLVMValueRef>
 }
@@ -24,10 +25,18 @@ impl SymbolTable {
         };
     }
 
+    pub fn contains(&self, name: &str) -> bool {
+        self.symbols.contains_key(name)
+    }
+
     pub fn get(&self, name: &str) -> LLVMValueRef {
-        match self.symbols.get(name) {
-            Some(v) => *v,
-            None => panic!("Get failed - symbol {} not found", name)
+        match self.try_get(name) {
+            Ok(v) => v,
+            Err(_) => panic!("Get failed - symbol {} not found", name)
         }
     }
+
+    pub fn try_get(&self, name: &str) -> Result<LLVMValueRef, PrismError> {
+        self.symbols.get(name).cloned().ok_or_else(|| PrismError::UnknownSymbol(name.to_string()))
+    }
 }
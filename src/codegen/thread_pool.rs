@@ -0,0 +1,137 @@
+//! The runtime support used by generated code to run the iterations of
+//! parallel loops on a pool of worker threads.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, OnceLock, atomic::{AtomicI32, Ordering}},
+    thread
+};
+use crate::tracing::{set_current_worker, WorkerId};
+
+/// A function generated to run a single iteration of a parallel loop. `closure`
+/// points to the values captured from the enclosing function.
+pub type ParallelBody = extern "C" fn(closure: *const u8, index: i32);
+
+/// Runs `body(closure, i)` for each `i` in [0, extent) on the global thread
+/// pool, returning once every iteration has completed. The calling thread also
+/// runs iterations, so parallel loops can safely be nested.
+pub extern "C" fn do_par_for(body: ParallelBody, closure: *const u8, extent: i32) {
+    if extent <= 0 {
+        return;
+    }
+    let job = Arc::new(Job {
+        body,
+        closure,
+        extent,
+        next: AtomicI32::new(0),
+        completed: Mutex::new(0),
+        finished: Condvar::new()
+    });
+    let pool = thread_pool();
+    pool.queue.lock().unwrap().push_back(job.clone());
+    pool.available.notify_all();
+
+    while job.run_next() {}
+    let mut completed = job.completed.lock().unwrap();
+    while *completed < extent {
+        completed = job.finished.wait(completed).unwrap();
+    }
+}
+
+/// A call to `do_par_for` whose iterations have not all been claimed.
+struct Job {
+    body: ParallelBody,
+    closure: *const u8,
+    extent: i32,
+    /// The next iteration to claim.
+    next: AtomicI32,
+    /// The number of iterations that have finished running.
+    completed: Mutex<i32>,
+    finished: Condvar
+}
+
+// The closure is only read, and is only used while `do_par_for` is waiting
+// for the iterations that use it to complete.
+unsafe impl Send for Job {}
+unsafe impl Sync for Job {}
+
+impl Job {
+    /// Claims and runs the next iteration, returning false if all
+    /// iterations have already been claimed.
+    fn run_next(&self) -> bool {
+        let index = self.next.fetch_add(1, Ordering::SeqCst);
+        if index >= self.extent {
+            return false;
+        }
+        (self.body)(self.closure, index);
+        let mut completed = self.completed.lock().unwrap();
+        *completed += 1;
+        if *completed == self.extent {
+            self.finished.notify_all();
+        }
+        true
+    }
+}
+
+struct ThreadPool {
+    queue: Mutex<VecDeque<Arc<Job>>>,
+    available: Condvar
+}
+
+/// Returns the global thread pool, starting its workers on first use.
+/// Workers are numbered from 1, as the main thread has worker id 0.
+fn thread_pool() -> &'static ThreadPool {
+    static POOL: OnceLock<Arc<ThreadPool>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let pool = Arc::new(ThreadPool { queue: Mutex::new(VecDeque::new()), available: Condvar::new() });
+        let workers = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        for worker in 1..=workers {
+            let pool = pool.clone();
+            thread::spawn(move || {
+                set_current_worker(WorkerId(worker));
+                run_worker(&pool);
+            });
+        }
+        pool
+    })
+}
+
+fn run_worker(pool: &ThreadPool) {
+    loop {
+        let job = {
+            let mut queue = pool.queue.lock().unwrap();
+            loop {
+                match queue.front() {
+                    Some(job) if job.next.load(Ordering::SeqCst) >= job.extent => {
+                        queue.pop_front();
+                    },
+                    Some(job) => break job.clone(),
+                    None => queue = pool.available.wait(queue).unwrap()
+                }
+            }
+        };
+        while job.run_next() {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn nested(_: *const u8, _: i32) {
+        do_par_for(count, std::ptr::null(), 10);
+    }
+
+    extern "C" fn count(_: *const u8, _: i32) {
+        COUNT.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_nested_do_par_for() {
+        do_par_for(nested, std::ptr::null(), 20);
+        assert_eq!(COUNT.load(Ordering::SeqCst), 200);
+    }
+}
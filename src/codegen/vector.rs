@@ -0,0 +1,204 @@
+//! Functions for lowering func definitions to LLVM vector instructions, to compute
+//! the values of a func at several consecutive values of a pure variable at once.
+//!
+//! Vectorized code performs no bounds checks, so may only be used for iterations
+//! where all accesses are known to be in bounds.
+
+use llvm_sys::prelude::*;
+use crate::{syntax::*, codegen::*, llvm::*};
+
+/// The values of x and y in each lane, when computing `width` consecutive
+/// values of `var` starting from the current values of the x and y symbols.
+fn lane_coordinates(
+    builder: &Builder,
+    var: Var,
+    width: u32,
+    symbols: &SymbolTable
+) -> (LLVMValueRef, LLVMValueRef) {
+    let (x, y) = (builder.splat(symbols.get("x"), width), builder.splat(symbols.get("y"), width));
+    let mut offsets: Vec<LLVMValueRef> = (0..width).map(|i| builder.const_i32(i as i32)).collect();
+    let offsets = builder.const_vector(&mut offsets);
+    match var {
+        Var::X => (builder.add(x, offsets), y),
+        Var::Y => (x, builder.add(y, offsets))
+    }
+}
+
+/// x and y are vectors of i32, return value is a vector of i32
+fn lower_var_expr_vector(
+    builder: &Builder,
+    expr: &VarExpr,
+    x: LLVMValueRef,
+    y: LLVMValueRef,
+    width: u32
+) -> LLVMValueRef {
+    let recurse = |v| lower_var_expr_vector(builder, v, x, y, width);
+    match expr {
+        VarExpr::Var(v) => match v { Var::X => x, Var::Y => y },
+        VarExpr::Const(c) => builder.splat(builder.const_i32(*c), width),
+        VarExpr::Add(l, r) => builder.add(recurse(l), recurse(r)),
+        VarExpr::Sub(l, r) => builder.sub(recurse(l), recurse(r)),
+        VarExpr::Mul(l, r) => builder.mul(recurse(l), recurse(r)),
+    }
+}
+
+/// Whether consecutive lanes of the vectorized variable `var` read from
+/// consecutive addresses in the buffer for `access`.
+fn is_contiguous(access: &Access, var: Var) -> bool {
+    match (affine_coefficients(&access.x), affine_coefficients(&access.y)) {
+        (Some((xa, xb, _)), Some((ya, yb, _))) => match var {
+            Var::X => xa == 1 && ya == 0,
+            Var::Y => xb == 1 && yb == 0
+        },
+        _ => false
+    }
+}
+
+/// Return value is a vector of the values read by `access` in each lane,
+/// zero extended to i32.
+fn lower_access_vector(
+    builder: &Builder,
+    access: &Access,
+    var: Var,
+    width: u32,
+    symbols: &SymbolTable
+) -> LLVMValueRef {
+    let input = symbols.get(&access.source);
+    let (x, y) = lane_coordinates(builder, var, width, symbols);
+    let (x, y) = (
+        lower_var_expr_vector(builder, &access.x, x, y, width),
+        lower_var_expr_vector(builder, &access.y, x, y, width)
+    );
+    let lanes: Vec<(LLVMValueRef, LLVMValueRef)> = (0..width)
+        .map(|i| {
+            let i = builder.const_i32(i as i32);
+            (builder.extract_element(x, i), builder.extract_element(y, i))
+        })
+        .collect();
+
+    let align = access.ty.size() as u32;
+    let vector_type = builder.type_vector(element_type(builder, access.ty), width);
+    let values = if is_contiguous(access, var) {
+        let (x, y) = lanes[0];
+        let offset = buffer_offset(builder, &access.source, x, y, symbols);
+        let ptr = builder.in_bounds_gep(input, offset);
+        builder.load(builder.bitcast(ptr, builder.ptr_type(vector_type)), align)
+    } else {
+        let mut values = builder.undef(vector_type);
+        for (i, (x, y)) in lanes.iter().enumerate() {
+            let offset = buffer_offset(builder, &access.source, *x, *y, symbols);
+            let value = builder.load(builder.in_bounds_gep(input, offset), align);
+            values = builder.insert_element(values, value, builder.const_i32(i as i32));
+        }
+        values
+    };
+    if let Ok(log_read) = symbols.try_get("log_read") {
+        let context = symbols.get("trace_context");
+        let source = symbols.get(&trace_id_symbol_name(&access.source));
+        for (x, y) in lanes {
+            builder.build_function_call(log_read, &mut [context, source, x, y]);
+        }
+    }
+    convert_from_element(builder, values, access.ty)
+}
+
+/// Return value is a vector of the type given by `definition.value_type()`
+fn lower_definition_vector(
+    builder: &Builder,
+    definition: &Definition,
+    var: Var,
+    width: u32,
+    symbols: &SymbolTable
+) -> LLVMValueRef {
+    // Lowers a subexpression and converts it to the given value type
+    let recurse = |v: &Definition, ty: Type| {
+        let value = lower_definition_vector(builder, v, var, width, symbols);
+        convert_value(builder, value, v.value_type(), ty)
+    };
+    let ty = definition.value_type();
+    match definition {
+        Definition::Access(a) => lower_access_vector(builder, a, var, width, symbols),
+        Definition::Const(c) => builder.splat(builder.const_i32(*c), width),
+        Definition::FloatConst(c) => builder.splat(builder.const_f32(*c), width),
+        Definition::Param(p) => builder.splat(symbols.get(p), width),
+        Definition::Cast(cast_type, d) => {
+            let value = recurse(d, d.value_type());
+            let stored = convert_to_element(builder, value, d.value_type(), *cast_type);
+            convert_from_element(builder, stored, *cast_type)
+        },
+        Definition::SaturatingCast(cast_type, d) => {
+            saturate_value(builder, recurse(d, d.value_type()), d.value_type(), *cast_type)
+        },
+        Definition::Clamp(v, lo, hi) => {
+            clamp_value(builder, ty, recurse(v, ty), recurse(lo, ty), recurse(hi, ty))
+        },
+        Definition::Cond(c) => {
+            let cmp_type = common_value_type(&c.lhs, &c.rhs);
+            let (left, right) = (recurse(&c.lhs, cmp_type), recurse(&c.rhs, cmp_type));
+            let cond = lower_comparison(builder, c.cmp, cmp_type, left, right);
+            builder.select(cond, recurse(&c.if_true, ty), recurse(&c.if_false, ty))
+        },
+        Definition::Add(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            if ty.is_float() { builder.fadd(l, r) } else { builder.add(l, r) }
+        },
+        Definition::Mul(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            if ty.is_float() { builder.fmul(l, r) } else { builder.mul(l, r) }
+        },
+        Definition::Sub(l, r) => {
+            let (l, r) = (recurse(l, ty), recurse(r, ty));
+            if ty.is_float() { builder.fsub(l, r) } else { builder.sub(l, r) }
+        },
+        Definition::Div(l, r) => lower_div(builder, ty, recurse(l, ty), recurse(r, ty)),
+        Definition::Abs(d) => lower_abs(builder, ty, recurse(d, ty)),
+        Definition::Binary(op, l, r) => lower_binary_op(builder, *op, ty, recurse(l, ty), recurse(r, ty)),
+        Definition::Select(c, t, f) => {
+            let cond = lower_bool_expr(builder, c, &mut |d, ty| recurse(d, ty));
+            builder.select(cond, recurse(t, ty), recurse(f, ty))
+        }
+    }
+}
+
+/// Computes and stores the values of `func` at `width` consecutive values of `var`,
+/// starting from the current values of the x and y symbols. All accesses made by
+/// `func` must be in bounds.
+pub(crate) fn lower_func_vector(
+    builder: &Builder,
+    func: &Func,
+    var: Var,
+    width: u32,
+    symbols: &SymbolTable
+) {
+    let val = lower_definition_vector(builder, &func.definition, var, width, symbols);
+    let align = func.ty.size() as u32;
+    let vector_type = builder.type_vector(element_type(builder, func.ty), width);
+    let (val, value_type) = apply_store_policy(builder, val, func.definition.value_type(), func);
+    let stored = convert_to_element(builder, val, value_type, func.ty);
+    let buffer = symbols.get(&func.name);
+    let log_write = symbols.try_get("log_write").ok();
+    let (x, y) = lane_coordinates(builder, var, width, symbols);
+
+    for i in 0..width {
+        let i = builder.const_i32(i as i32);
+        let (x, y) = (builder.extract_element(x, i), builder.extract_element(y, i));
+        if let Some(log_write) = log_write {
+            let context = symbols.get("trace_context");
+            let id = symbols.get(&trace_id_symbol_name(&func.name));
+            let value = trace_value(builder, builder.extract_element(val, i), value_type, func.ty);
+            builder.build_function_call(log_write, &mut [context, id, x, y, value]);
+        }
+        // Lanes in the same row are stored below with a single vector store
+        if var == Var::Y {
+            let offset = buffer_offset(builder, &func.name, x, y, symbols);
+            let value = builder.extract_element(stored, i);
+            builder.store(value, builder.in_bounds_gep(buffer, offset), align);
+        }
+    }
+    if var == Var::X {
+        let (x, y) = (symbols.get("x"), symbols.get("y"));
+        let offset = buffer_offset(builder, &func.name, x, y, symbols);
+        let ptr = builder.in_bounds_gep(buffer, offset);
+        builder.store(stored, builder.bitcast(ptr, builder.ptr_type(vector_type)), align);
+    }
+}
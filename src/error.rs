@@ -0,0 +1,75 @@
+//! The errors reported when constructing or running a pipeline.
+
+use std::{error::Error, fmt};
+use crate::syntax::Type;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrismError {
+    /// More than one func has this name.
+    DuplicateFunc(String),
+    /// The named func reads from itself.
+    SelfReference(String),
+    /// Each func in the cycle reads from the next, and the last reads from the first.
+    Cycle(Vec<String>),
+    /// The named output is not one of the funcs in the graph.
+    UnknownOutput(String),
+    /// The graph has no outputs, either because it has no funcs or because no outputs were named.
+    NoOutputs,
+    /// The schedule of `func` refers to a loop level in `unknown`,
+    /// which is not a func in the graph.
+    UnknownFunc { func: String, unknown: String },
+    /// No schedule was provided for the named func or source.
+    MissingSchedule(String),
+    /// The schedule of `func` cannot be satisfied.
+    InvalidSchedule { func: String, reason: String },
+    /// Code generation referred to a symbol that has not been defined.
+    UnknownSymbol(String),
+    /// The named source is read by the pipeline but no image was provided for it.
+    MissingSource(String),
+    /// The named param is used by the pipeline but no value was provided for it.
+    MissingParam(String),
+    /// The named func, source or image has element type `actual`, but `expected` was required.
+    TypeMismatch { name: String, expected: Type, actual: Type },
+    /// The graph reads no sources, so there is no input to determine the size of its outputs.
+    NoInputs,
+    /// Tracing was requested from a processor compiled without tracing instrumentation.
+    NotInstrumented,
+    /// The text of a pipeline could not be parsed. Lines and columns are numbered from 1.
+    Parse { line: usize, column: usize, message: String }
+}
+
+impl fmt::Display for PrismError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrismError::DuplicateFunc(name) => write!(f, "More than one func is named {}", name),
+            PrismError::SelfReference(name) => write!(f, "{} reads from itself", name),
+            PrismError::Cycle(names) => write!(f, "Funcs form a cycle: {} -> {}", names.join(" -> "), names[0]),
+            PrismError::UnknownOutput(name) => write!(f, "Output {} is not a func in this graph", name),
+            PrismError::NoOutputs => write!(f, "The graph has no outputs"),
+            PrismError::UnknownFunc { func, unknown } => write!(
+                f, "The schedule for {} refers to {}, which is not a func in this graph", func, unknown
+            ),
+            PrismError::MissingSchedule(name) => write!(f, "No schedule was provided for {}", name),
+            PrismError::InvalidSchedule { func, reason } => write!(f, "Invalid schedule for {}: {}", func, reason),
+            PrismError::UnknownSymbol(name) => write!(f, "Symbol {} not found", name),
+            PrismError::MissingSource(name) => write!(
+                f, "Required source {} is not calculated and is not provided as an input", name
+            ),
+            PrismError::MissingParam(name) => write!(f, "No value was provided for param {}", name),
+            PrismError::TypeMismatch { name, expected, actual } => write!(
+                f, "Expected {} to have element type {}, but it has type {}", name, expected, actual
+            ),
+            PrismError::NoInputs => write!(
+                f, "The graph has no inputs, so the size of its outputs cannot be determined"
+            ),
+            PrismError::NotInstrumented => write!(
+                f, "Tracing requires code generated with CodegenOptions::tracing set"
+            ),
+            PrismError::Parse { line, column, message } => write!(
+                f, "Parse error at line {}, column {}: {}", line, column, message
+            )
+        }
+    }
+}
+
+impl Error for PrismError {}
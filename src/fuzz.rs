@@ -0,0 +1,512 @@
+//! Generates random pipelines, for differential testing of the generated code
+//! against the reference interpreter.
+
+use std::{collections::HashMap, fmt};
+use crate::{
+    codegen::*,
+    error::PrismError,
+    image::*,
+    interpreter::*,
+    llvm::*,
+    syntax::*
+};
+
+/// A small xorshift random number generator, so that failing
+/// cases can be reproduced from their seed.
+pub struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // The state must be non-zero
+        Rng { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Returns a value in [min, max].
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next_u64() % (max - min + 1) as u64) as i32
+    }
+
+    /// Returns true with probability `1 / n`.
+    pub fn one_in(&mut self, n: u32) -> bool {
+        self.range(0, n as i32 - 1) == 0
+    }
+
+    pub fn choose<'a, T>(&mut self, values: &'a [T]) -> &'a T {
+        &values[self.range(0, values.len() as i32 - 1) as usize]
+    }
+}
+
+/// A randomly generated pipeline, along with the inputs to run it on.
+/// The final func is the pipeline's only output.
+#[derive(Clone)]
+pub struct Case {
+    pub funcs: Vec<Func>,
+    pub schedules: Vec<FuncSchedule>,
+    pub params: Vec<(String, i32)>,
+    pub width: usize,
+    pub height: usize,
+    pub input_type: Type,
+    pub seed: u64
+}
+
+/// The element types given to the input and to funcs. u8 is the most common
+/// in practice, so is chosen more often.
+const TYPES: [Type; 6] = [Type::U8, Type::U8, Type::U16, Type::I16, Type::I32, Type::F32];
+
+const INPUT: &str = "input";
+
+impl Case {
+    /// Generates a random pipeline, for which `graph` returns a valid `Graph`
+    /// that reads from the input.
+    pub fn random(rng: &mut Rng) -> Case {
+        let params: Vec<(String, i32)> = (0..rng.range(0, 2))
+            .map(|i| (format!("p{}", i), rng.range(-10, 10)))
+            .collect();
+        let param_names: Vec<String> = params.iter().map(|p| p.0.clone()).collect();
+        let (width, height) = (rng.range(1, 20) as usize, rng.range(1, 20) as usize);
+        let input_type = *rng.choose(&TYPES);
+        // Retry until the output reads from the input, as otherwise there is
+        // nothing to determine the size of the output
+        let mut case = loop {
+            let mut funcs: Vec<Func> = vec![];
+            for i in 0..rng.range(1, 4) {
+                let mut sources = vec![(INPUT.to_string(), input_type)];
+                sources.extend(funcs.iter().map(|f| (f.name.clone(), f.ty)));
+                let definition = random_definition(rng, &sources, &param_names, 3);
+                let store_policy = if rng.one_in(3) { StorePolicy::Saturate } else { StorePolicy::Wrap };
+                let func = Func::new(&format!("f{}", i), definition)
+                    .with_type(*rng.choose(&TYPES))
+                    .with_store_policy(store_policy);
+                funcs.push(func);
+            }
+            let case = Case {
+                schedules: funcs.iter().map(|_| FuncSchedule::by_row()).collect(),
+                funcs,
+                params: params.clone(),
+                width,
+                height,
+                input_type,
+                seed: rng.next_u64()
+            };
+            if case.graph().is_ok() {
+                break case;
+            }
+        };
+        // Not all combinations of schedules are valid, so retry until we find one
+        // that is. Scheduling every func by row at root is always valid
+        for _ in 0..10 {
+            let schedules = random_schedules(rng, &case.funcs);
+            let candidate = Case { schedules, ..case.clone() };
+            if candidate.graph().is_ok() {
+                return candidate;
+            }
+        }
+        case.schedules = case.funcs.iter().map(|_| FuncSchedule::by_row()).collect();
+        case
+    }
+
+    /// Returns the graph for this pipeline, or an error if it is invalid or does not
+    /// read from the input.
+    pub fn graph(&self) -> Result<Graph, PrismError> {
+        let mut sched = Schedule::new();
+        sched.add_source(&self.source(), FuncSchedule::by_row());
+        for (func, schedule) in self.funcs.iter().zip(&self.schedules) {
+            sched.add_func(func, schedule.clone());
+        }
+        let output = &self.funcs.last().unwrap().name;
+        let graph = Graph::with_outputs("fuzz", self.funcs.clone(), &[output], sched)?;
+        if graph.inputs().is_empty() {
+            return Err(PrismError::NoInputs);
+        }
+        Ok(graph)
+    }
+
+    fn source(&self) -> Source {
+        Source::new(INPUT).with_type(self.input_type)
+    }
+
+    /// Returns an image of random values spanning most or all of the range of the input's
+    /// element type. Float inputs are multiples of 1/4, with both signs.
+    pub fn input(&self) -> DynamicImage {
+        fn random_image<T: Pixel>(case: &Case, value: impl Fn(&mut Rng) -> T) -> ImageBuffer<T> {
+            let mut rng = Rng::new(case.seed);
+            let mut image = ImageBuffer::new(case.width, case.height);
+            for y in 0..case.height {
+                for x in 0..case.width {
+                    image.set(x, y, value(&mut rng));
+                }
+            }
+            image
+        }
+        match self.input_type {
+            Type::U8 => DynamicImage::from(random_image(self, |rng| rng.range(0, 255) as u8)),
+            Type::U16 => DynamicImage::from(random_image(self, |rng| rng.range(0, 65535) as u16)),
+            Type::I16 => DynamicImage::from(random_image(self, |rng| rng.range(-32768, 32767) as i16)),
+            Type::I32 => DynamicImage::from(random_image(self, |rng| rng.range(-100_000, 100_000))),
+            Type::F32 => DynamicImage::from(random_image(self, |rng| rng.range(-1000, 1000) as f32 / 4.0))
+        }
+    }
+
+    fn param_values(&self) -> HashMap<Param, i32> {
+        self.params.iter().map(|(name, value)| (Param::new(name), *value)).collect()
+    }
+
+    /// Returns the output of the generated code for this pipeline.
+    pub fn run_jit(&self) -> HashMap<String, DynamicImage> {
+        let graph = self.graph().unwrap();
+        let context = Context::new();
+        let mut module = create_ir_module(&context, &graph);
+        optimise(&mut module);
+        let processor = create_processor(module, &graph);
+        let (input, image) = (self.source(), self.input());
+        processor.process_dynamic(&[(&input, &image)], &self.param_values())
+    }
+
+    /// Returns the output of the reference interpreter for this pipeline.
+    pub fn run_interpreter(&self) -> HashMap<String, DynamicImage> {
+        let graph = self.graph().unwrap();
+        let (input, image) = (self.source(), self.input());
+        interpret_dynamic(&graph, &[(&input, &image)], &self.param_values()).unwrap()
+    }
+
+    /// Whether the generated code and the interpreter disagree on this pipeline.
+    /// NaNs are treated as equal to each other, whatever their bit patterns.
+    pub fn fails(&self) -> bool {
+        let (jit, interpreter) = (self.run_jit(), self.run_interpreter());
+        jit.iter().any(|(name, image)| match (image, &interpreter[name]) {
+            (DynamicImage::F32(a), DynamicImage::F32(b)) => {
+                a.data().iter().zip(b.data()).any(|(a, b)| a != b && !(a.is_nan() && b.is_nan()))
+            },
+            (a, b) => a != b
+        })
+    }
+
+    /// Returns the cases that are one step simpler than this one. Not all
+    /// of these are guaranteed to be valid.
+    fn simplifications(&self) -> Vec<Case> {
+        let mut cases = vec![];
+        for (i, func) in self.funcs.iter().enumerate() {
+            if self.schedules[i] != FuncSchedule::by_row() {
+                let mut case = self.clone();
+                case.schedules[i] = FuncSchedule::by_row();
+                cases.push(case);
+            }
+            for definition in simplifications(&func.definition) {
+                let mut case = self.clone();
+                case.funcs[i] = Func { definition, ..func.clone() };
+                cases.push(case);
+            }
+            if func.ty != Type::U8 {
+                let mut case = self.clone();
+                case.funcs[i] = func.clone().with_type(Type::U8);
+                cases.push(case);
+            }
+        }
+        if self.input_type != Type::U8 {
+            cases.push(self.with_input_type(Type::U8));
+        }
+        if self.width > 1 {
+            cases.push(Case { width: self.width - 1, ..self.clone() });
+        }
+        if self.height > 1 {
+            cases.push(Case { height: self.height - 1, ..self.clone() });
+        }
+        cases
+    }
+
+    /// Returns this case with the input, and so every access to it, given element type `ty`.
+    fn with_input_type(&self, ty: Type) -> Case {
+        let mut case = Case { input_type: ty, ..self.clone() };
+        for func in &mut case.funcs {
+            for access in func.definition.accesses_mut() {
+                if access.source == INPUT {
+                    access.ty = ty;
+                }
+            }
+        }
+        case
+    }
+
+    /// Repeatedly simplifies this case for as long as `fails` continues to hold.
+    pub fn shrink(&self, fails: impl Fn(&Case) -> bool) -> Case {
+        let mut case = self.clone();
+        'shrink: loop {
+            for candidate in case.simplifications() {
+                if candidate.graph().is_ok() && fails(&candidate) {
+                    case = candidate;
+                    continue 'shrink;
+                }
+            }
+            return case;
+        }
+    }
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "input: {}x{} {}, seed {}", self.width, self.height, self.input_type, self.seed)?;
+        for (name, value) in &self.params {
+            writeln!(f, "{} = {}", name, value)?;
+        }
+        for (func, schedule) in self.funcs.iter().zip(&self.schedules) {
+            writeln!(f, "{}", func.pretty_print())?;
+            writeln!(f, "    {:?}", schedule)?;
+        }
+        Ok(())
+    }
+}
+
+fn random_var_expr(rng: &mut Rng, var: Var) -> VarExpr {
+    let offset = VarExpr::Const(rng.range(-2, 2));
+    let var = if rng.one_in(6) {
+        VarExpr::Mul(Box::new(VarExpr::Const(2)), Box::new(VarExpr::Var(var)))
+    } else {
+        VarExpr::Var(var)
+    };
+    VarExpr::Add(Box::new(var), Box::new(offset))
+}
+
+/// Returns a random definition reading from `sources`, which are given along with their element types.
+fn random_definition(rng: &mut Rng, sources: &[(String, Type)], params: &[String], depth: u32) -> Definition {
+    let leaf = depth == 0 || rng.one_in(3);
+    if leaf {
+        return match rng.range(0, 6) {
+            0 => Definition::Const(rng.range(-5, 20)),
+            1 if !params.is_empty() => Definition::Param(rng.choose(params).clone()),
+            2 if rng.one_in(2) => {
+                // Values outside the range of i32 exercise conversions that saturate, and
+                // infinity leads to NaN when multiplied by zero
+                let value = if rng.one_in(4) {
+                    *rng.choose(&[1e10, -1e10, f32::INFINITY])
+                } else {
+                    rng.range(-20, 80) as f32 / 4.0
+                };
+                Definition::FloatConst(value)
+            },
+            _ => {
+                let (source, ty) = rng.choose(sources);
+                let (x, y) = (random_var_expr(rng, Var::X), random_var_expr(rng, Var::Y));
+                if rng.one_in(4) {
+                    let boundaries = [
+                        Boundary::Zero,
+                        Boundary::Constant(rng.range(0, 255)),
+                        Boundary::Clamp,
+                        Boundary::Mirror,
+                        Boundary::Wrap
+                    ];
+                    Definition::Access(Access::with_boundary(source, x, y, *rng.choose(&boundaries)).with_type(*ty))
+                } else {
+                    Definition::Access(Access::new(source, x, y).with_type(*ty))
+                }
+            }
+        };
+    }
+    let recurse = |rng: &mut Rng| Box::new(random_definition(rng, sources, params, depth - 1));
+    match rng.range(0, 10) {
+        0 => Definition::Add(recurse(rng), recurse(rng)),
+        1 => Definition::Sub(recurse(rng), recurse(rng)),
+        2 => Definition::Mul(recurse(rng), recurse(rng)),
+        3 => Definition::Div(recurse(rng), recurse(rng)),
+        4 => Definition::Clamp(recurse(rng), recurse(rng), recurse(rng)),
+        5 => {
+            let types = [Type::U8, Type::U16, Type::I16];
+            Definition::SaturatingCast(*rng.choose(&types), recurse(rng))
+        },
+        6 => Definition::Abs(recurse(rng)),
+        7 => {
+            let ops = [
+                BinaryOp::Min,
+                BinaryOp::Max,
+                BinaryOp::AbsDiff,
+                BinaryOp::Rem,
+                BinaryOp::BitAnd,
+                BinaryOp::BitOr,
+                BinaryOp::BitXor,
+                BinaryOp::Shl,
+                BinaryOp::Shr
+            ];
+            Definition::Binary(*rng.choose(&ops), recurse(rng), recurse(rng))
+        },
+        8 => {
+            let cond = random_bool_expr(rng, sources, params, depth - 1);
+            Definition::Select(Box::new(cond), recurse(rng), recurse(rng))
+        },
+        9 => Definition::Cast(*rng.choose(&TYPES), recurse(rng)),
+        _ => {
+            let cmps = [Comparison::EQ, Comparison::GT, Comparison::GTE, Comparison::LT, Comparison::LTE];
+            let cmp = *rng.choose(&cmps);
+            let (lhs, rhs) = (recurse(rng), recurse(rng));
+            let (if_true, if_false) = (recurse(rng), recurse(rng));
+            Definition::Cond(Condition::new(cmp, *lhs, *rhs, *if_true, *if_false))
+        }
+    }
+}
+
+fn random_bool_expr(rng: &mut Rng, sources: &[(String, Type)], params: &[String], depth: u32) -> BoolExpr {
+    let recurse = |rng: &mut Rng| Box::new(random_bool_expr(rng, sources, params, depth - 1));
+    match rng.range(0, 3) {
+        0 if depth > 0 => BoolExpr::And(recurse(rng), recurse(rng)),
+        1 if depth > 0 => BoolExpr::Or(recurse(rng), recurse(rng)),
+        2 if depth > 0 => BoolExpr::Not(recurse(rng)),
+        _ => {
+            let cmps = [Comparison::EQ, Comparison::GT, Comparison::GTE, Comparison::LT, Comparison::LTE];
+            let cmp = *rng.choose(&cmps);
+            let lhs = random_definition(rng, sources, params, depth);
+            let rhs = random_definition(rng, sources, params, depth);
+            BoolExpr::Compare(cmp, Box::new(lhs), Box::new(rhs))
+        }
+    }
+}
+
+fn random_schedules(rng: &mut Rng, funcs: &[Func]) -> Vec<FuncSchedule> {
+    let mut schedules: Vec<FuncSchedule> = vec![];
+    for (i, func) in funcs.iter().enumerate() {
+        let mut sched = if rng.one_in(3) { FuncSchedule::by_column() } else { FuncSchedule::by_row() };
+        if rng.one_in(2) {
+            let var = sched.variables[rng.range(0, 1) as usize].clone();
+            let (outer, inner) = (format!("{}o", var), format!("{}i", var));
+            sched = sched.split(var, outer.as_str(), inner.as_str(), rng.range(1, 5) as u32);
+        }
+        let innermost = sched.variables.last().unwrap().clone();
+        if rng.one_in(3) && (innermost.as_var().is_some() || sched.split_creating(&innermost).is_some()) {
+            sched = sched.vectorize(innermost.clone(), *rng.choose(&[2, 4, 8]));
+        }
+        if rng.one_in(4) {
+            let var = rng.choose(&sched.variables).clone();
+            sched = sched.unroll(var, rng.range(2, 3) as u32);
+        }
+        if rng.one_in(4) {
+            let var = sched.variables[0].clone();
+            sched = sched.parallel(var);
+        }
+
+        // Funcs with a single consumer may be computed inline or within its loops
+        let readers: Vec<&Func> = funcs.iter().filter(|f| f.sources().contains(&func.name)).collect();
+        if i + 1 < funcs.len() && readers.len() == 1 {
+            let consumer = readers[0];
+            let consumer_index = funcs.iter().position(|f| f.name == consumer.name).unwrap();
+            match rng.range(0, 2) {
+                0 => sched = sched.compute_inline(),
+                1 => {
+                    // The consumer's schedule is chosen later, so assume the default loop variables
+                    let var = *rng.choose(&[Var::Y, Var::X]);
+                    if consumer_index > i {
+                        sched = sched.compute_at(consumer, var);
+                    }
+                },
+                _ => {}
+            }
+        }
+        schedules.push(sched);
+    }
+    schedules
+}
+
+/// Returns the definitions obtained by replacing a single subexpression
+/// of `definition` with either one of its children or zero.
+fn simplifications(definition: &Definition) -> Vec<Definition> {
+    let mut result = vec![];
+    if !matches!(definition, Definition::Const(0)) {
+        result.push(Definition::Const(0));
+    }
+    let binary = |result: &mut Vec<Definition>, ctor: &dyn Fn(Box<Definition>, Box<Definition>) -> Definition, l: &Definition, r: &Definition| {
+        result.push(l.clone());
+        result.push(r.clone());
+        for s in simplifications(l) {
+            result.push(ctor(Box::new(s), Box::new(r.clone())));
+        }
+        for s in simplifications(r) {
+            result.push(ctor(Box::new(l.clone()), Box::new(s)));
+        }
+    };
+    match definition {
+        Definition::Access(_) | Definition::Const(_) | Definition::FloatConst(_) | Definition::Param(_) => {},
+        Definition::Binary(op, l, r) => binary(&mut result, &|l, r| Definition::Binary(*op, l, r), l, r),
+        // The condition is left unchanged
+        Definition::Select(c, t, f) => {
+            result.push((**t).clone());
+            result.push((**f).clone());
+            for s in simplifications(t) {
+                result.push(Definition::Select(c.clone(), Box::new(s), f.clone()));
+            }
+            for s in simplifications(f) {
+                result.push(Definition::Select(c.clone(), t.clone(), Box::new(s)));
+            }
+        },
+        Definition::Abs(d) => {
+            result.push((**d).clone());
+            for s in simplifications(d) {
+                result.push(Definition::Abs(Box::new(s)));
+            }
+        },
+        Definition::Cast(ty, d) => {
+            result.push((**d).clone());
+            for s in simplifications(d) {
+                result.push(Definition::Cast(*ty, Box::new(s)));
+            }
+        },
+        Definition::SaturatingCast(ty, d) => {
+            result.push((**d).clone());
+            for s in simplifications(d) {
+                result.push(Definition::SaturatingCast(*ty, Box::new(s)));
+            }
+        },
+        Definition::Clamp(v, lo, hi) => {
+            let children = [v, lo, hi];
+            for (i, child) in children.iter().enumerate() {
+                result.push((***child).clone());
+                for s in simplifications(child) {
+                    let mut parts: Vec<Box<Definition>> = children.iter().map(|c| (*c).clone()).collect();
+                    *parts[i] = s;
+                    let mut parts = parts.into_iter();
+                    let mut next = || parts.next().unwrap();
+                    result.push(Definition::Clamp(next(), next(), next()));
+                }
+            }
+        },
+        Definition::Add(l, r) => binary(&mut result, &Definition::Add, l, r),
+        Definition::Sub(l, r) => binary(&mut result, &Definition::Sub, l, r),
+        Definition::Mul(l, r) => binary(&mut result, &Definition::Mul, l, r),
+        Definition::Div(l, r) => binary(&mut result, &Definition::Div, l, r),
+        Definition::Cond(c) => {
+            let children = [&c.lhs, &c.rhs, &c.if_true, &c.if_false];
+            for (i, child) in children.iter().enumerate() {
+                result.push((***child).clone());
+                for s in simplifications(child) {
+                    let mut parts: Vec<Definition> = children.iter().map(|c| (***c).clone()).collect();
+                    parts[i] = s;
+                    let mut parts = parts.into_iter();
+                    let mut next = || parts.next().unwrap();
+                    result.push(Definition::Cond(Condition::new(c.cmp, next(), next(), next(), next())));
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jit_matches_interpreter() {
+        initialise_llvm_jit();
+        for seed in 0..100 {
+            let case = Case::random(&mut Rng::new(seed));
+            if case.fails() {
+                let minimal = case.shrink(|c| c.fails());
+                panic!("Generated code and interpreter disagree for seed {}. Minimal case:\n{}", seed, minimal);
+            }
+        }
+    }
+}
@@ -2,7 +2,7 @@
 use std::fmt;
 use crate::image::*;
 
-/// Trivial factory that just calls GrayImage::new
+/// Trivial factory that just calls ImageBuffer::new
 pub struct BufferFactory {}
 
 impl BufferFactory {
@@ -11,11 +11,11 @@ impl BufferFactory {
     }
 }
 
-impl Factory for BufferFactory {
-    type Image = GrayImage;
+impl<T: Zero + Copy> Factory<T> for BufferFactory {
+    type Image = ImageBuffer<T>;
 
-    fn create_image(&mut self, width: usize, height: usize) -> GrayImage {
-        GrayImage::new(width, height)
+    fn create_image(&mut self, width: usize, height: usize) -> ImageBuffer<T> {
+        ImageBuffer::new(width, height)
     }
 }
 
@@ -0,0 +1,158 @@
+//! Images whose element type is only known at runtime, as used by pipelines
+//! whose inputs and outputs have types other than u8.
+
+use crate::{image::*, syntax::Type};
+
+/// An image with one of the element types supported by generated code.
+#[derive(Clone, PartialEq, Debug)]
+pub enum DynamicImage {
+    U8(ImageBuffer<u8>),
+    U16(ImageBuffer<u16>),
+    I16(ImageBuffer<i16>),
+    I32(ImageBuffer<i32>),
+    F32(ImageBuffer<f32>)
+}
+
+/// Element types which can be stored in a `DynamicImage`.
+pub trait Pixel: Zero + Copy + Sized {
+    const TYPE: Type;
+
+    fn as_buffer(image: &DynamicImage) -> Option<&ImageBuffer<Self>>;
+    fn into_buffer(image: DynamicImage) -> Option<ImageBuffer<Self>>;
+
+    /// Converts to a u8, clamping to [0, 255].
+    fn to_u8(self) -> u8;
+}
+
+macro_rules! impl_pixel {
+    ($($t:ty, $variant:ident);*) => {
+        $(
+            impl Pixel for $t {
+                const TYPE: Type = Type::$variant;
+
+                fn as_buffer(image: &DynamicImage) -> Option<&ImageBuffer<$t>> {
+                    match image {
+                        DynamicImage::$variant(buffer) => Some(buffer),
+                        _ => None
+                    }
+                }
+
+                fn into_buffer(image: DynamicImage) -> Option<ImageBuffer<$t>> {
+                    match image {
+                        DynamicImage::$variant(buffer) => Some(buffer),
+                        _ => None
+                    }
+                }
+
+                fn to_u8(self) -> u8 {
+                    // Float to int casts saturate, and map NaN to 0
+                    (self as f32).max(0.0).min(255.0) as u8
+                }
+            }
+
+            impl From<ImageBuffer<$t>> for DynamicImage {
+                fn from(image: ImageBuffer<$t>) -> DynamicImage {
+                    DynamicImage::$variant(image)
+                }
+            }
+        )*
+    }
+}
+
+impl_pixel!(u8, U8; u16, U16; i16, I16; i32, I32; f32, F32);
+
+/// Applies `$f` to the buffer held by a `DynamicImage`, whatever its type.
+macro_rules! with_buffer {
+    ($image: expr, $buffer: ident => $f: expr) => {
+        match $image {
+            DynamicImage::U8($buffer) => $f,
+            DynamicImage::U16($buffer) => $f,
+            DynamicImage::I16($buffer) => $f,
+            DynamicImage::I32($buffer) => $f,
+            DynamicImage::F32($buffer) => $f
+        }
+    }
+}
+
+impl DynamicImage {
+    /// Creates a zero-filled image with the given element type.
+    pub fn new(ty: Type, width: usize, height: usize) -> DynamicImage {
+        match ty {
+            Type::U8 => DynamicImage::U8(ImageBuffer::new(width, height)),
+            Type::U16 => DynamicImage::U16(ImageBuffer::new(width, height)),
+            Type::I16 => DynamicImage::I16(ImageBuffer::new(width, height)),
+            Type::I32 => DynamicImage::I32(ImageBuffer::new(width, height)),
+            Type::F32 => DynamicImage::F32(ImageBuffer::new(width, height))
+        }
+    }
+
+    /// Converts a `GrayImage` to an image with the given element type.
+    pub fn from_gray(image: &GrayImage, ty: Type) -> DynamicImage {
+        fn convert<T: Pixel>(image: &GrayImage, f: impl Fn(u8) -> T) -> ImageBuffer<T> {
+            ImageBuffer::from_raw(image.width, image.height, image.buffer.iter().map(|c| f(*c)).collect())
+        }
+        match ty {
+            Type::U8 => DynamicImage::U8(image.clone()),
+            Type::U16 => DynamicImage::U16(convert(image, u16::from)),
+            Type::I16 => DynamicImage::I16(convert(image, i16::from)),
+            Type::I32 => DynamicImage::I32(convert(image, i32::from)),
+            Type::F32 => DynamicImage::F32(convert(image, f32::from))
+        }
+    }
+
+    pub fn element_type(&self) -> Type {
+        match self {
+            DynamicImage::U8(_) => Type::U8,
+            DynamicImage::U16(_) => Type::U16,
+            DynamicImage::I16(_) => Type::I16,
+            DynamicImage::I32(_) => Type::I32,
+            DynamicImage::F32(_) => Type::F32
+        }
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        with_buffer!(self, b => (b.width, b.height))
+    }
+
+    /// Returns the underlying buffer, or `None` if this image does not have element type `T`.
+    pub fn as_buffer<T: Pixel>(&self) -> Option<&ImageBuffer<T>> {
+        T::as_buffer(self)
+    }
+
+    /// Returns the underlying buffer, or `None` if this image does not have element type `T`.
+    pub fn into_buffer<T: Pixel>(self) -> Option<ImageBuffer<T>> {
+        T::into_buffer(self)
+    }
+
+    /// A pointer to the start of the image data.
+    pub(crate) fn as_ptr(&self) -> *const u8 {
+        with_buffer!(self, b => b.buffer.as_ptr().cast())
+    }
+
+    /// Converts to a `GrayImage`, clamping each value to [0, 255].
+    pub fn to_gray(&self) -> GrayImage {
+        with_buffer!(self, b => ImageBuffer {
+            width: b.width,
+            height: b.height,
+            buffer: b.buffer.iter().map(|c| c.to_u8()).collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dynamic_image() {
+        let image = DynamicImage::from(gray_image!(type: i16, -3i16, 40; 300, 7));
+        assert_eq!(image.element_type(), Type::I16);
+        assert_eq!(image.dimensions(), (2, 2));
+        assert_eq!(image.as_buffer::<u8>(), None);
+        assert_eq!(image.as_buffer::<i16>().unwrap().get(0, 1), 300);
+        assert_eq!(image.to_gray(), gray_image!(0, 40; 255, 7));
+        let converted = DynamicImage::from_gray(&gray_image!(3, 200), Type::I16);
+        assert_eq!(converted, DynamicImage::from(gray_image!(type: i16, 3i16, 200)));
+        assert_eq!(DynamicImage::new(Type::F32, 1, 1).into_buffer::<f32>(), Some(gray_image!(type: f32, 0.0)));
+    }
+}
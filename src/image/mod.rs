@@ -1,10 +1,12 @@
 //! Defines the basic image traits, and a buffer-based implementation of them.
 
 pub use self::buffer::*;
+pub use self::dynamic::*;
 pub use self::io::*;
 pub use self::traits::*;
 
 #[macro_use]
 mod buffer;
+mod dynamic;
 mod io;
 mod traits;
@@ -1,8 +1,8 @@
 
 /// Allocates images. This trait exists solely to allow us to trace reads and writes
 /// to all images when creating visualisations.
-pub trait Factory {
-    type Image: Image<u8>;
+pub trait Factory<T = u8> {
+    type Image: Image<T>;
     fn create_image(&mut self, width: usize, height: usize) -> Self::Image;
 }
 
@@ -43,6 +43,12 @@ macro_rules! impl_zero {
 
 impl_zero!(u8, i8, u16, i16, u32, i32, u64, i64);
 
+impl Zero for f32 {
+    fn zero() -> f32 {
+        0.0
+    }
+}
+
 impl Zero for [u8; 3] {
     fn zero() -> [u8; 3] {
         [0, 0, 0]
@@ -0,0 +1,448 @@
+//! A reference interpreter, which evaluates a `Graph` directly rather than generating
+//! code for it. Results match those of the generated code: intermediate calculations
+//! are performed on i32s or f32s as described by `Definition::value_type`, values
+//! are converted to the element type of their func when stored, and reads outside
+//! the bounds of a source are handled as specified by their boundary conditions.
+//!
+//! This is much slower than running the generated code, but provides an oracle
+//! for testing code generation and allows the value of any func to be inspected
+//! at each location where it was computed.
+
+use std::collections::HashMap;
+use crate::{error::PrismError, image::*, syntax::*};
+
+/// The value of a definition, with the type given by `Definition::value_type`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Float(f32)
+}
+
+impl Value {
+    /// Converts to an i32, rounding towards zero.
+    pub fn to_int(self) -> i32 {
+        match self {
+            Value::Int(i) => i,
+            Value::Float(f) => f as i32
+        }
+    }
+
+    pub fn to_float(self) -> f32 {
+        match self {
+            Value::Int(i) => i as f32,
+            Value::Float(f) => f
+        }
+    }
+
+    /// Converts to the given value type, which is `Type::I32` or `Type::F32`.
+    fn convert(self, ty: Type) -> Value {
+        if ty.is_float() { Value::Float(self.to_float()) } else { Value::Int(self.to_int()) }
+    }
+}
+
+/// The values of all funcs in a graph, computed by `evaluate_graph`.
+pub struct Evaluation {
+    /// The region computed for each func, or provided for each input.
+    regions: HashMap<String, Region<i32>>,
+    /// The values of each func and input over its region.
+    images: HashMap<String, DynamicImage>,
+    /// The region that accesses with a boundary condition treat as
+    /// the bounds of each func and input.
+    domains: HashMap<String, Region<i32>>,
+    outputs: Vec<String>,
+    /// The region over which outputs are returned.
+    output_region: Region<i32>
+}
+
+impl Evaluation {
+    /// Returns the value of the named func or input at (x, y), converted to
+    /// its value type, or `None` if it was not computed at this location.
+    pub fn value(&self, name: &str, x: i32, y: i32) -> Option<Value> {
+        let region = self.regions.get(name)?;
+        if x < region.x.min || x > region.x.max || y < region.y.min || y > region.y.max {
+            return None;
+        }
+        let (x, y) = ((x - region.x.min) as usize, (y - region.y.min) as usize);
+        Some(load(&self.images[name], x, y))
+    }
+
+    /// Returns the values of each output over the region covered by the first input,
+    /// as returned by `Processor::process`. Outputs that are also read by other funcs
+    /// may have been computed over a larger region.
+    pub fn outputs(&self) -> HashMap<String, DynamicImage> {
+        let region = self.output_region;
+        let width = std::cmp::max(region.x.max + 1, 0) as usize;
+        let height = std::cmp::max(region.y.max + 1, 0) as usize;
+        self.outputs
+            .iter()
+            .map(|o| {
+                let mut image = DynamicImage::new(self.images[o].element_type(), width, height);
+                for y in 0..height {
+                    for x in 0..width {
+                        store(&mut image, x, y, self.value(o, x as i32, y as i32).unwrap());
+                    }
+                }
+                (o.clone(), image)
+            })
+            .collect()
+    }
+
+    /// Returns the value read by `access` when its func is evaluated at (x, y).
+    fn read(&self, access: &Access, x: i32, y: i32) -> Value {
+        let (x, y) = (access.x.evaluate(x, y), access.y.evaluate(x, y));
+        let default = match access.boundary {
+            Some(Boundary::Constant(c)) => Value::Int(c),
+            _ => Value::Int(0)
+        };
+        let default = default.convert(access.ty.value_type());
+        let (x, y) = match access.boundary {
+            Some(boundary) => {
+                let domain = &self.domains[&access.source];
+                let (x, y) = (apply_boundary(boundary, x, &domain.x), apply_boundary(boundary, y, &domain.y));
+                if x < domain.x.min || x > domain.x.max || y < domain.y.min || y > domain.y.max {
+                    return default;
+                }
+                (x, y)
+            },
+            None => (x, y)
+        };
+        self.value(&access.source, x, y).unwrap_or(default)
+    }
+}
+
+/// Reads a value and converts it to its value type, as `codegen::convert_from_element` does.
+fn load(image: &DynamicImage, x: usize, y: usize) -> Value {
+    match image {
+        DynamicImage::U8(i) => Value::Int(i.get(x, y) as i32),
+        DynamicImage::U16(i) => Value::Int(i.get(x, y) as i32),
+        DynamicImage::I16(i) => Value::Int(i.get(x, y) as i32),
+        DynamicImage::I32(i) => Value::Int(i.get(x, y)),
+        DynamicImage::F32(i) => Value::Float(i.get(x, y))
+    }
+}
+
+/// Converts a value to the element type of `image` and stores it, as
+/// `codegen::convert_to_element` does.
+fn store(image: &mut DynamicImage, x: usize, y: usize, value: Value) {
+    match image {
+        DynamicImage::U8(i) => i.set(x, y, value.to_int() as u8),
+        DynamicImage::U16(i) => i.set(x, y, value.to_int() as u16),
+        DynamicImage::I16(i) => i.set(x, y, value.to_int() as i16),
+        DynamicImage::I32(i) => i.set(x, y, value.to_int()),
+        DynamicImage::F32(i) => i.set(x, y, value.to_float())
+    }
+}
+
+/// Converts a value to the element type `ty` and back, as storing it in
+/// a buffer of that type and reading it again would.
+fn cast(value: Value, ty: Type) -> Value {
+    match ty {
+        Type::U8 => Value::Int(value.to_int() as u8 as i32),
+        Type::U16 => Value::Int(value.to_int() as u16 as i32),
+        Type::I16 => Value::Int(value.to_int() as i16 as i32),
+        Type::I32 => Value::Int(value.to_int()),
+        Type::F32 => Value::Float(value.to_float())
+    }
+}
+
+/// Converts a value to the element type `ty` and back, clamping it to the range
+/// of `ty`, as `codegen::saturate_value` does.
+fn saturating_cast(value: Value, ty: Type) -> Value {
+    match ty.int_range() {
+        // Rust's float to int conversions also saturate, and convert NaN to zero
+        Some((min, max)) => Value::Int(value.to_int().clamp(min, max)),
+        None => Value::Float(value.to_float())
+    }
+}
+
+/// Applies `int_op` if both operands are i32s, and otherwise converts both to f32s and applies `float_op`.
+fn arithmetic(l: Value, r: Value, int_op: fn(i32, i32) -> i32, float_op: fn(f32, f32) -> f32) -> Value {
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => Value::Int(int_op(l, r)),
+        (l, r) => Value::Float(float_op(l.to_float(), r.to_float()))
+    }
+}
+
+/// Applies `op` as `codegen::lower_binary_op` does. Integer remainders by zero are zero.
+fn binary_op(op: BinaryOp, l: Value, r: Value) -> Value {
+    match op {
+        BinaryOp::Min => arithmetic(l, r, i32::min, f32::min),
+        BinaryOp::Max => arithmetic(l, r, i32::max, f32::max),
+        BinaryOp::AbsDiff => arithmetic(l, r, |l, r| l.max(r).wrapping_sub(l.min(r)), |l, r| (l - r).abs()),
+        BinaryOp::Rem => arithmetic(l, r, |l, r| if r == 0 { 0 } else { l.wrapping_rem(r) }, |l, r| l % r),
+        BinaryOp::BitAnd => Value::Int(l.to_int() & r.to_int()),
+        BinaryOp::BitOr => Value::Int(l.to_int() | r.to_int()),
+        BinaryOp::BitXor => Value::Int(l.to_int() ^ r.to_int()),
+        // The shift is taken modulo 32
+        BinaryOp::Shl => Value::Int(l.to_int().wrapping_shl(r.to_int() as u32)),
+        BinaryOp::Shr => Value::Int(l.to_int().wrapping_shr(r.to_int() as u32))
+    }
+}
+
+/// As for `arithmetic`. Comparisons involving NaN are false.
+fn compare(cmp: Comparison, l: Value, r: Value) -> bool {
+    fn holds<T: PartialOrd>(cmp: Comparison, l: T, r: T) -> bool {
+        match cmp {
+            Comparison::EQ => l == r,
+            Comparison::GT => l > r,
+            Comparison::GTE => l >= r,
+            Comparison::LT => l < r,
+            Comparison::LTE => l <= r
+        }
+    }
+    match (l, r) {
+        (Value::Int(l), Value::Int(r)) => holds(cmp, l, r),
+        (l, r) => holds(cmp, l.to_float(), r.to_float())
+    }
+}
+
+/// Maps a coordinate outside `bounds` to one inside it, as specified by `boundary`.
+/// Matches `codegen::apply_boundary`.
+fn apply_boundary(boundary: Boundary, c: i32, bounds: &Interval<i32>) -> i32 {
+    let extent = std::cmp::max(bounds.max - bounds.min + 1, 1);
+    let offset = c - bounds.min;
+    let offset = match boundary {
+        Boundary::Zero | Boundary::Constant(_) => return c,
+        Boundary::Clamp => std::cmp::min(std::cmp::max(offset, 0), extent - 1),
+        Boundary::Wrap => offset.rem_euclid(extent),
+        Boundary::Mirror => {
+            let r = offset.rem_euclid(2 * extent);
+            if r < extent { r } else { 2 * extent - 1 - r }
+        }
+    };
+    bounds.min + offset
+}
+
+/// Returns the value of `definition` at (x, y). `read` returns the value read
+/// by an access when evaluated at (x, y). Integer arithmetic wraps on overflow,
+/// as in the generated code, and integer quotients and remainders by zero are zero.
+pub fn evaluate_definition(
+    definition: &Definition,
+    x: i32,
+    y: i32,
+    params: &HashMap<String, i32>,
+    read: &impl Fn(&Access, i32, i32) -> Value
+) -> Value {
+    let recurse = |d: &Definition| evaluate_definition(d, x, y, params, read);
+    match definition {
+        Definition::Access(a) => read(a, x, y),
+        Definition::Const(c) => Value::Int(*c),
+        Definition::FloatConst(c) => Value::Float(*c),
+        Definition::Param(p) => Value::Int(params[p]),
+        Definition::Cast(ty, d) => cast(recurse(d), *ty),
+        Definition::SaturatingCast(ty, d) => saturating_cast(recurse(d), *ty),
+        Definition::Clamp(v, lo, hi) => {
+            let ty = definition.value_type();
+            let (v, lo, hi) = (recurse(v).convert(ty), recurse(lo).convert(ty), recurse(hi).convert(ty));
+            if compare(Comparison::LT, v, lo) {
+                lo
+            } else if compare(Comparison::GT, v, hi) {
+                hi
+            } else {
+                v
+            }
+        },
+        Definition::Cond(c) => {
+            let branch = if compare(c.cmp, recurse(&c.lhs), recurse(&c.rhs)) { &c.if_true } else { &c.if_false };
+            recurse(branch).convert(definition.value_type())
+        },
+        Definition::Add(l, r) => arithmetic(recurse(l), recurse(r), i32::wrapping_add, |l, r| l + r),
+        Definition::Mul(l, r) => arithmetic(recurse(l), recurse(r), i32::wrapping_mul, |l, r| l * r),
+        Definition::Sub(l, r) => arithmetic(recurse(l), recurse(r), i32::wrapping_sub, |l, r| l - r),
+        Definition::Div(l, r) => {
+            arithmetic(recurse(l), recurse(r), |l, r| if r == 0 { 0 } else { l.wrapping_div(r) }, |l, r| l / r)
+        },
+        Definition::Abs(d) => match recurse(d) {
+            Value::Int(i) => Value::Int(i.wrapping_abs()),
+            Value::Float(f) => Value::Float(f.abs())
+        },
+        Definition::Binary(op, l, r) => binary_op(*op, recurse(l), recurse(r)),
+        Definition::Select(c, t, f) => {
+            // Both values are evaluated, as in the generated code
+            let (t, f) = (recurse(t), recurse(f));
+            let value = if evaluate_bool_expr(c, &recurse) { t } else { f };
+            value.convert(definition.value_type())
+        }
+    }
+}
+
+/// Returns the value of `expr`. `evaluate` returns the value of a definition.
+fn evaluate_bool_expr(expr: &BoolExpr, evaluate: &impl Fn(&Definition) -> Value) -> bool {
+    match expr {
+        BoolExpr::Compare(cmp, l, r) => compare(*cmp, evaluate(l), evaluate(r)),
+        BoolExpr::And(l, r) => evaluate_bool_expr(l, evaluate) & evaluate_bool_expr(r, evaluate),
+        BoolExpr::Or(l, r) => evaluate_bool_expr(l, evaluate) | evaluate_bool_expr(r, evaluate),
+        BoolExpr::Not(e) => !evaluate_bool_expr(e, evaluate)
+    }
+}
+
+/// Evaluates each func in `graph` over the region required to compute its outputs
+/// over the dimensions of the graph's first input, as `Processor::process` does.
+/// Returns an error if the graph has no inputs.
+pub fn evaluate_graph(
+    graph: &Graph,
+    inputs: &[(&Source, &DynamicImage)],
+    params: &HashMap<Param, i32>
+) -> Result<Evaluation, PrismError> {
+    let mut images = HashMap::new();
+    let mut regions = HashMap::new();
+    let mut domains = HashMap::new();
+    for source in graph.inputs() {
+        let image = match inputs.iter().find(|i| &i.0.name == source) {
+            Some(input) => input.1,
+            None => return Err(PrismError::MissingSource(source.clone()))
+        };
+        let expected = graph.element_type(source).unwrap();
+        if image.element_type() != expected {
+            return Err(PrismError::TypeMismatch {
+                name: source.clone(),
+                expected,
+                actual: image.element_type()
+            });
+        }
+        let (width, height) = image.dimensions();
+        let region = Region::new(Interval::new(0, width as i32 - 1), Interval::new(0, height as i32 - 1));
+        images.insert(source.clone(), image.clone());
+        regions.insert(source.clone(), region);
+        domains.insert(source.clone(), region);
+    }
+    let mut param_values = HashMap::new();
+    for name in graph.params() {
+        match params.iter().find(|p| &p.0.name == name) {
+            Some(p) => param_values.insert(name.clone(), *p.1),
+            None => return Err(PrismError::MissingParam(name.clone()))
+        };
+    }
+
+    // As in the generated code, outputs are computed over the region covered by
+    // the graph's first input
+    let (w, h) = match graph.inputs().first() {
+        Some(input) => images[input].dimensions(),
+        None => return Err(PrismError::NoInputs)
+    };
+    let output_region = Region::new(Interval::new(0, w as i32 - 1), Interval::new(0, h as i32 - 1));
+    let required = required_regions(&Concrete, graph, output_region);
+    let mut evaluation = Evaluation { regions, images, domains, outputs: graph.outputs().to_vec(), output_region };
+
+    for func in graph.funcs() {
+        evaluation.domains.insert(func.name.clone(), output_region);
+        let region = match required.get(&func.name) {
+            Some(region) => *region,
+            None => continue
+        };
+        let width = std::cmp::max(region.x.max - region.x.min + 1, 0) as usize;
+        let height = std::cmp::max(region.y.max - region.y.min + 1, 0) as usize;
+        let mut image = DynamicImage::new(func.ty, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (fx, fy) = (region.x.min + x as i32, region.y.min + y as i32);
+                let read = |a: &Access, x, y| evaluation.read(a, x, y);
+                let value = evaluate_definition(&func.definition, fx, fy, &param_values, &read);
+                let value = match func.store_policy {
+                    StorePolicy::Wrap => value,
+                    StorePolicy::Saturate => saturating_cast(value, func.ty)
+                };
+                store(&mut image, x, y, value);
+            }
+        }
+        evaluation.images.insert(func.name.clone(), image);
+        evaluation.regions.insert(func.name.clone(), region);
+    }
+    Ok(evaluation)
+}
+
+/// Evaluates `graph` and returns the values of its outputs. Results match
+/// those returned by `Processor::try_process`.
+pub fn interpret(
+    graph: &Graph,
+    inputs: &[(&Source, &GrayImage)],
+    params: &HashMap<Param, i32>
+) -> Result<HashMap<String, GrayImage>, PrismError> {
+    let inputs: Vec<(&Source, DynamicImage)> = inputs
+        .iter()
+        .map(|(s, i)| (*s, DynamicImage::from((*i).clone())))
+        .collect();
+    let inputs: Vec<(&Source, &DynamicImage)> = inputs.iter().map(|(s, i)| (*s, i)).collect();
+    let mut results = HashMap::new();
+    for (name, image) in interpret_dynamic(graph, &inputs, params)? {
+        let actual = image.element_type();
+        match image.into_buffer() {
+            Some(image) => results.insert(name, image),
+            None => return Err(PrismError::TypeMismatch { name, expected: Type::U8, actual })
+        };
+    }
+    Ok(results)
+}
+
+/// Evaluates `graph` on inputs of any element type and returns the values of its
+/// outputs. Results match those returned by `Processor::try_process_dynamic`.
+pub fn interpret_dynamic(
+    graph: &Graph,
+    inputs: &[(&Source, &DynamicImage)],
+    params: &HashMap<Param, i32>
+) -> Result<HashMap<String, DynamicImage>, PrismError> {
+    Ok(evaluate_graph(graph, inputs, params)?.outputs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpret() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        param!(p);
+        func!(f = input.at(x - 1, y) + &p);
+        func!(g = f.at_with_boundary(x, y + 1, Boundary::Clamp) * 2);
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        sched.add_func(&g, FuncSchedule::by_row());
+        let graph = Graph::new("graph", vec![f, g], sched).unwrap();
+
+        let mut image = GrayImage::new(3, 2);
+        for y in 0..2 {
+            for x in 0..3 {
+                image.set(x, y, (10 * y + x + 1) as u8);
+            }
+        }
+        let mut params = HashMap::new();
+        params.insert(p, 100);
+        let image = DynamicImage::from(image);
+        let evaluation = evaluate_graph(&graph, &[(&input, &image)], &params).unwrap();
+        // f(0, 1) reads input(-1, 1), which is zero
+        assert_eq!(evaluation.value("f", 0, 1), Some(Value::Int(100)));
+        // 2 * (input(1, 1) + 100) is truncated to u8
+        assert_eq!(evaluation.value("g", 2, 1), Some(Value::Int((2 * (12 + 100)) as u8 as i32)));
+        assert_eq!(evaluation.value("g", 3, 1), None);
+        assert_eq!(evaluation.outputs().keys().collect::<Vec<_>>(), vec!["g"]);
+    }
+
+    #[test]
+    fn test_output_size() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(a);
+        source!(b);
+        param!(p);
+        func!(f = a.at(x, y) + b.at(x, y));
+        func!(g = Definition::from(&p) * 2);
+        let mut sched = Schedule::new();
+        sched.add_source(&a, FuncSchedule::by_row());
+        sched.add_source(&b, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        sched.add_func(&g, FuncSchedule::by_row());
+        let graph = Graph::new("graph", vec![f], sched.clone()).unwrap();
+
+        // Outputs are the size of a, the first input alphabetically, in either order
+        let (small, large) = (DynamicImage::new(Type::U8, 3, 2), DynamicImage::new(Type::U8, 5, 4));
+        for inputs in &[[(&a, &small), (&b, &large)], [(&b, &large), (&a, &small)]] {
+            let outputs = interpret_dynamic(&graph, inputs, &HashMap::new()).unwrap();
+            assert_eq!(outputs["f"].dimensions(), (3, 2));
+        }
+
+        let graph = Graph::new("graph", vec![g], sched).unwrap();
+        let params: HashMap<Param, i32> = vec![(p, 3)].into_iter().collect();
+        assert_eq!(interpret_dynamic(&graph, &[], &params).err(), Some(PrismError::NoInputs));
+    }
+}
@@ -15,5 +15,9 @@ pub mod codegen;
 #[macro_use]
 pub mod syntax;
 pub mod blur3;
+pub mod error;
+#[cfg(test)]
+mod fuzz;
+pub mod interpreter;
 pub mod llvm;
 pub mod tracing;
@@ -53,6 +53,26 @@ macro_rules! impl_llvm_binary_op {
     }
 }
 
+macro_rules! impl_llvm_cast {
+    ($name:ident, $func:expr) => {
+        pub fn $name(&self, val: LLVMValueRef, dest_ty: LLVMTypeRef) -> LLVMValueRef {
+            unsafe {
+                $func(self.builder, val, dest_ty, noname())
+            }
+        }
+    }
+}
+
+macro_rules! impl_fcmp {
+    ($name:ident, $op:ident) => {
+        pub fn $name(&self, lhs: LLVMValueRef, rhs: LLVMValueRef) -> LLVMValueRef {
+            unsafe {
+                LLVMBuildFCmp(self.builder, LLVMRealPredicate::$op, lhs, rhs, noname())
+            }
+        }
+    };
+}
+
 macro_rules! impl_icmp {
     ($name:ident, $op:ident) => {
         pub fn $name(&self, lhs: LLVMValueRef, rhs: LLVMValueRef) -> LLVMValueRef {
@@ -74,19 +94,32 @@ impl Builder {
     }
 
     impl_llvm_type_getter!(type_void, LLVMVoidTypeInContext);
+    impl_llvm_type_getter!(type_i1, LLVMInt1TypeInContext);
     impl_llvm_type_getter!(type_i8, LLVMInt8TypeInContext);
     impl_llvm_type_getter!(type_i16, LLVMInt16TypeInContext);
     impl_llvm_type_getter!(type_i32, LLVMInt32TypeInContext);
     impl_llvm_type_getter!(type_i64, LLVMInt64TypeInContext);
+    impl_llvm_type_getter!(type_f32, LLVMFloatTypeInContext);
 
     impl_llvm_binary_op!(add, LLVMBuildAdd);
     impl_llvm_binary_op!(add_nsw, LLVMBuildNSWAdd);
     impl_llvm_binary_op!(mul, LLVMBuildMul);
     impl_llvm_binary_op!(sub, LLVMBuildSub);
     impl_llvm_binary_op!(sdiv, LLVMBuildSDiv);
+    impl_llvm_binary_op!(srem, LLVMBuildSRem);
     impl_llvm_binary_op!(and, LLVMBuildAnd);
     impl_llvm_binary_op!(or, LLVMBuildOr);
     impl_llvm_binary_op!(xor, LLVMBuildXor);
+    impl_llvm_binary_op!(shl, LLVMBuildShl);
+    impl_llvm_binary_op!(ashr, LLVMBuildAShr);
+    impl_llvm_binary_op!(fadd, LLVMBuildFAdd);
+    impl_llvm_binary_op!(fsub, LLVMBuildFSub);
+    impl_llvm_binary_op!(fmul, LLVMBuildFMul);
+    impl_llvm_binary_op!(fdiv, LLVMBuildFDiv);
+    impl_llvm_binary_op!(frem, LLVMBuildFRem);
+
+    impl_llvm_cast!(sitofp, LLVMBuildSIToFP);
+    impl_llvm_cast!(fptosi, LLVMBuildFPToSI);
 
     impl_icmp!(icmp_eq, LLVMIntEQ);
     impl_icmp!(icmp_ne, LLVMIntNE);
@@ -99,6 +132,13 @@ impl Builder {
     impl_icmp!(icmp_slt, LLVMIntSLT);
     impl_icmp!(icmp_sle, LLVMIntSLE);
 
+    // Ordered comparisons, which are false if either operand is NaN
+    impl_fcmp!(fcmp_oeq, LLVMRealOEQ);
+    impl_fcmp!(fcmp_ogt, LLVMRealOGT);
+    impl_fcmp!(fcmp_oge, LLVMRealOGE);
+    impl_fcmp!(fcmp_olt, LLVMRealOLT);
+    impl_fcmp!(fcmp_ole, LLVMRealOLE);
+
     pub fn const_i32(&self, value: i32) -> LLVMValueRef {
         unsafe {
             const SIGN_EXTEND: LLVMBool = 0;
@@ -120,6 +160,10 @@ impl Builder {
         }
     }
 
+    pub fn const_f32(&self, value: f32) -> LLVMValueRef {
+        unsafe { LLVMConstReal(self.type_f32(), value as f64) }
+    }
+
     pub fn const_string(&self, value: &str) -> LLVMValueRef {
         unsafe {
             let value = CString::new(value).unwrap();
@@ -144,6 +188,93 @@ impl Builder {
         }
     }
 
+    pub fn type_vector(&self, element_type: LLVMTypeRef, width: u32) -> LLVMTypeRef {
+        unsafe { LLVMVectorType(element_type, width) }
+    }
+
+    pub fn const_vector(&self, values: &mut [LLVMValueRef]) -> LLVMValueRef {
+        unsafe { LLVMConstVector(values.as_mut_ptr(), values.len() as u32) }
+    }
+
+    pub fn undef(&self, ty: LLVMTypeRef) -> LLVMValueRef {
+        unsafe { LLVMGetUndef(ty) }
+    }
+
+    pub fn type_struct(&self, element_types: &mut [LLVMTypeRef]) -> LLVMTypeRef {
+        unsafe {
+            const IS_PACKED: LLVMBool = 0;
+            LLVMStructTypeInContext(
+                self.context,
+                element_types.as_mut_ptr(),
+                element_types.len() as u32,
+                IS_PACKED
+            )
+        }
+    }
+
+    pub fn type_of(&self, value: LLVMValueRef) -> LLVMTypeRef {
+        unsafe { LLVMTypeOf(value) }
+    }
+
+    /// Returns `element_type` if `value` is a scalar, or a vector of `element_type`
+    /// with the same width as `value` if it is a vector.
+    pub fn scalar_or_vector_type(&self, value: LLVMValueRef, element_type: LLVMTypeRef) -> LLVMTypeRef {
+        unsafe {
+            let ty = LLVMTypeOf(value);
+            match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMVectorTypeKind => self.type_vector(element_type, LLVMGetVectorSize(ty)),
+                _ => element_type
+            }
+        }
+    }
+
+    /// Returns `scalar`, or a vector with every lane equal to `scalar` if `value` is a vector.
+    pub fn splat_like(&self, value: LLVMValueRef, scalar: LLVMValueRef) -> LLVMValueRef {
+        unsafe {
+            let ty = LLVMTypeOf(value);
+            match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMVectorTypeKind => self.splat(scalar, LLVMGetVectorSize(ty)),
+                _ => scalar
+            }
+        }
+    }
+
+    /// The suffix used in the names of overloaded intrinsics for the given
+    /// integer, float or vector type, e.g. "i32" or "v8f32".
+    pub fn intrinsic_type_suffix(&self, ty: LLVMTypeRef) -> String {
+        unsafe {
+            match LLVMGetTypeKind(ty) {
+                LLVMTypeKind::LLVMVectorTypeKind => {
+                    format!("v{}{}", LLVMGetVectorSize(ty), self.intrinsic_type_suffix(LLVMGetElementType(ty)))
+                },
+                LLVMTypeKind::LLVMIntegerTypeKind => format!("i{}", LLVMGetIntTypeWidth(ty)),
+                LLVMTypeKind::LLVMFloatTypeKind => String::from("f32"),
+                kind => panic!("No intrinsic suffix for type kind {:?}", kind)
+            }
+        }
+    }
+
+    /// Calls the LLVM intrinsic with the given name, which must include the suffixes
+    /// for any overloaded types, declaring it in the current module if necessary.
+    pub fn call_intrinsic(
+        &self,
+        name: &str,
+        ret: LLVMTypeRef,
+        args: &mut [LLVMValueRef]
+    ) -> LLVMValueRef {
+        unsafe {
+            let func = LLVMGetBasicBlockParent(self.get_insert_block());
+            let module = LLVMGetGlobalParent(func);
+            let c_name = CString::new(name).unwrap();
+            let mut intrinsic = LLVMGetNamedFunction(module, c_name.as_ptr());
+            if intrinsic.is_null() {
+                let mut arg_types: Vec<LLVMTypeRef> = args.iter().map(|a| LLVMTypeOf(*a)).collect();
+                intrinsic = LLVMAddFunction(module, c_name.as_ptr(), self.func_type(ret, &mut arg_types));
+            }
+            self.build_function_call(intrinsic, args)
+        }
+    }
+
     pub fn type_i8_ptr(&self) -> LLVMTypeRef {
         unsafe { LLVMPointerType(self.type_i8(), 0) }
     }
@@ -171,6 +302,22 @@ impl Builder {
         }
     }
 
+    /// Adds a function to the module containing `func`. The new function has
+    /// internal linkage, so is not visible outside this module.
+    pub fn add_sibling_func(
+        &self,
+        func: LLVMValueRef,
+        name: &str,
+        func_type: LLVMTypeRef
+    ) -> LLVMValueRef {
+        unsafe {
+            let name = CString::new(name).unwrap();
+            let sibling = LLVMAddFunction(LLVMGetGlobalParent(func), name.as_ptr(), func_type);
+            LLVMSetLinkage(sibling, LLVMLinkage::LLVMInternalLinkage);
+            sibling
+        }
+    }
+
     pub fn new_block(
         &self,
         function: LLVMValueRef,
@@ -251,6 +398,25 @@ impl Builder {
         self.named_alloca(ty, "", align)
     }
 
+    /// Adds an alloca to the start of the entry block of `func`, so that it is
+    /// executed only once however deeply the current insertion point is nested
+    /// in loops. The builder remains positioned at the end of the current block.
+    pub fn entry_alloca(&self, func: LLVMValueRef, ty: LLVMTypeRef, align: u32) -> LLVMValueRef {
+        unsafe {
+            let current = self.get_insert_block();
+            let entry = LLVMGetEntryBasicBlock(func);
+            let first = LLVMGetFirstInstruction(entry);
+            if first.is_null() {
+                LLVMPositionBuilderAtEnd(self.builder, entry);
+            } else {
+                LLVMPositionBuilderBefore(self.builder, first);
+            }
+            let a = self.alloca(ty, align);
+            self.position_at_end(current);
+            a
+        }
+    }
+
     pub fn named_alloca(&self, ty: LLVMTypeRef, name: &str, align: u32) -> LLVMValueRef {
         unsafe {
             let name = CString::new(name).unwrap();
@@ -277,12 +443,75 @@ impl Builder {
         }
     }
 
+    pub fn not(&self, value: LLVMValueRef) -> LLVMValueRef {
+        unsafe { LLVMBuildNot(self.builder, value, noname()) }
+    }
+
     pub fn trunc(&self, value: LLVMValueRef, ty: LLVMTypeRef) -> LLVMValueRef {
         unsafe {
             LLVMBuildTrunc(self.builder, value, ty, noname())
         }
     }
 
+    pub fn select(
+        &self,
+        cond: LLVMValueRef,
+        then_value: LLVMValueRef,
+        else_value: LLVMValueRef
+    ) -> LLVMValueRef {
+        unsafe {
+            LLVMBuildSelect(self.builder, cond, then_value, else_value, noname())
+        }
+    }
+
+    pub fn bitcast(&self, value: LLVMValueRef, ty: LLVMTypeRef) -> LLVMValueRef {
+        unsafe {
+            LLVMBuildBitCast(self.builder, value, ty, noname())
+        }
+    }
+
+    pub fn insert_element(
+        &self,
+        vector: LLVMValueRef,
+        element: LLVMValueRef,
+        index: LLVMValueRef
+    ) -> LLVMValueRef {
+        unsafe {
+            LLVMBuildInsertElement(self.builder, vector, element, index, noname())
+        }
+    }
+
+    pub fn extract_element(&self, vector: LLVMValueRef, index: LLVMValueRef) -> LLVMValueRef {
+        unsafe {
+            LLVMBuildExtractElement(self.builder, vector, index, noname())
+        }
+    }
+
+    pub fn shuffle_vector(
+        &self,
+        v1: LLVMValueRef,
+        v2: LLVMValueRef,
+        mask: LLVMValueRef
+    ) -> LLVMValueRef {
+        unsafe {
+            LLVMBuildShuffleVector(self.builder, v1, v2, mask, noname())
+        }
+    }
+
+    /// Returns a vector of the given width with every element equal to `value`.
+    pub fn splat(&self, value: LLVMValueRef, width: u32) -> LLVMValueRef {
+        unsafe {
+            let vector_type = self.type_vector(LLVMTypeOf(value), width);
+            let vector = self.insert_element(self.undef(vector_type), value, self.const_i32(0));
+            let mask = self.const_null(self.type_vector(self.type_i32(), width));
+            self.shuffle_vector(vector, self.undef(vector_type), mask)
+        }
+    }
+
+    pub fn struct_gep(&self, ptr: LLVMValueRef, index: u32) -> LLVMValueRef {
+        unsafe { LLVMBuildStructGEP(self.builder, ptr, index, noname()) }
+    }
+
     pub fn in_bounds_gep(&self, ptr: LLVMValueRef, offset: LLVMValueRef) -> LLVMValueRef {
         unsafe {
             let mut indices = [offset];
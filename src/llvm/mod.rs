@@ -4,18 +4,28 @@ pub use self::builder::*;
 pub use self::context::*;
 pub use self::execution_engine::*;
 pub use self::module::*;
+pub use self::target_machine::*;
 
-use std::mem::MaybeUninit;
+use std::ptr;
+use llvm_sys::prelude::*;
 
 mod builder;
 mod context;
 mod execution_engine;
 mod module;
+mod target_machine;
 
 /// Do the global setup necessary to create execution engines which compile to native code
 pub fn initialise_llvm_jit() {
     unsafe {
         llvm_sys::execution_engine::LLVMLinkInMCJIT();
+    }
+    initialise_llvm_native_target();
+}
+
+/// Do the global setup necessary to create target machines which compile to native code
+pub fn initialise_llvm_native_target() {
+    unsafe {
         if llvm_sys::target::LLVM_InitializeNativeTarget() != 0 {
             panic!("Failed to initialise native target");
         }
@@ -27,27 +37,28 @@ pub fn initialise_llvm_jit() {
 
 /// Parse a string containing a textual representation of an IR module into an in-memory module.
 pub fn create_module_from_ir_string<'c, 'i>(context: &'c Context, ir: &'i str) -> Module<'c> {
-    use std::{ffi::CString, mem};
+    unsafe { context.wrap_llvm_module(parse_ir(context.context, ir)) }
+}
+
+/// Parses `ir` into a new module in `context`. Panics if parsing fails.
+pub(crate) unsafe fn parse_ir(context: LLVMContextRef, ir: &str) -> LLVMModuleRef {
     use llvm_sys::{
         core::LLVMCreateMemoryBufferWithMemoryRangeCopy,
         ir_reader::LLVMParseIRInContext
     };
 
-    unsafe {
-        let ir_buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(
-            ir.as_ptr() as *const _, ir.as_bytes().len(), std::ptr::null());
+    let ir_buffer = LLVMCreateMemoryBufferWithMemoryRangeCopy(
+        ir.as_ptr() as *const _, ir.as_bytes().len(), ptr::null());
 
-        let module = MaybeUninit::uninit().as_mut_ptr();
-        let mut message = mem::zeroed();
-        let res = LLVMParseIRInContext(context.context, ir_buffer, module, &mut message);
+    let mut module = ptr::null_mut();
+    let mut message = ptr::null_mut();
+    let res = LLVMParseIRInContext(context, ir_buffer, &mut module, &mut message);
 
-        if res != 0 {
-            let message_str = CString::from_raw(message);
-            panic!("IR parsing failed: {:?}", message_str);
-        }
-
-        context.wrap_llvm_module(*module)
+    if res != 0 {
+        panic!("IR parsing failed: {}", target_machine::take_message(message));
     }
+
+    module
 }
 
 pub fn optimise(module: &mut Module<'_>) {
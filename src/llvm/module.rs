@@ -1,13 +1,13 @@
 //! A trivial wrapper type for an LLVM module
 
 use std::{
-    ffi::CStr,
+    ffi::{CStr, CString},
     fs::File,
     io::Write,
     marker::PhantomData,
     path::Path
 };
-use llvm_sys::{core::*, prelude::*};
+use llvm_sys::{core::*, linker::LLVMLinkModules2, prelude::*, LLVMLinkage};
 
 pub struct Module<'c> {
     pub module: LLVMModuleRef,
@@ -33,4 +33,53 @@ impl<'c> Module<'c> {
         let mut file = File::create(path)?;
         file.write_all(self.dump_to_string().as_bytes()).map(|_| ())
     }
+
+    /// Parses `ir` and links it into this module, so that any functions it
+    /// defines replace this module's declarations of the same name. Panics
+    /// if `ir` cannot be parsed or linked.
+    pub fn link_ir(&mut self, ir: &str) {
+        unsafe {
+            let other = crate::llvm::parse_ir(LLVMGetModuleContext(self.module), ir);
+            if LLVMLinkModules2(self.module, other) != 0 {
+                panic!("Failed to link IR into module");
+            }
+        }
+    }
+
+    /// Returns true if this module defines or declares a function with the given name.
+    pub fn contains_function(&self, name: &str) -> bool {
+        unsafe {
+            let name = CString::new(name).unwrap();
+            !LLVMGetNamedFunction(self.module, name.as_ptr()).is_null()
+        }
+    }
+
+    /// Gives the named function internal linkage, so that it is not visible
+    /// outside this module and may be removed once all calls to it are inlined.
+    pub fn internalise(&mut self, name: &str) {
+        unsafe {
+            let name = CString::new(name).unwrap();
+            let func = LLVMGetNamedFunction(self.module, name.as_ptr());
+            assert!(!func.is_null(), "No function named {:?}", name);
+            LLVMSetLinkage(func, LLVMLinkage::LLVMInternalLinkage);
+        }
+    }
+
+    /// Returns the names of the functions defined in this module that are visible to the linker.
+    pub fn exported_functions(&self) -> Vec<String> {
+        let mut names = vec![];
+        unsafe {
+            let mut func = LLVMGetFirstFunction(self.module);
+            while !func.is_null() {
+                let linkage = LLVMGetLinkage(func);
+                let local = linkage == LLVMLinkage::LLVMInternalLinkage
+                    || linkage == LLVMLinkage::LLVMPrivateLinkage;
+                if LLVMIsDeclaration(func) == 0 && !local {
+                    names.push(CStr::from_ptr(LLVMGetValueName2(func, &mut 0)).to_string_lossy().to_string());
+                }
+                func = LLVMGetNextFunction(func);
+            }
+        }
+        names
+    }
 }
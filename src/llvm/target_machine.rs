@@ -0,0 +1,95 @@
+//! A trivial wrapper type for an LLVM target machine, used to compile modules to object code
+
+use std::{
+    ffi::CStr,
+    mem,
+    slice
+};
+use llvm_sys::{core::*, target::*, target_machine::*};
+use crate::llvm::module::Module;
+
+pub struct TargetMachine {
+    machine: LLVMTargetMachineRef
+}
+
+impl TargetMachine {
+    /// Creates a target machine for the host, using all features supported by
+    /// the host CPU. Generated code is position independent, so that it can be
+    /// linked into shared libraries and position independent executables.
+    pub fn native() -> Result<TargetMachine, String> {
+        unsafe {
+            let triple = LLVMGetDefaultTargetTriple();
+            let mut target = mem::zeroed();
+            let mut message = mem::zeroed();
+            if LLVMGetTargetFromTriple(triple, &mut target, &mut message) != 0 {
+                LLVMDisposeMessage(triple);
+                return Err(take_message(message));
+            }
+            let cpu = LLVMGetHostCPUName();
+            let features = LLVMGetHostCPUFeatures();
+            let machine = LLVMCreateTargetMachine(
+                target,
+                triple,
+                cpu,
+                features,
+                LLVMCodeGenOptLevel::LLVMCodeGenLevelAggressive,
+                LLVMRelocMode::LLVMRelocPIC,
+                LLVMCodeModel::LLVMCodeModelDefault
+            );
+            LLVMDisposeMessage(triple);
+            LLVMDisposeMessage(cpu);
+            LLVMDisposeMessage(features);
+            Ok(TargetMachine { machine })
+        }
+    }
+
+    /// Sets the target triple and data layout of `module` to match this machine.
+    pub fn configure(&self, module: &mut Module<'_>) {
+        unsafe {
+            let triple = LLVMGetTargetMachineTriple(self.machine);
+            LLVMSetTarget(module.module, triple);
+            LLVMDisposeMessage(triple);
+            let layout = LLVMCreateTargetDataLayout(self.machine);
+            LLVMSetModuleDataLayout(module.module, layout);
+            LLVMDisposeTargetData(layout);
+        }
+    }
+
+    /// Compiles `module` and returns the contents of the resulting object file.
+    pub fn emit_object(&self, module: &Module<'_>) -> Result<Vec<u8>, String> {
+        unsafe {
+            let mut buffer = mem::zeroed();
+            let mut message = mem::zeroed();
+            let failed = LLVMTargetMachineEmitToMemoryBuffer(
+                self.machine,
+                module.module,
+                LLVMCodeGenFileType::LLVMObjectFile,
+                &mut message,
+                &mut buffer
+            );
+            if failed != 0 {
+                return Err(take_message(message));
+            }
+            let start = LLVMGetBufferStart(buffer) as *const u8;
+            let object = slice::from_raw_parts(start, LLVMGetBufferSize(buffer)).to_vec();
+            LLVMDisposeMemoryBuffer(buffer);
+            Ok(object)
+        }
+    }
+}
+
+impl Drop for TargetMachine {
+    fn drop(&mut self) {
+        unsafe {
+            LLVMDisposeTargetMachine(self.machine);
+        }
+    }
+}
+
+/// Copies and frees an error message returned by LLVM.
+pub(super) unsafe fn take_message(message: *mut ::libc::c_char) -> String {
+    let result = CStr::from_ptr(message).to_string_lossy().to_string();
+    LLVMDisposeMessage(message);
+    result
+}
+
@@ -1,9 +1,6 @@
 
-use std::{
-    collections::HashMap,
-    fmt
-};
-use crate::syntax::pretty_print::*;
+use std::fmt;
+use crate::syntax::{bounds::*, pretty_print::*};
 
 // [NOTE: AST terminology]
 //
@@ -22,56 +19,6 @@ use crate::syntax::pretty_print::*;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Var { X, Y }
 
-pub struct Schedule {
-    /// Schedules indexed by function name.
-    pub(crate) func_schedules: HashMap<String, FuncSchedule>
-}
-
-impl Schedule {
-    pub fn new() -> Schedule {
-        Schedule { func_schedules: HashMap::new() }
-    }
-
-    pub fn add_func(&mut self, func: &Func, sched: FuncSchedule) {
-        self.func_schedules.insert(func.name.to_string(), sched);
-    }
-
-    pub fn add_source(&mut self, func: &Source, sched: FuncSchedule) {
-        self.func_schedules.insert(func.name.to_string(), sched);
-    }
-
-    pub fn get_func_schedule(&self, func: &Func) -> &FuncSchedule {
-        self.func_schedules.get(&func.name).unwrap()
-    }
-
-    pub fn get_source_schedule(&self, func: &Source) -> &FuncSchedule {
-        self.func_schedules.get(&func.name).unwrap()
-    }
-}
-
-// TODO: implement real schedules. 
-// Need iteration order, compute location and storage
-// location for each func. Compute location determins how a function's
-// loops nest inside those of its callers, storage location determines
-// the point in the loop nest where its storage is allocated, and iteration
-// order defines the nesting order of its loops
-pub struct FuncSchedule {
-    // TODO support more than just X and Y!
-    pub(crate) variables: Vec<Var>
-}
-
-impl FuncSchedule {
-    /// By default the y variable is iterated in the outer loop
-    pub fn by_row() -> FuncSchedule {
-        FuncSchedule { variables: vec![Var::Y, Var::X] }
-    }
-
-    /// Iterates over the x variable in the outer loop
-    pub fn by_column() -> FuncSchedule {
-        FuncSchedule { variables: vec![Var::X, Var::Y] }
-    }
-}
-
 impl fmt::Display for Var {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -92,54 +39,24 @@ pub enum VarExpr {
     Mul(Box<VarExpr>, Box<VarExpr>)
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-enum Extent { Width, Height }
-
-fn extent(e: &VarExpr, ex: Extent, w: i32, h: i32) -> (i32, i32) {
+/// Returns the range of values taken by `e` as x and y vary over [0, w) x [0, h).
+fn extent(e: &VarExpr, w: i32, h: i32) -> Interval<i32> {
     assert!(w >= 0, "width must be non-negative");
     assert!(h >= 0, "height must be non-negative");
-    let (d1, d2) = match ex {
-        Extent::Width => ((0, w - 1), (0, h - 1)),
-        Extent::Height => ((0, h - 1), (0, w - 1))
-    };
-    match e {
-        VarExpr::Var(v) => {
-            match v {
-                Var::X => d1,
-                Var::Y => d2
-            }
-        },
-        VarExpr::Const(c) => (*c, *c),
-        VarExpr::Add(l, r) => {
-            let l = extent(&*l, ex, w, h);
-            let r = extent(&*r, ex, w, h);
-            (l.0 + r.0, l.1 + r.1)
-        },
-        VarExpr::Sub(l, r) => {
-            let l = extent(&*l, ex, w, h);
-            let r = extent(&*r, ex, w, h);
-            (l.0 - r.1, l.1 - r.0)
-        },
-        VarExpr::Mul(l, r) => {
-            let l = extent(&*l, ex, w, h);
-            let r = extent(&*r, ex, w, h);
-            let (a, b, c, d) = (l.0 * r.0, l.0 * r.1, l.1 * r.0, l.1 * r.1);
-            (
-                std::cmp::min(std::cmp::min(a, b), std::cmp::min(c, d)),
-                std::cmp::max(std::cmp::max(a, b), std::cmp::max(c, d))
-            )
-        }
-    }
+    let region = Region::new(Interval::new(0, w - 1), Interval::new(0, h - 1));
+    interval(&Concrete, e, &region)
 }
 
 /// Returns [left, right]
 pub fn x_extent(e: &VarExpr, w: i32, h: i32) -> (i32, i32) {
-    extent(e, Extent::Width, w, h)
+    let i = extent(e, w, h);
+    (i.min, i.max)
 }
 
 /// Returns [top, bottom]
 pub fn y_extent(e: &VarExpr, w: i32, h: i32) -> (i32, i32) {
-    extent(e, Extent::Height, w, h)
+    let i = extent(e, w, h);
+    (i.min, i.max)
 }
 
 impl VarExpr {
@@ -157,6 +74,19 @@ impl VarExpr {
             VarExpr::Mul(l, r) => l.evaluate(x, y) * r.evaluate(x, y)
         }
     }
+
+    /// Replaces each occurrence of `Var::X` by `x` and of `Var::Y` by `y`.
+    pub fn substitute(&self, x: &VarExpr, y: &VarExpr) -> VarExpr {
+        let recurse = |e: &VarExpr| Box::new(e.substitute(x, y));
+        match self {
+            VarExpr::Var(Var::X) => x.clone(),
+            VarExpr::Var(Var::Y) => y.clone(),
+            VarExpr::Const(c) => VarExpr::Const(*c),
+            VarExpr::Add(l, r) => VarExpr::Add(recurse(l), recurse(r)),
+            VarExpr::Sub(l, r) => VarExpr::Sub(recurse(l), recurse(r)),
+            VarExpr::Mul(l, r) => VarExpr::Mul(recurse(l), recurse(r))
+        }
+    }
 }
 
 impl PrettyPrint for VarExpr {
@@ -178,6 +108,76 @@ impl PrettyPrint for VarExpr {
     }
 }
 
+/// The type of the elements of a func or source. Intermediate calculations are
+/// performed on i32s or f32s: values are converted to f32 when read from an f32
+/// buffer and to i32 when read from any other, and converted to the element type
+/// of a func's buffer when stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type { U8, U16, I16, I32, F32 }
+
+impl Type {
+    /// The size of a single element, in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Type::U8 => 1,
+            Type::U16 | Type::I16 => 2,
+            Type::I32 | Type::F32 => 4
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        *self == Type::F32
+    }
+
+    pub fn is_signed(&self) -> bool {
+        match self {
+            Type::U8 | Type::U16 => false,
+            Type::I16 | Type::I32 | Type::F32 => true
+        }
+    }
+
+    /// The type used for calculations on values read from a buffer with this
+    /// element type. This is `Type::F32` for floats and `Type::I32` otherwise.
+    pub fn value_type(&self) -> Type {
+        if self.is_float() { Type::F32 } else { Type::I32 }
+    }
+
+    /// The smallest and largest values of an integer type, or `None` for f32.
+    pub fn int_range(&self) -> Option<(i32, i32)> {
+        match self {
+            Type::U8 => Some((0, u8::MAX as i32)),
+            Type::U16 => Some((0, u16::MAX as i32)),
+            Type::I16 => Some((i16::MIN as i32, i16::MAX as i32)),
+            Type::I32 => Some((i32::MIN, i32::MAX)),
+            Type::F32 => None
+        }
+    }
+}
+
+/// How values are converted to the element type of a func's buffer when they are
+/// stored. Conversions to f32 are the same for both policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorePolicy {
+    /// Values are converted as by `Definition::Cast`, so integers wrap around.
+    Wrap,
+    /// Values are converted as by `Definition::SaturatingCast`, so are clamped
+    /// to the range of the element type.
+    Saturate
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Type::U8 => "u8",
+            Type::U16 => "u16",
+            Type::I16 => "i16",
+            Type::I32 => "i32",
+            Type::F32 => "f32"
+        };
+        write!(f, "{}", name)
+    }
+}
+
 /// A runtime parameter to a function of type i32.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Param {
@@ -190,6 +190,24 @@ impl Param {
     }
 }
 
+/// Determines the values read from outside the bounds of an image.
+/// Sources are bounded by the size of their input image. Funcs are
+/// bounded by the size of the pipeline's output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    /// Reads outside the image return zero. This is the default for sources.
+    Zero,
+    /// Reads outside the image return the given value.
+    Constant(i32),
+    /// Coordinates are clamped to the nearest edge of the image.
+    Clamp,
+    /// Coordinates are reflected about the edges of the image,
+    /// so that the edge pixels are repeated.
+    Mirror,
+    /// Coordinates wrap around to the opposite edge of the image.
+    Wrap
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Access {
     /// The stage from which we're reading
@@ -199,19 +217,59 @@ pub struct Access {
     pub(crate) x: VarExpr,
     /// The y-coordinate to read from, in terms of
     /// variables X and Y.
-    pub(crate) y: VarExpr
+    pub(crate) y: VarExpr,
+    /// How to handle reads outside the bounds of the source. If this
+    /// is `None` then reads from sources outside their bounds return zero,
+    /// and funcs are computed at whatever locations are read.
+    pub(crate) boundary: Option<Boundary>,
+    /// The element type of the source. Accesses to funcs are given
+    /// the type of the func when a `Graph` is created.
+    pub(crate) ty: Type
 }
 
 impl Access {
     pub fn new(source: &str, x: VarExpr, y: VarExpr) -> Access {
         let source = source.to_string();
-        Access { source, x, y }
+        Access { source, x, y, boundary: None, ty: Type::U8 }
+    }
+
+    pub fn with_boundary(source: &str, x: VarExpr, y: VarExpr, boundary: Boundary) -> Access {
+        let source = source.to_string();
+        Access { source, x, y, boundary: Some(boundary), ty: Type::U8 }
+    }
+
+    /// Sets the element type of the source.
+    pub fn with_type(mut self, ty: Type) -> Access {
+        self.ty = ty;
+        self
+    }
+}
+
+impl PrettyPrint for Boundary {
+    fn pretty_print(&self) -> String {
+        match self {
+            Boundary::Zero => String::from("zero"),
+            Boundary::Constant(c) => format!("constant({})", c),
+            Boundary::Clamp => String::from("clamp"),
+            Boundary::Mirror => String::from("mirror"),
+            Boundary::Wrap => String::from("wrap")
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        true
     }
 }
 
 impl PrettyPrint for Access {
+    /// Boundary conditions other than `Boundary::Zero`, the default
+    /// for sources, are printed as a third argument.
     fn pretty_print(&self) -> String {
-        format!("{}({}, {})", self.source, self.x.pretty_print(), self.y.pretty_print())
+        let (x, y) = (self.x.pretty_print(), self.y.pretty_print());
+        match self.boundary {
+            None | Some(Boundary::Zero) => format!("{}({}, {})", self.source, x, y),
+            Some(boundary) => format!("{}({}, {}, {})", self.source, x, y, boundary.pretty_print())
+        }
     }
 
     fn is_leaf(&self) -> bool {
@@ -250,6 +308,64 @@ impl PrettyPrint for Comparison {
     }
 }
 
+/// Binary operators other than the arithmetic operators `+`, `-`, `*` and `/`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BinaryOp {
+    /// The smaller operand. If one operand is NaN then the other is returned.
+    Min,
+    /// The larger operand. If one operand is NaN then the other is returned.
+    Max,
+    /// The absolute difference of the operands. For integers this is computed
+    /// as `max - min`, which wraps if the difference does not fit in an i32.
+    AbsDiff,
+    /// The remainder of dividing left by right, which has the sign of left. For
+    /// integers, the remainder of dividing by zero is zero. For floats it is NaN.
+    Rem,
+    /// Bitwise and. Operands are converted to i32.
+    BitAnd,
+    /// Bitwise or. Operands are converted to i32.
+    BitOr,
+    /// Bitwise exclusive or. Operands are converted to i32.
+    BitXor,
+    /// Shifts left by right bits, modulo 32. Operands are converted to i32.
+    Shl,
+    /// Arithmetic shift right by right bits, modulo 32. Operands are converted to i32.
+    Shr
+}
+
+impl BinaryOp {
+    /// Whether the operands of this operator are always converted to i32.
+    pub fn is_bitwise(&self) -> bool {
+        match self {
+            BinaryOp::Min | BinaryOp::Max | BinaryOp::AbsDiff | BinaryOp::Rem => false,
+            BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::Shl | BinaryOp::Shr => true
+        }
+    }
+
+    /// Whether this operator is written as a function call rather than an infix operator.
+    pub fn is_function(&self) -> bool {
+        match self {
+            BinaryOp::Min | BinaryOp::Max | BinaryOp::AbsDiff => true,
+            _ => false
+        }
+    }
+
+    /// The function name or infix symbol used to write this operator.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Min => "min",
+            BinaryOp::Max => "max",
+            BinaryOp::AbsDiff => "absd",
+            BinaryOp::Rem => "%",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>"
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Condition {
     pub cmp: Comparison,
@@ -277,20 +393,107 @@ impl Condition {
     }
 }
 
+/// A boolean expression, used to choose between values in a `Definition::Select`.
+/// Both operands of `And` and `Or` are always evaluated.
+#[derive(Debug, Clone)]
+pub enum BoolExpr {
+    /// Compares two values, converting them as described by `Definition::value_type`.
+    /// Comparisons involving NaN are false.
+    Compare(Comparison, Box<Definition>, Box<Definition>),
+    And(Box<BoolExpr>, Box<BoolExpr>),
+    Or(Box<BoolExpr>, Box<BoolExpr>),
+    Not(Box<BoolExpr>)
+}
+
+impl BoolExpr {
+    /// Returns the operands of all the comparisons in this expression.
+    pub(crate) fn definitions(&self) -> Vec<&Definition> {
+        match self {
+            BoolExpr::Compare(_, l, r) => vec![l, r],
+            BoolExpr::And(l, r) | BoolExpr::Or(l, r) => {
+                let mut definitions = l.definitions();
+                definitions.extend(r.definitions());
+                definitions
+            },
+            BoolExpr::Not(e) => e.definitions()
+        }
+    }
+
+    /// Returns mutable references to the operands of all the comparisons in this expression.
+    pub(crate) fn definitions_mut(&mut self) -> Vec<&mut Definition> {
+        match self {
+            BoolExpr::Compare(_, l, r) => vec![l, r],
+            BoolExpr::And(l, r) | BoolExpr::Or(l, r) => {
+                let mut definitions = l.definitions_mut();
+                definitions.extend(r.definitions_mut());
+                definitions
+            },
+            BoolExpr::Not(e) => e.definitions_mut()
+        }
+    }
+
+    /// Replaces the operand `d` of each comparison in this expression by `f(d)`.
+    pub(crate) fn map(&self, f: &dyn Fn(&Definition) -> Definition) -> BoolExpr {
+        match self {
+            BoolExpr::Compare(cmp, l, r) => BoolExpr::Compare(*cmp, Box::new(f(l)), Box::new(f(r))),
+            BoolExpr::And(l, r) => BoolExpr::And(Box::new(l.map(f)), Box::new(r.map(f))),
+            BoolExpr::Or(l, r) => BoolExpr::Or(Box::new(l.map(f)), Box::new(r.map(f))),
+            BoolExpr::Not(e) => BoolExpr::Not(Box::new(e.map(f)))
+        }
+    }
+}
+
+impl PrettyPrint for BoolExpr {
+    fn pretty_print(&self) -> String {
+        match self {
+            BoolExpr::Compare(cmp, l, r) => combine_with_op(&cmp.pretty_print(), l, r),
+            BoolExpr::And(l, r) => combine_with_op("&&", l, r),
+            BoolExpr::Or(l, r) => combine_with_op("||", l, r),
+            BoolExpr::Not(e) => format!("!{}", pretty_print_with_parens(e))
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self, BoolExpr::Not(_))
+    }
+}
+
 /// An expression defining the value to set an image pixel to
 #[derive(Debug, Clone)]
 pub enum Definition {
     Access(Access),
-    // All intermediate calculations happen at type i32 for now
     Const(i32),
+    FloatConst(f32),
     Param(String),
     Cond(Condition),
+    /// Converts a value to the given type and back to its value type, as if it
+    /// were stored to and read from a buffer of that type. Conversions from f32
+    /// to integer types round towards zero, clamp to the range of i32 and convert
+    /// NaN to zero, as `f as i32` does, before wrapping to the given type.
+    Cast(Type, Box<Definition>),
+    /// Converts a value to the given type and back to its value type, clamping
+    /// it to the range of that type. Conversions from f32 to integer types round
+    /// towards zero and convert NaN to zero.
+    SaturatingCast(Type, Box<Definition>),
+    /// Clamps a value to lie between a lower and an upper bound, as if by
+    /// `if v < lo {lo} else {if v > hi {hi} else {v}}`. The result is NaN if
+    /// the value is NaN.
+    Clamp(Box<Definition>, Box<Definition>, Box<Definition>),
     // TODO: share code for printing and lowering arithmetic expressions
     // TODO: between VarExpr and Definition
     Add(Box<Definition>, Box<Definition>),
     Mul(Box<Definition>, Box<Definition>),
     Sub(Box<Definition>, Box<Definition>),
-    Div(Box<Definition>, Box<Definition>)
+    /// Divides left by right. Integer division rounds towards zero, wraps for
+    /// `i32::MIN / -1` and gives zero for a divisor of zero.
+    Div(Box<Definition>, Box<Definition>),
+    /// The absolute value of a value. The absolute value of `i32::MIN` is `i32::MIN`.
+    Abs(Box<Definition>),
+    Binary(BinaryOp, Box<Definition>, Box<Definition>),
+    /// Chooses the second value if the condition holds and the third otherwise.
+    /// Unlike `Cond`, both values are always evaluated, so this is computed
+    /// without any branches.
+    Select(Box<BoolExpr>, Box<Definition>, Box<Definition>)
 }
 
 fn sources(definitions: &[&Box<Definition>]) -> Vec<String> {
@@ -313,36 +516,240 @@ impl Definition {
     pub(crate) fn sources(&self) -> Vec<String> {
         match self {
             Definition::Access(a) => vec![a.source.clone()],
-            Definition::Const(_) => vec![],
+            Definition::Const(_) | Definition::FloatConst(_) => vec![],
             Definition::Param(_) => vec![],
             Definition::Cond(c) => sources(&vec![&c.lhs, &c.rhs, &c.if_true, &c.if_false]),
+            Definition::Cast(_, d) | Definition::SaturatingCast(_, d) => d.sources(),
+            Definition::Clamp(v, lo, hi) => sources(&vec![v, lo, hi]),
             Definition::Add(l, r) => sources(&vec![l, r]),
             Definition::Mul(l, r) => sources(&vec![l, r]),
             Definition::Sub(l, r) => sources(&vec![l, r]),
             Definition::Div(l, r) => sources(&vec![l, r]),
+            Definition::Abs(d) => d.sources(),
+            Definition::Binary(_, l, r) => sources(&vec![l, r]),
+            Definition::Select(c, t, f) => {
+                let mut sources: Vec<String> = c.definitions().iter().flat_map(|d| d.sources()).collect();
+                sources.extend(t.sources());
+                sources.extend(f.sources());
+                sources
+            }
+        }
+    }
+
+    /// Returns all the accesses made by this definition.
+    pub(crate) fn accesses(&self) -> Vec<&Access> {
+        match self {
+            Definition::Access(a) => vec![a],
+            Definition::Const(_) | Definition::FloatConst(_) => vec![],
+            Definition::Param(_) => vec![],
+            Definition::Cast(_, d) | Definition::SaturatingCast(_, d) | Definition::Abs(d) => d.accesses(),
+            Definition::Clamp(v, lo, hi) => {
+                let mut accesses = v.accesses();
+                accesses.extend(lo.accesses());
+                accesses.extend(hi.accesses());
+                accesses
+            },
+            Definition::Select(c, t, f) => {
+                let mut accesses: Vec<&Access> = c.definitions().into_iter().flat_map(|d| d.accesses()).collect();
+                accesses.extend(t.accesses());
+                accesses.extend(f.accesses());
+                accesses
+            },
+            Definition::Cond(c) => {
+                let mut accesses = c.lhs.accesses();
+                accesses.extend(c.rhs.accesses());
+                accesses.extend(c.if_true.accesses());
+                accesses.extend(c.if_false.accesses());
+                accesses
+            },
+            Definition::Add(l, r)
+            | Definition::Mul(l, r)
+            | Definition::Sub(l, r)
+            | Definition::Div(l, r)
+            | Definition::Binary(_, l, r) => {
+                let mut accesses = l.accesses();
+                accesses.extend(r.accesses());
+                accesses
+            }
+        }
+    }
+
+    /// Returns mutable references to all the accesses made by this definition.
+    pub(crate) fn accesses_mut(&mut self) -> Vec<&mut Access> {
+        match self {
+            Definition::Access(a) => vec![a],
+            Definition::Const(_) | Definition::FloatConst(_) => vec![],
+            Definition::Param(_) => vec![],
+            Definition::Cast(_, d) | Definition::SaturatingCast(_, d) | Definition::Abs(d) => d.accesses_mut(),
+            Definition::Clamp(v, lo, hi) => {
+                let mut accesses = v.accesses_mut();
+                accesses.extend(lo.accesses_mut());
+                accesses.extend(hi.accesses_mut());
+                accesses
+            },
+            Definition::Select(c, t, f) => {
+                let mut accesses: Vec<&mut Access> = c.definitions_mut().into_iter().flat_map(|d| d.accesses_mut()).collect();
+                accesses.extend(t.accesses_mut());
+                accesses.extend(f.accesses_mut());
+                accesses
+            },
+            Definition::Cond(c) => {
+                let mut accesses = c.lhs.accesses_mut();
+                accesses.extend(c.rhs.accesses_mut());
+                accesses.extend(c.if_true.accesses_mut());
+                accesses.extend(c.if_false.accesses_mut());
+                accesses
+            },
+            Definition::Add(l, r)
+            | Definition::Mul(l, r)
+            | Definition::Sub(l, r)
+            | Definition::Div(l, r)
+            | Definition::Binary(_, l, r) => {
+                let mut accesses = l.accesses_mut();
+                accesses.extend(r.accesses_mut());
+                accesses
+            }
+        }
+    }
+
+    /// Replaces each access to `func` by the definition of `func`, with its
+    /// variables replaced by the coordinates of the access and its value
+    /// converted as it would be when stored to the buffer for `func`.
+    pub(crate) fn inline(&self, func: &Func) -> Definition {
+        let recurse = |d: &Definition| Box::new(d.inline(func));
+        match self {
+            Definition::Access(a) if a.source == func.name => {
+                func.store_conversion(func.definition.substitute(&a.x, &a.y))
+            },
+            Definition::Access(_)
+            | Definition::Const(_)
+            | Definition::FloatConst(_)
+            | Definition::Param(_) => self.clone(),
+            Definition::Cast(ty, d) => Definition::Cast(*ty, recurse(d)),
+            Definition::SaturatingCast(ty, d) => Definition::SaturatingCast(*ty, recurse(d)),
+            Definition::Clamp(v, lo, hi) => Definition::Clamp(recurse(v), recurse(lo), recurse(hi)),
+            Definition::Cond(c) => Definition::Cond(Condition {
+                cmp: c.cmp,
+                lhs: recurse(&c.lhs),
+                rhs: recurse(&c.rhs),
+                if_true: recurse(&c.if_true),
+                if_false: recurse(&c.if_false)
+            }),
+            Definition::Add(l, r) => Definition::Add(recurse(l), recurse(r)),
+            Definition::Mul(l, r) => Definition::Mul(recurse(l), recurse(r)),
+            Definition::Sub(l, r) => Definition::Sub(recurse(l), recurse(r)),
+            Definition::Div(l, r) => Definition::Div(recurse(l), recurse(r)),
+            Definition::Abs(d) => Definition::Abs(recurse(d)),
+            Definition::Binary(op, l, r) => Definition::Binary(*op, recurse(l), recurse(r)),
+            Definition::Select(c, t, f) => {
+                Definition::Select(Box::new(c.map(&|d| d.inline(func))), recurse(t), recurse(f))
+            }
+        }
+    }
+
+    /// Replaces each occurrence of `Var::X` in an access by `x`, and of `Var::Y` by `y`.
+    fn substitute(&self, x: &VarExpr, y: &VarExpr) -> Definition {
+        let recurse = |d: &Definition| Box::new(d.substitute(x, y));
+        match self {
+            Definition::Access(a) => Definition::Access(Access {
+                x: a.x.substitute(x, y),
+                y: a.y.substitute(x, y),
+                ..a.clone()
+            }),
+            Definition::Const(_) | Definition::FloatConst(_) | Definition::Param(_) => self.clone(),
+            Definition::Cast(ty, d) => Definition::Cast(*ty, recurse(d)),
+            Definition::SaturatingCast(ty, d) => Definition::SaturatingCast(*ty, recurse(d)),
+            Definition::Clamp(v, lo, hi) => Definition::Clamp(recurse(v), recurse(lo), recurse(hi)),
+            Definition::Cond(c) => Definition::Cond(Condition {
+                cmp: c.cmp,
+                lhs: recurse(&c.lhs),
+                rhs: recurse(&c.rhs),
+                if_true: recurse(&c.if_true),
+                if_false: recurse(&c.if_false)
+            }),
+            Definition::Add(l, r) => Definition::Add(recurse(l), recurse(r)),
+            Definition::Mul(l, r) => Definition::Mul(recurse(l), recurse(r)),
+            Definition::Sub(l, r) => Definition::Sub(recurse(l), recurse(r)),
+            Definition::Div(l, r) => Definition::Div(recurse(l), recurse(r)),
+            Definition::Abs(d) => Definition::Abs(recurse(d)),
+            Definition::Binary(op, l, r) => Definition::Binary(*op, recurse(l), recurse(r)),
+            Definition::Select(c, t, f) => {
+                Definition::Select(Box::new(c.map(&|d| d.substitute(x, y))), recurse(t), recurse(f))
+            }
         }
     }
 
     pub(crate) fn params(&self) -> Vec<String> {
         match self {
             Definition::Access(_) => vec![],
-            Definition::Const(_) => vec![],
+            Definition::Const(_) | Definition::FloatConst(_) => vec![],
             Definition::Param(p) => vec![p.clone()],
             Definition::Cond(c) => params(&vec![&c.lhs, &c.rhs, &c.if_true, &c.if_false]),
+            Definition::Cast(_, d) | Definition::SaturatingCast(_, d) => d.params(),
+            Definition::Clamp(v, lo, hi) => params(&vec![v, lo, hi]),
             Definition::Add(l, r) => params(&vec![l, r]),
             Definition::Mul(l, r) => params(&vec![l, r]),
             Definition::Sub(l, r) => params(&vec![l, r]),
             Definition::Div(l, r) => params(&vec![l, r]),
+            Definition::Abs(d) => d.params(),
+            Definition::Binary(_, l, r) => params(&vec![l, r]),
+            Definition::Select(c, t, f) => {
+                let mut params: Vec<String> = c.definitions().iter().flat_map(|d| d.params()).collect();
+                params.extend(t.params());
+                params.extend(f.params());
+                params
+            }
+        }
+    }
+
+    /// The type of the value of this definition, which is either `Type::I32` or `Type::F32`.
+    /// Arithmetic and comparisons with one f32 operand convert the other operand to f32,
+    /// and conditions or selects with one f32 branch convert the other branch to f32. Bitwise
+    /// operators convert both operands to i32.
+    pub fn value_type(&self) -> Type {
+        match self {
+            Definition::Access(a) => a.ty.value_type(),
+            Definition::Const(_) | Definition::Param(_) => Type::I32,
+            Definition::FloatConst(_) => Type::F32,
+            Definition::Cast(ty, _) | Definition::SaturatingCast(ty, _) => ty.value_type(),
+            Definition::Clamp(v, lo, hi) => clamp_value_type(v, lo, hi),
+            Definition::Cond(c) => common_value_type(&c.if_true, &c.if_false),
+            Definition::Add(l, r)
+            | Definition::Mul(l, r)
+            | Definition::Sub(l, r)
+            | Definition::Div(l, r) => common_value_type(l, r),
+            Definition::Abs(d) => d.value_type(),
+            Definition::Binary(op, _, _) if op.is_bitwise() => Type::I32,
+            Definition::Binary(_, l, r) => common_value_type(l, r),
+            Definition::Select(_, t, f) => common_value_type(t, f)
         }
     }
 }
 
+/// The type to which the values of `l` and `r` are converted when they are
+/// combined: `Type::F32` if either has value type f32, and `Type::I32` otherwise.
+pub(crate) fn common_value_type(l: &Definition, r: &Definition) -> Type {
+    if l.value_type().is_float() || r.value_type().is_float() { Type::F32 } else { Type::I32 }
+}
+
+/// The type to which the operands of a `Definition::Clamp` are converted.
+pub(crate) fn clamp_value_type(v: &Definition, lo: &Definition, hi: &Definition) -> Type {
+    if common_value_type(lo, hi).is_float() { Type::F32 } else { v.value_type() }
+}
+
 impl PrettyPrint for Definition {
     fn pretty_print(&self) -> String {
         match self {
             Definition::Access(a) => a.pretty_print(),
             Definition::Const(c) => c.to_string(),
+            // The Debug representation always includes a decimal point or an exponent
+            Definition::FloatConst(c) => format!("{:?}", c),
             Definition::Param(p) => p.clone(),
+            Definition::Cast(ty, d) => format!("{}({})", ty, d.pretty_print()),
+            Definition::SaturatingCast(ty, d) => format!("saturating_{}({})", ty, d.pretty_print()),
+            Definition::Clamp(v, lo, hi) => {
+                format!("clamp({}, {}, {})", v.pretty_print(), lo.pretty_print(), hi.pretty_print())
+            },
             Definition::Cond(c) => {
                 let l = pretty_print_with_parens(&*c.lhs);
                 let op = pretty_print_with_parens(&c.cmp);
@@ -354,13 +761,29 @@ impl PrettyPrint for Definition {
             Definition::Add(l, r) => combine_with_op("+", l, r),
             Definition::Sub(l, r) => combine_with_op("-", l, r),
             Definition::Mul(l, r) => combine_with_op("*", l, r),
-            Definition::Div(l, r) => combine_with_op("/", l, r)
+            Definition::Div(l, r) => combine_with_op("/", l, r),
+            Definition::Abs(d) => format!("abs({})", d.pretty_print()),
+            Definition::Binary(op, l, r) if op.is_function() => {
+                format!("{}({}, {})", op.symbol(), l.pretty_print(), r.pretty_print())
+            },
+            Definition::Binary(op, l, r) => combine_with_op(op.symbol(), l, r),
+            Definition::Select(c, t, f) => {
+                format!("select({}, {}, {})", c.pretty_print(), t.pretty_print(), f.pretty_print())
+            }
         }
     }
 
     fn is_leaf(&self) -> bool {
         match self {
-            Definition::Access(_) | Definition::Const(_) => true,
+            Definition::Access(_)
+            | Definition::Const(_)
+            | Definition::FloatConst(_)
+            | Definition::Cast(_, _)
+            | Definition::SaturatingCast(_, _)
+            | Definition::Clamp(_, _, _)
+            | Definition::Abs(_)
+            | Definition::Select(_, _, _) => true,
+            Definition::Binary(op, _, _) => op.is_function(),
             _ => false
         }
     }
@@ -369,12 +792,28 @@ impl PrettyPrint for Definition {
 /// An image provided as an input
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Source {
-    pub name: String
+    pub name: String,
+    /// The boundary condition used by accesses created by `at`.
+    pub boundary: Boundary,
+    /// The type of the elements of the input image. Defaults to u8.
+    pub ty: Type
 }
 
 impl Source {
     pub fn new(name: &str) -> Source {
-        Source { name: name.to_string() }
+        Source { name: name.to_string(), boundary: Boundary::Zero, ty: Type::U8 }
+    }
+
+    /// Sets the boundary condition used by accesses created by `at`.
+    pub fn with_boundary(mut self, boundary: Boundary) -> Source {
+        self.boundary = boundary;
+        self
+    }
+
+    /// Sets the type of the elements of the input image.
+    pub fn with_type(mut self, ty: Type) -> Source {
+        self.ty = ty;
+        self
     }
 
     pub fn at<U, V>(&self, x: U, y: V) -> Definition
@@ -382,21 +821,66 @@ impl Source {
         U: Into<VarExpr>,
         V: Into<VarExpr>
     {
-        Definition::Access(Access::new(&self.name, x.into(), y.into()))
+        self.at_with_boundary(x, y, self.boundary)
+    }
+
+    /// Reads from this source using the given boundary condition,
+    /// rather than the source's default.
+    pub fn at_with_boundary<U, V>(&self, x: U, y: V, boundary: Boundary) -> Definition
+    where
+        U: Into<VarExpr>,
+        V: Into<VarExpr>
+    {
+        let access = Access::with_boundary(&self.name, x.into(), y.into(), boundary);
+        Definition::Access(access.with_type(self.ty))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Func {
     pub(crate) name: String,
-    pub(crate) definition: Definition
+    pub(crate) definition: Definition,
+    /// The type of the elements of this func's buffer. Defaults to u8.
+    pub(crate) ty: Type,
+    /// How values are converted to `ty`. Defaults to `StorePolicy::Wrap`.
+    pub(crate) store_policy: StorePolicy
 }
 
 impl Func {
     pub fn new(name: &str, definition: Definition) -> Func {
         Func {
             name: name.to_string(),
-            definition: definition
+            definition: definition,
+            ty: Type::U8,
+            store_policy: StorePolicy::Wrap
+        }
+    }
+
+    /// Sets the type of the elements of this func's buffer.
+    pub fn with_type(mut self, ty: Type) -> Func {
+        self.ty = ty;
+        self
+    }
+
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+
+    /// Sets how values are converted to the element type of this func's buffer.
+    pub fn with_store_policy(mut self, store_policy: StorePolicy) -> Func {
+        self.store_policy = store_policy;
+        self
+    }
+
+    pub fn store_policy(&self) -> StorePolicy {
+        self.store_policy
+    }
+
+    /// Wraps `definition` in the conversion applied to values stored to this func's buffer.
+    pub(crate) fn store_conversion(&self, definition: Definition) -> Definition {
+        match self.store_policy {
+            StorePolicy::Wrap => Definition::Cast(self.ty, Box::new(definition)),
+            StorePolicy::Saturate => Definition::SaturatingCast(self.ty, Box::new(definition))
         }
     }
 
@@ -417,13 +901,32 @@ impl Func {
         U: Into<VarExpr>,
         V: Into<VarExpr>
     {
-        Definition::Access(Access::new(&self.name, x.into(), y.into()))
+        Definition::Access(Access::new(&self.name, x.into(), y.into()).with_type(self.ty))
+    }
+
+    /// Reads from this func using the given boundary condition. The
+    /// func is treated as if it were only defined over the region
+    /// computed for the pipeline's output.
+    pub fn at_with_boundary<U, V>(&self, x: U, y: V, boundary: Boundary) -> Definition
+    where
+        U: Into<VarExpr>,
+        V: Into<VarExpr>
+    {
+        let access = Access::with_boundary(&self.name, x.into(), y.into(), boundary);
+        Definition::Access(access.with_type(self.ty))
     }
 }
 
 impl PrettyPrint for Func {
+    /// Element types other than u8, the default, are printed after the variables,
+    /// preceded by `saturating` if the func has a saturating store policy.
     fn pretty_print(&self) -> String {
-        format!("{}(x, y) = {}", self.name, self.definition.pretty_print())
+        let definition = self.definition.pretty_print();
+        match (self.ty, self.store_policy) {
+            (Type::U8, StorePolicy::Wrap) => format!("{}(x, y) = {}", self.name, definition),
+            (ty, StorePolicy::Wrap) => format!("{}(x, y): {} = {}", self.name, ty, definition),
+            (ty, StorePolicy::Saturate) => format!("{}(x, y): saturating {} = {}", self.name, ty, definition)
+        }
     }
 
     fn is_leaf(&self) -> bool {
@@ -461,6 +964,24 @@ mod tests {
         assert_eq!(f.pretty_print(), "f(x, y) = (g(x + 1, y - 1) + g(x - 1, y)) + 2");
     }
 
+    #[test]
+    fn test_inline() {
+        let (x, y) = (Var::X, Var::Y);
+        let g = Source::new("g");
+        let f = Func::new("f", g.at(x + 1, y) * g.at(x, 2 * y));
+        let h = Func::new("h", f.at(x - 1, y + 3) + 1);
+        assert_eq!(
+            h.definition.inline(&f).pretty_print(),
+            "u8(g((x - 1) + 1, y + 3) * g(x - 1, 2 * (y + 3))) + 1"
+        );
+        let k = Func::new("k", f.at(x, y) - 1).with_store_policy(StorePolicy::Saturate);
+        let m = Func::new("m", k.at(x, y + 1) / 2);
+        assert_eq!(
+            m.definition.inline(&k).pretty_print(),
+            "saturating_u8(f(x, y + 1) - 1) / 2"
+        );
+    }
+
     #[test]
     fn test_x_extent() {
         let (x, y) = (Var::X, Var::Y);
@@ -475,4 +996,14 @@ mod tests {
         assert_eq!(x_extent(&((x - y) * (x + 1)), w, h), (-19 * 10, 9 * 10));
         assert_eq!(x_extent(&((x - y) * (x - y)), w, h), (-19 * 9, 19 * 19));
     }
+
+    #[test]
+    fn test_y_extent() {
+        let (x, y) = (Var::X, Var::Y);
+        let (w, h) = (10, 20);
+        assert_eq!(y_extent(&(y.into()), w, h), (0, 19));
+        assert_eq!(y_extent(&(y - 1), w, h), (-1, 18));
+        assert_eq!(y_extent(&(x.into()), w, h), (0, 9));
+        assert_eq!(y_extent(&(2 * y + x), w, h), (0, 47));
+    }
 }
\ No newline at end of file
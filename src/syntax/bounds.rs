@@ -0,0 +1,338 @@
+//! Interval arithmetic on `VarExpr`s, used to work out which region of each
+//! func needs to be computed in order to compute a given region of its consumers.
+
+use std::collections::HashMap;
+use crate::syntax::*;
+
+/// The arithmetic needed to compute bounds. This is implemented for `i32`
+/// values by `Concrete`, and for LLVM values in codegen so that generated
+/// code can compute bounds at runtime.
+pub trait BoundsArithmetic {
+    type Value: Copy;
+    fn constant(&self, c: i32) -> Self::Value;
+    fn add(&self, l: Self::Value, r: Self::Value) -> Self::Value;
+    fn sub(&self, l: Self::Value, r: Self::Value) -> Self::Value;
+    fn mul(&self, l: Self::Value, r: Self::Value) -> Self::Value;
+    fn min(&self, l: Self::Value, r: Self::Value) -> Self::Value;
+    fn max(&self, l: Self::Value, r: Self::Value) -> Self::Value;
+}
+
+/// Computes bounds on known values.
+pub struct Concrete;
+
+impl BoundsArithmetic for Concrete {
+    type Value = i32;
+    fn constant(&self, c: i32) -> i32 { c }
+    fn add(&self, l: i32, r: i32) -> i32 { l + r }
+    fn sub(&self, l: i32, r: i32) -> i32 { l - r }
+    fn mul(&self, l: i32, r: i32) -> i32 { l * r }
+    fn min(&self, l: i32, r: i32) -> i32 { std::cmp::min(l, r) }
+    fn max(&self, l: i32, r: i32) -> i32 { std::cmp::max(l, r) }
+}
+
+/// The closed interval [min, max].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval<T> {
+    pub min: T,
+    pub max: T
+}
+
+impl<T: Copy> Interval<T> {
+    pub fn new(min: T, max: T) -> Interval<T> {
+        Interval { min, max }
+    }
+
+    pub fn point(value: T) -> Interval<T> {
+        Interval { min: value, max: value }
+    }
+
+    /// The smallest interval containing both `self` and `other`.
+    pub fn union<A>(&self, a: &A, other: &Interval<T>) -> Interval<T>
+    where
+        A: BoundsArithmetic<Value = T>
+    {
+        Interval::new(a.min(self.min, other.min), a.max(self.max, other.max))
+    }
+
+    /// The intersection of `self` and `other`. This is empty, i.e. has
+    /// `max < min`, if the intervals do not overlap.
+    pub fn intersect<A>(&self, a: &A, other: &Interval<T>) -> Interval<T>
+    where
+        A: BoundsArithmetic<Value = T>
+    {
+        Interval::new(a.max(self.min, other.min), a.min(self.max, other.max))
+    }
+
+    /// The values obtained by clamping each value in `self` to `other`.
+    pub fn clamp<A>(&self, a: &A, other: &Interval<T>) -> Interval<T>
+    where
+        A: BoundsArithmetic<Value = T>
+    {
+        let clamp = |v| a.min(a.max(v, other.min), other.max);
+        Interval::new(clamp(self.min), clamp(self.max))
+    }
+}
+
+/// A rectangular region of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region<T> {
+    pub x: Interval<T>,
+    pub y: Interval<T>
+}
+
+impl<T: Copy> Region<T> {
+    pub fn new(x: Interval<T>, y: Interval<T>) -> Region<T> {
+        Region { x, y }
+    }
+
+    /// The smallest region containing both `self` and `other`.
+    pub fn union<A>(&self, a: &A, other: &Region<T>) -> Region<T>
+    where
+        A: BoundsArithmetic<Value = T>
+    {
+        Region::new(self.x.union(a, &other.x), self.y.union(a, &other.y))
+    }
+}
+
+/// Returns the range of values taken by `expr` as x and y vary over `region`.
+pub fn interval<A: BoundsArithmetic>(
+    a: &A,
+    expr: &VarExpr,
+    region: &Region<A::Value>
+) -> Interval<A::Value> {
+    match expr {
+        VarExpr::Var(Var::X) => region.x,
+        VarExpr::Var(Var::Y) => region.y,
+        VarExpr::Const(c) => Interval::point(a.constant(*c)),
+        VarExpr::Add(l, r) => {
+            let (l, r) = (interval(a, l, region), interval(a, r, region));
+            Interval::new(a.add(l.min, r.min), a.add(l.max, r.max))
+        },
+        VarExpr::Sub(l, r) => {
+            let (l, r) = (interval(a, l, region), interval(a, r, region));
+            Interval::new(a.sub(l.min, r.max), a.sub(l.max, r.min))
+        },
+        VarExpr::Mul(l, r) => {
+            let (l, r) = (interval(a, l, region), interval(a, r, region));
+            let (p, q, s, t) = (
+                a.mul(l.min, r.min),
+                a.mul(l.min, r.max),
+                a.mul(l.max, r.min),
+                a.mul(l.max, r.max)
+            );
+            Interval::new(
+                a.min(a.min(p, q), a.min(s, t)),
+                a.max(a.max(p, q), a.max(s, t))
+            )
+        }
+    }
+}
+
+/// Returns `(a, b, c)` such that `expr` is equal to `a * x + b * y + c`,
+/// or `None` if `expr` is not affine in x and y.
+pub fn affine_coefficients(expr: &VarExpr) -> Option<(i32, i32, i32)> {
+    match expr {
+        VarExpr::Var(Var::X) => Some((1, 0, 0)),
+        VarExpr::Var(Var::Y) => Some((0, 1, 0)),
+        VarExpr::Const(c) => Some((0, 0, *c)),
+        VarExpr::Add(l, r) => {
+            let (l, r) = (affine_coefficients(l)?, affine_coefficients(r)?);
+            Some((l.0 + r.0, l.1 + r.1, l.2 + r.2))
+        },
+        VarExpr::Sub(l, r) => {
+            let (l, r) = (affine_coefficients(l)?, affine_coefficients(r)?);
+            Some((l.0 - r.0, l.1 - r.1, l.2 - r.2))
+        },
+        VarExpr::Mul(l, r) => {
+            let (l, r) = (affine_coefficients(l)?, affine_coefficients(r)?);
+            match (l, r) {
+                ((0, 0, c), (a, b, d)) | ((a, b, d), (0, 0, c)) => Some((c * a, c * b, c * d)),
+                _ => None
+            }
+        }
+    }
+}
+
+/// Returns the region of its source that is read by `access` as x and y vary
+/// over `region`. `domain` is the region that accesses with a boundary condition
+/// treat as the bounds of their source.
+pub fn access_region<A: BoundsArithmetic>(
+    a: &A,
+    access: &Access,
+    region: &Region<A::Value>,
+    domain: &Region<A::Value>
+) -> Region<A::Value> {
+    let (x, y) = (interval(a, &access.x, region), interval(a, &access.y, region));
+    match access.boundary {
+        None => Region::new(x, y),
+        Some(Boundary::Zero) | Some(Boundary::Constant(_)) => {
+            Region::new(x.intersect(a, &domain.x), y.intersect(a, &domain.y))
+        },
+        Some(Boundary::Clamp) => Region::new(x.clamp(a, &domain.x), y.clamp(a, &domain.y)),
+        // Reflected or wrapped coordinates may lie anywhere in the domain
+        Some(Boundary::Mirror) | Some(Boundary::Wrap) => *domain
+    }
+}
+
+/// Returns the region of `source` that is read when computing `region` of `func`,
+/// or `None` if `func` does not read from `source`. `domain` is as for `access_region`.
+pub fn required_region<A: BoundsArithmetic>(
+    a: &A,
+    func: &Func,
+    region: &Region<A::Value>,
+    domain: &Region<A::Value>,
+    source: &str
+) -> Option<Region<A::Value>> {
+    func.definition
+        .accesses()
+        .iter()
+        .filter(|access| access.source == source)
+        .map(|access| access_region(a, access, region, domain))
+        .fold(None, |acc, r| match acc {
+            Some(acc) => Some(r.union(a, &acc)),
+            None => Some(r)
+        })
+}
+
+/// Returns the region of the named func or input that is required to compute
+/// the regions of all funcs that are present in `regions`, or `None` if none
+/// of these funcs read from it.
+fn region_required_by_consumers<A: BoundsArithmetic>(
+    a: &A,
+    graph: &Graph,
+    regions: &HashMap<String, Region<A::Value>>,
+    domain: &Region<A::Value>,
+    producer: &str
+) -> Option<Region<A::Value>> {
+    graph.funcs()
+        .iter()
+        .filter_map(|consumer| {
+            let region = regions.get(&consumer.name)?;
+            required_region(a, consumer, region, domain, producer)
+        })
+        .fold(None, |acc: Option<Region<A::Value>>, r| match acc {
+            Some(acc) => Some(r.union(a, &acc)),
+            None => Some(r)
+        })
+}
+
+/// Adds to `regions` the region of each of `producers` that is required to compute
+/// the regions of funcs that are already present in `regions`, and the regions
+/// of the other producers. `producers` must be in dependency order. Producers that
+/// are not read by any of these funcs are not added. `domain` is as for `access_region`.
+pub fn infer_regions<A: BoundsArithmetic>(
+    a: &A,
+    graph: &Graph,
+    producers: &[&Func],
+    domain: &Region<A::Value>,
+    regions: &mut HashMap<String, Region<A::Value>>
+) {
+    for producer in producers.iter().rev() {
+        if let Some(required) = region_required_by_consumers(a, graph, regions, domain, &producer.name) {
+            regions.insert(producer.name.clone(), required);
+        }
+    }
+}
+
+/// Walks backwards through `graph` to find the region of each func and input that
+/// is required to compute `output_region` of each of the graph's outputs. Outputs
+/// that are also read by other funcs are computed over the union of `output_region`
+/// and the region their consumers require. Accesses with a boundary condition treat
+/// `output_region` as the bounds of their source.
+pub fn required_regions<A: BoundsArithmetic>(
+    a: &A,
+    graph: &Graph,
+    output_region: Region<A::Value>
+) -> HashMap<String, Region<A::Value>> {
+    let mut regions = HashMap::new();
+    for func in graph.funcs().iter().rev() {
+        let required = region_required_by_consumers(a, graph, &regions, &output_region, &func.name);
+        let region = if graph.outputs().contains(&func.name) {
+            Some(required.map_or(output_region, |r| r.union(a, &output_region)))
+        } else {
+            required
+        };
+        if let Some(region) = region {
+            regions.insert(func.name.clone(), region);
+        }
+    }
+    for input in graph.inputs() {
+        if let Some(required) = region_required_by_consumers(a, graph, &regions, &output_region, input) {
+            regions.insert(input.clone(), required);
+        }
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer_regions() {
+        let (x, y) = (Var::X, Var::Y);
+        let input = Source::new("input");
+        let f = Func::new("f", input.at(x - 1, y) + input.at(x + 1, y));
+        let g = Func::new("g", f.at(x, y - 2) + f.at(2 * x, y));
+        let h = Func::new("h", g.at(x, y + 1) + f.at(x, y));
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        sched.add_func(&g, FuncSchedule::by_row());
+        sched.add_func(&h, FuncSchedule::by_row());
+        let graph = Graph::new("graph", vec![f.clone(), g.clone(), h.clone()], sched).unwrap();
+
+        let mut regions = HashMap::new();
+        let domain = Region::new(Interval::new(0, 9), Interval::new(0, 4));
+        regions.insert(h.name.clone(), domain);
+        infer_regions(&Concrete, &graph, &[&f, &g], &domain, &mut regions);
+
+        assert_eq!(regions["g"], Region::new(Interval::new(0, 9), Interval::new(1, 5)));
+        assert_eq!(regions["f"], Region::new(Interval::new(0, 18), Interval::new(-1, 5)));
+    }
+
+    #[test]
+    fn test_required_regions() {
+        let (x, y) = (Var::X, Var::Y);
+        let input = Source::new("input");
+        let f = Func::new("f", input.at(x, y - 1) + input.at(x, y + 1));
+        let g = Func::new("g", f.at(x - 1, y) + f.at(x + 1, y));
+        let mut sched = Schedule::new();
+        sched.add_source(&input, FuncSchedule::by_row());
+        sched.add_func(&f, FuncSchedule::by_row());
+        sched.add_func(&g, FuncSchedule::by_row());
+        let graph = Graph::new("graph", vec![f, g], sched).unwrap();
+
+        let regions = required_regions(&Concrete, &graph, Region::new(Interval::new(0, 9), Interval::new(0, 4)));
+        assert_eq!(regions["g"], Region::new(Interval::new(0, 9), Interval::new(0, 4)));
+        assert_eq!(regions["f"], Region::new(Interval::new(-1, 10), Interval::new(0, 4)));
+        // Reads from sources default to a zero boundary, so don't extend outside the image
+        assert_eq!(regions["input"], Region::new(Interval::new(0, 9), Interval::new(0, 4)));
+    }
+
+    #[test]
+    fn test_affine_coefficients() {
+        let (x, y) = (Var::X, Var::Y);
+        assert_eq!(affine_coefficients(&(2 * (x - 3) + y)), Some((2, 1, -6)));
+        assert_eq!(affine_coefficients(&(y - x * 4)), Some((-4, 1, 0)));
+        assert_eq!(affine_coefficients(&(x * y)), None);
+    }
+
+    #[test]
+    fn test_access_region_with_boundary() {
+        let (x, y) = (Var::X, Var::Y);
+        let region = Region::new(Interval::new(0, 9), Interval::new(0, 4));
+        let access = |boundary| Access::with_boundary("f", x - 2, y + 1, boundary);
+        let expected = |x_min, x_max, y_min, y_max| {
+            Region::new(Interval::new(x_min, x_max), Interval::new(y_min, y_max))
+        };
+        let required = |boundary| access_region(&Concrete, &access(boundary), &region, &region);
+        assert_eq!(required(Boundary::Zero), expected(0, 7, 1, 4));
+        assert_eq!(required(Boundary::Constant(3)), expected(0, 7, 1, 4));
+        assert_eq!(required(Boundary::Clamp), expected(0, 7, 1, 4));
+        assert_eq!(required(Boundary::Mirror), region);
+        assert_eq!(required(Boundary::Wrap), region);
+        let unbounded = Access::new("f", x - 2, y + 1);
+        assert_eq!(access_region(&Concrete, &unbounded, &region, &region), expected(-2, 7, 1, 5));
+    }
+}
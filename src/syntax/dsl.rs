@@ -1,7 +1,7 @@
 //! Contains operator impls and macros to allow ergonomic construction of AST nodes.
 
-use std::ops::{Add, Div, Mul, Sub};
-use crate::syntax::ast::*;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Not, Rem, Shl, Shr, Sub};
+use crate::{image::Pixel, syntax::ast::*};
 
 /// Shorthand for creating a new `Source`.
 ///
@@ -132,6 +132,20 @@ macro_rules! impl_definition_bin_op {
             }
         }
 
+        impl $trait_name<f32> for Definition {
+            type Output = Definition;
+            fn $trait_op(self, rhs: f32) -> Definition {
+                $ctor(Box::new(self), Box::new(Definition::FloatConst(rhs)))
+            }
+        }
+
+        impl $trait_name<Definition> for f32 {
+            type Output = Definition;
+            fn $trait_op(self, rhs: Definition) -> Definition {
+                $ctor(Box::new(Definition::FloatConst(self)), Box::new(rhs))
+            }
+        }
+
         impl $trait_name<&Param> for Definition {
             type Output = Definition;
             fn $trait_op(self, rhs: &Param) -> Definition {
@@ -152,3 +166,135 @@ impl_definition_bin_op!(Add, add, Definition::Add);
 impl_definition_bin_op!(Sub, sub, Definition::Sub);
 impl_definition_bin_op!(Mul, mul, Definition::Mul);
 impl_definition_bin_op!(Div, div, Definition::Div);
+impl_definition_bin_op!(Rem, rem, |l, r| Definition::Binary(BinaryOp::Rem, l, r));
+impl_definition_bin_op!(BitAnd, bitand, |l, r| Definition::Binary(BinaryOp::BitAnd, l, r));
+impl_definition_bin_op!(BitOr, bitor, |l, r| Definition::Binary(BinaryOp::BitOr, l, r));
+impl_definition_bin_op!(BitXor, bitxor, |l, r| Definition::Binary(BinaryOp::BitXor, l, r));
+impl_definition_bin_op!(Shl, shl, |l, r| Definition::Binary(BinaryOp::Shl, l, r));
+impl_definition_bin_op!(Shr, shr, |l, r| Definition::Binary(BinaryOp::Shr, l, r));
+
+impl Definition {
+    /// Converts this definition to an f32.
+    pub fn to_float(self) -> Definition {
+        Definition::Cast(Type::F32, Box::new(self))
+    }
+
+    /// Converts this definition to an i32, rounding towards zero if it is an f32.
+    pub fn to_int(self) -> Definition {
+        Definition::Cast(Type::I32, Box::new(self))
+    }
+
+    /// Converts this definition to the element type `T`, as if it were stored to and
+    /// read from a buffer of that type. Integers wrap around.
+    pub fn cast<T: Pixel>(self) -> Definition {
+        Definition::Cast(T::TYPE, Box::new(self))
+    }
+
+    /// Converts this definition to the element type `T`, clamping it to the range of that type.
+    pub fn saturating_cast<T: Pixel>(self) -> Definition {
+        Definition::SaturatingCast(T::TYPE, Box::new(self))
+    }
+
+    /// The smaller of this definition and `other`.
+    pub fn min<D: Into<Definition>>(self, other: D) -> Definition {
+        Definition::Binary(BinaryOp::Min, Box::new(self), Box::new(other.into()))
+    }
+
+    /// The larger of this definition and `other`.
+    pub fn max<D: Into<Definition>>(self, other: D) -> Definition {
+        Definition::Binary(BinaryOp::Max, Box::new(self), Box::new(other.into()))
+    }
+
+    /// The absolute value of this definition.
+    pub fn abs(self) -> Definition {
+        Definition::Abs(Box::new(self))
+    }
+
+    /// The absolute difference between this definition and `other`.
+    pub fn absd<D: Into<Definition>>(self, other: D) -> Definition {
+        Definition::Binary(BinaryOp::AbsDiff, Box::new(self), Box::new(other.into()))
+    }
+
+    /// True if this definition is equal to `other`. Not named `eq` to avoid
+    /// shadowing `PartialEq::eq`.
+    pub fn equals<D: Into<Definition>>(self, other: D) -> BoolExpr {
+        BoolExpr::Compare(Comparison::EQ, Box::new(self), Box::new(other.into()))
+    }
+
+    /// True if this definition is not equal to `other`.
+    pub fn not_equals<D: Into<Definition>>(self, other: D) -> BoolExpr {
+        !self.equals(other)
+    }
+
+    /// True if this definition is strictly greater than `other`.
+    pub fn gt<D: Into<Definition>>(self, other: D) -> BoolExpr {
+        BoolExpr::Compare(Comparison::GT, Box::new(self), Box::new(other.into()))
+    }
+
+    /// True if this definition is greater than or equal to `other`.
+    pub fn ge<D: Into<Definition>>(self, other: D) -> BoolExpr {
+        BoolExpr::Compare(Comparison::GTE, Box::new(self), Box::new(other.into()))
+    }
+
+    /// True if this definition is strictly less than `other`.
+    pub fn lt<D: Into<Definition>>(self, other: D) -> BoolExpr {
+        BoolExpr::Compare(Comparison::LT, Box::new(self), Box::new(other.into()))
+    }
+
+    /// True if this definition is less than or equal to `other`.
+    pub fn le<D: Into<Definition>>(self, other: D) -> BoolExpr {
+        BoolExpr::Compare(Comparison::LTE, Box::new(self), Box::new(other.into()))
+    }
+
+    /// Clamps this definition to lie between `lo` and `hi`.
+    pub fn clamp<L: Into<Definition>, H: Into<Definition>>(self, lo: L, hi: H) -> Definition {
+        Definition::Clamp(Box::new(self), Box::new(lo.into()), Box::new(hi.into()))
+    }
+}
+
+impl From<i32> for Definition {
+    fn from(c: i32) -> Definition {
+        Definition::Const(c)
+    }
+}
+
+impl From<f32> for Definition {
+    fn from(c: f32) -> Definition {
+        Definition::FloatConst(c)
+    }
+}
+
+impl From<&Param> for Definition {
+    fn from(p: &Param) -> Definition {
+        Definition::Param(p.name.clone())
+    }
+}
+
+impl BoolExpr {
+    /// True if both this expression and `other` are true.
+    pub fn and(self, other: BoolExpr) -> BoolExpr {
+        BoolExpr::And(Box::new(self), Box::new(other))
+    }
+
+    /// True if either this expression or `other` is true.
+    pub fn or(self, other: BoolExpr) -> BoolExpr {
+        BoolExpr::Or(Box::new(self), Box::new(other))
+    }
+}
+
+impl Not for BoolExpr {
+    type Output = BoolExpr;
+    fn not(self) -> BoolExpr {
+        BoolExpr::Not(Box::new(self))
+    }
+}
+
+/// Returns `if_true` where `cond` holds and `if_false` elsewhere. Both values are
+/// computed at every location, and the choice is made without branching.
+pub fn select<T, F>(cond: BoolExpr, if_true: T, if_false: F) -> Definition
+where
+    T: Into<Definition>,
+    F: Into<Definition>
+{
+    Definition::Select(Box::new(cond), Box::new(if_true.into()), Box::new(if_false.into()))
+}
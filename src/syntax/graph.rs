@@ -1,29 +1,69 @@
 
-use std::collections::HashSet;
-use crate::syntax::{Func, Schedule};
+use std::collections::{HashMap, HashSet};
+use crate::{error::PrismError, syntax::{Func, LoopLevel, Schedule, Type}};
 
 /// Doesn't yet look very graph-like...
+#[derive(Clone)]
 pub struct Graph {
     pub name: String,
+    /// The funcs that are not computed inline, with
+    /// the definitions of any inline funcs substituted
+    /// into their consumers.
     funcs: Vec<Func>,
     /// Names of the required input buffers,
     /// computed from funcs.
     inputs: Vec<String>,
-    /// Names of the output buffers (including)
-    /// all intermediates), in some valid dependency
-    /// order.
+    /// Names of the funcs whose values are returned to the
+    /// caller, in dependency order. Storage for all other
+    /// funcs is allocated by the generated code.
     outputs: Vec<String>,
     /// Names of the required parameters,
     /// computed form funcs. These are guaranteed to be
     /// in lexicographic order.
     params: Vec<String>,
+    /// The element type of each func and input.
+    types: HashMap<String, Type>,
     /// The iteration, compute and storage orders for each function.
     pub(crate) schedule: Schedule
 }
 
 impl Graph {
-    pub fn new(name: &str, funcs: Vec<Func>, schedule: Schedule) -> Graph {
+    /// Creates a graph computing `funcs`, which may be provided in any order.
+    /// Returns an error if any func reads from itself, either directly or
+    /// via other funcs, or if any func or source is missing a schedule or
+    /// has a schedule that cannot be satisfied. The outputs of the graph are
+    /// the funcs that are not read by any other func.
+    pub fn new(name: &str, funcs: Vec<Func>, schedule: Schedule) -> Result<Graph, PrismError> {
+        let sinks: Vec<String> = funcs
+            .iter()
+            .filter(|f| funcs.iter().all(|c| !c.sources().contains(&f.name)))
+            .map(|f| f.name.clone())
+            .collect();
+        let sinks: Vec<&str> = sinks.iter().map(|s| s.as_str()).collect();
+        Graph::with_outputs(name, funcs, &sinks, schedule)
+    }
+
+    /// Creates a graph computing the named `outputs`, which must be computed at root.
+    /// Only the values of the outputs are returned to the caller, and any funcs that
+    /// are not required to compute the outputs are discarded. Returns an error if
+    /// there are no outputs.
+    pub fn with_outputs(
+        name: &str,
+        funcs: Vec<Func>,
+        outputs: &[&str],
+        schedule: Schedule
+    ) -> Result<Graph, PrismError> {
         let name = name.to_string();
+        for output in outputs {
+            if funcs.iter().all(|f| f.name != *output) {
+                return Err(PrismError::UnknownOutput(output.to_string()));
+            }
+        }
+        let mut funcs = eliminate_dead_funcs(topological_sort(funcs)?, outputs);
+        if funcs.is_empty() {
+            return Err(PrismError::NoOutputs);
+        }
+        let types = resolve_types(&mut funcs)?;
         // The names of the funcs being computed
         let func_names: HashSet<String> = funcs.iter().map(|f| f.name.clone()).collect();
         // The buffers that any func reads from
@@ -32,29 +72,57 @@ impl Graph {
         // computed and so must be provided as inputs
         let mut inputs: Vec<String> = reads.difference(&func_names).cloned().collect();
         inputs.sort();
-        // TODO: actually do the topological sort!
-        // TODO: for now we just assume that the inputs were provided in a valid order
-        let outputs: Vec<String> = funcs.iter().map(|f| f.name.clone()).collect();
 
         let params: HashSet<String> = funcs.iter().flat_map(|f| f.params()).collect();
         let mut params: Vec<String> = params.iter().cloned().collect();
         params.sort();
 
-        let schedule_names: HashSet<String> = schedule.func_schedules.keys().cloned().collect();
-        let funcs_without_schedules: Vec<String> = func_names.difference(&schedule_names).cloned().collect();
-        let buffers: HashSet<String> = inputs.iter().cloned().collect();
-        let buffers_without_schedules: Vec<String> = buffers.difference(&schedule_names).cloned().collect();
-        if funcs_without_schedules.len() > 0 || buffers_without_schedules.len() > 0 {
-            let schedule_names: Vec<String> = schedule_names.iter().cloned().collect();
-            panic!("Not all functions have schedules. \
-                Schedule names: {}, Missing funcs: {}. Missing buffers: {}",
-                schedule_names.join(", "),
-                funcs_without_schedules.join(", "),
-                buffers_without_schedules.join(", ")
-            );
+        let names = funcs.iter().map(|f| &f.name).chain(inputs.iter());
+        if let Some(name) = names.into_iter().find(|n| !schedule.func_schedules.contains_key(*n)) {
+            return Err(PrismError::MissingSchedule(name.clone()));
+        }
+
+        for func in &funcs {
+            let sched = schedule.get_func_schedule(func);
+            for level in &[&sched.compute_level, sched.store_level()] {
+                if let LoopLevel::At { func: consumer, .. } = level {
+                    if !func_names.contains(consumer) {
+                        return Err(PrismError::UnknownFunc { func: func.name.clone(), unknown: consumer.clone() });
+                    }
+                }
+            }
         }
 
-        Graph { name, funcs, inputs, outputs, params, schedule }
+        let outputs: Vec<String> = funcs
+            .iter()
+            .filter(|f| outputs.contains(&f.name.as_str()))
+            .map(|f| f.name.clone())
+            .collect();
+        for output in &outputs {
+            if schedule.func_schedules[output].compute_level != LoopLevel::Root {
+                return Err(invalid_schedule(output, "it is an output so must be computed at root"));
+            }
+        }
+
+        let funcs = inline_funcs(funcs, &schedule)?;
+        validate_loop_levels(&funcs, &schedule)?;
+
+        Ok(Graph { name, funcs, inputs, outputs, params, types, schedule })
+    }
+
+    /// Returns the func with the given name, if there is one.
+    pub fn func(&self, name: &str) -> Option<&Func> {
+        self.funcs.iter().find(|f| f.name == name)
+    }
+
+    /// Returns the funcs that are computed inside the loop over the `level`th
+    /// variable of `consumer`, either directly or within the loops of another
+    /// such func, in dependency order.
+    pub(crate) fn funcs_computed_within(&self, consumer: &Func, level: usize) -> Vec<&Func> {
+        self.funcs
+            .iter()
+            .filter(|f| is_computed_within(&self.funcs, &self.schedule, f, consumer, level))
+            .collect()
     }
 
     pub fn funcs(&self) -> &[Func] {
@@ -76,4 +144,360 @@ impl Graph {
     pub fn params(&self) -> &[String] {
         &self.params
     }
+
+    /// Returns the element type of the named func or input, if there is one.
+    pub fn element_type(&self, name: &str) -> Option<Type> {
+        self.types.get(name).cloned()
+    }
+}
+
+/// Sets the type of each access to a func to the type of that func, and returns the
+/// element type of each func and input. Returns an error if an input is read with
+/// more than one type.
+fn resolve_types(funcs: &mut [Func]) -> Result<HashMap<String, Type>, PrismError> {
+    let mut types: HashMap<String, Type> = funcs.iter().map(|f| (f.name.clone(), f.ty)).collect();
+    let func_types = types.clone();
+    for func in funcs.iter_mut() {
+        for access in func.definition.accesses_mut() {
+            if let Some(ty) = func_types.get(&access.source) {
+                access.ty = *ty;
+                continue;
+            }
+            match types.get(&access.source) {
+                Some(ty) if *ty != access.ty => return Err(PrismError::TypeMismatch {
+                    name: access.source.clone(),
+                    expected: *ty,
+                    actual: access.ty
+                }),
+                _ => { types.insert(access.source.clone(), access.ty); }
+            }
+        }
+    }
+    Ok(types)
+}
+
+/// Orders `funcs` so that each func comes after all the funcs it reads from.
+/// Funcs that are already in a valid order are left unchanged.
+fn topological_sort(funcs: Vec<Func>) -> Result<Vec<Func>, PrismError> {
+    let mut indices = HashMap::new();
+    for (i, func) in funcs.iter().enumerate() {
+        if indices.insert(func.name.clone(), i).is_some() {
+            return Err(PrismError::DuplicateFunc(func.name.clone()));
+        }
+        if func.sources().contains(&func.name) {
+            return Err(PrismError::SelfReference(func.name.clone()));
+        }
+    }
+
+    // Depth first search, adding each func once all of its producers have been added.
+    // `path` holds the funcs currently being visited, so that we can detect cycles
+    fn visit(
+        funcs: &[Func],
+        indices: &HashMap<String, usize>,
+        i: usize,
+        path: &mut Vec<usize>,
+        visited: &mut Vec<bool>,
+        order: &mut Vec<usize>
+    ) -> Result<(), PrismError> {
+        if visited[i] {
+            return Ok(());
+        }
+        if let Some(p) = path.iter().position(|j| *j == i) {
+            let cycle = path[p..].iter().map(|j| funcs[*j].name.clone()).collect();
+            return Err(PrismError::Cycle(cycle));
+        }
+        path.push(i);
+        for source in funcs[i].sources() {
+            if let Some(j) = indices.get(&source) {
+                visit(funcs, indices, *j, path, visited, order)?;
+            }
+        }
+        path.pop();
+        visited[i] = true;
+        order.push(i);
+        Ok(())
+    }
+
+    let mut visited = vec![false; funcs.len()];
+    let mut order = vec![];
+    for i in 0..funcs.len() {
+        visit(&funcs, &indices, i, &mut vec![], &mut visited, &mut order)?;
+    }
+    let mut funcs: Vec<Option<Func>> = funcs.into_iter().map(Some).collect();
+    Ok(order.into_iter().map(|i| funcs[i].take().unwrap()).collect())
+}
+
+/// Removes the funcs that are not read by any of the named outputs, either
+/// directly or via other funcs. `funcs` must be in dependency order.
+fn eliminate_dead_funcs(funcs: Vec<Func>, outputs: &[&str]) -> Vec<Func> {
+    let mut live: HashSet<String> = outputs.iter().map(|o| o.to_string()).collect();
+    for func in funcs.iter().rev() {
+        if live.contains(&func.name) {
+            live.extend(func.sources());
+        }
+    }
+    funcs.into_iter().filter(|f| live.contains(&f.name)).collect()
+}
+
+fn invalid_schedule(func: &str, reason: &str) -> PrismError {
+    PrismError::InvalidSchedule { func: func.to_string(), reason: reason.to_string() }
+}
+
+/// Substitutes the definition of each func that is computed inline into each
+/// of its consumers, and returns the remaining funcs. `funcs` must be in
+/// dependency order.
+fn inline_funcs(funcs: Vec<Func>, schedule: &Schedule) -> Result<Vec<Func>, PrismError> {
+    let is_inline = |f: &Func| schedule.get_func_schedule(f).compute_level == LoopLevel::Inline;
+    let inlined: Vec<&Func> = funcs.iter().filter(|f| is_inline(f)).collect();
+    for func in &inlined {
+        if schedule.get_func_schedule(func).store_level.is_some() {
+            return Err(invalid_schedule(&func.name, "it is computed inline so cannot have a store level"));
+        }
+        if !funcs.iter().any(|f| f.sources().contains(&func.name)) {
+            return Err(invalid_schedule(&func.name, "it is computed inline but is not read by any other func"));
+        }
+        for f in &funcs {
+            if f.definition.accesses().iter().any(|a| a.source == func.name && a.boundary.is_some()) {
+                return Err(invalid_schedule(
+                    &func.name,
+                    &format!("it is computed inline so cannot be read by {} with a boundary condition", f.name)
+                ));
+            }
+        }
+        for f in &funcs {
+            let sched = schedule.get_func_schedule(f);
+            for level in &[&sched.compute_level, sched.store_level()] {
+                if let LoopLevel::At { func: consumer, .. } = level {
+                    if *consumer == func.name {
+                        return Err(invalid_schedule(
+                            &f.name,
+                            &format!("it cannot be computed or stored within {}, as that is computed inline", func.name)
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut result: Vec<Func> = vec![];
+    for func in &funcs {
+        let mut definition = func.definition.clone();
+        for producer in &result {
+            if is_inline(producer) {
+                definition = definition.inline(producer);
+            }
+        }
+        result.push(Func { definition, ..func.clone() });
+    }
+    Ok(result.into_iter().filter(|f| !is_inline(f)).collect())
+}
+
+/// Whether `func` is computed inside the loop over the `level`th variable of
+/// `consumer`, either directly or within the loops of another such func.
+fn is_computed_within(
+    funcs: &[Func],
+    schedule: &Schedule,
+    func: &Func,
+    consumer: &Func,
+    level: usize
+) -> bool {
+    let mut current = func;
+    // Bound the number of steps, in case of cycles
+    for _ in 0..funcs.len() {
+        let (name, var) = match &schedule.get_func_schedule(current).compute_level {
+            LoopLevel::Root | LoopLevel::Inline => return false,
+            LoopLevel::At { func, var } => (func, var)
+        };
+        if *name == consumer.name {
+            let sched = schedule.get_func_schedule(consumer);
+            return sched.variables.iter().position(|v| v == var).map_or(false, |p| p >= level);
+        }
+        current = match funcs.iter().find(|f| f.name == *name) {
+            Some(f) => f,
+            None => return false
+        };
+    }
+    false
+}
+
+/// Checks that every func computed or stored inside the loops of another func
+/// refers to a valid loop level, that it is stored at or outside the level at which
+/// it's computed but not outside a parallel loop it's computed inside, and that all
+/// of its consumers are computed within that level.
+fn validate_loop_levels(funcs: &[Func], schedule: &Schedule) -> Result<(), PrismError> {
+    let position = |func: &Func, level: &LoopLevel| -> Result<Option<(&Func, usize)>, PrismError> {
+        match level {
+            LoopLevel::Root | LoopLevel::Inline => Ok(None),
+            LoopLevel::At { func: name, var } => {
+                let consumer = match funcs.iter().find(|f| f.name == *name) {
+                    Some(f) => f,
+                    None => return Err(PrismError::UnknownFunc { func: func.name.clone(), unknown: name.clone() })
+                };
+                let sched = schedule.get_func_schedule(consumer);
+                match sched.variables.iter().position(|v| v == var) {
+                    Some(p) => Ok(Some((consumer, p))),
+                    None => Err(invalid_schedule(
+                        &func.name,
+                        &format!("{} is not a loop variable of {}", var, name)
+                    ))
+                }
+            }
+        }
+    };
+
+    for func in funcs {
+        let sched = schedule.get_func_schedule(func);
+        let compute = position(func, &sched.compute_level)?;
+        let store = position(func, sched.store_level())?;
+        // The position of the loop in which the func is stored, or None if it is stored at root
+        let (consumer, level, store_level) = match (compute, store) {
+            (None, None) => continue,
+            (Some((consumer, level)), None) => (consumer, level, None),
+            (Some((consumer, level)), Some((store_consumer, store_level))) => {
+                if store_consumer.name != consumer.name || store_level > level {
+                    return Err(invalid_schedule(
+                        &func.name,
+                        "it must be stored at or outside the level at which it's computed"
+                    ));
+                }
+                (consumer, level, Some(store_level))
+            },
+            (None, Some(_)) => {
+                return Err(invalid_schedule(&func.name, "it is computed at root so must be stored at root"));
+            }
+        };
+        if consumer.name == func.name {
+            return Err(invalid_schedule(&func.name, "it cannot be computed within its own loops"));
+        }
+        // Iterations of a parallel loop would write concurrently to storage allocated outside it
+        let consumer_sched = schedule.get_func_schedule(consumer);
+        let inside_storage = store_level.map(|s| s + 1).unwrap_or(0);
+        let shared = consumer_sched.variables[inside_storage..=level].iter().find(|v| consumer_sched.is_parallel(v));
+        if let Some(var) = shared {
+            return Err(invalid_schedule(
+                &func.name,
+                &format!("it is computed inside the parallel loop over {} of {} so must be stored inside it", var, consumer.name)
+            ));
+        }
+        for reader in funcs.iter().filter(|f| f.sources().contains(&func.name)) {
+            if reader.name != consumer.name
+                && !is_computed_within(funcs, schedule, reader, consumer, level) {
+                return Err(invalid_schedule(
+                    &func.name,
+                    &format!("it is computed within the loops of {}, but is also read by {}", consumer.name, reader.name)
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::PrismError, syntax::*};
+
+    fn schedule(funcs: &[&Func]) -> Schedule {
+        let mut sched = Schedule::new();
+        for f in funcs {
+            sched.add_func(f, FuncSchedule::by_row());
+        }
+        sched
+    }
+
+    fn names(graph: &Graph) -> Vec<String> {
+        graph.funcs().iter().map(|f| f.name.clone()).collect()
+    }
+
+    #[test]
+    fn test_topological_sort() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) + 1);
+        func!(g = f.at(x, y) * 2);
+        func!(h = g.at(x, y) + f.at(x, y));
+        let mut sched = schedule(&[&f, &g, &h]);
+        sched.add_source(&input, FuncSchedule::by_row());
+        let graph = Graph::new("graph", vec![h, f, g], sched).unwrap();
+        assert_eq!(names(&graph), vec!["f", "g", "h"]);
+    }
+
+    #[test]
+    fn test_dead_code_elimination() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) + 1);
+        func!(g = f.at(x, y) * 2);
+        func!(h = f.at(x, y) - 1);
+        let mut sched = schedule(&[&f, &g, &h]);
+        sched.add_source(&input, FuncSchedule::by_row());
+        let graph = Graph::with_outputs("graph", vec![f, g, h], &["g"], sched).unwrap();
+        assert_eq!(names(&graph), vec!["f", "g"]);
+        assert_eq!(graph.outputs(), &["g".to_string()]);
+    }
+
+    #[test]
+    fn test_no_outputs() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) + 1);
+        let mut sched = schedule(&[&f]);
+        sched.add_source(&input, FuncSchedule::by_row());
+        let error = Graph::with_outputs("graph", vec![f], &[], sched.clone()).err();
+        assert_eq!(error, Some(PrismError::NoOutputs));
+        let error = Graph::new("graph", vec![], sched).err();
+        assert_eq!(error, Some(PrismError::NoOutputs));
+    }
+
+    #[test]
+    fn test_missing_schedule() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) + 1);
+        let sched = schedule(&[&f]);
+        let error = Graph::new("graph", vec![f], sched).err();
+        assert_eq!(error, Some(PrismError::MissingSchedule("input".to_string())));
+    }
+
+    #[test]
+    fn test_parallel_shared_storage() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(input);
+        func!(f = input.at(x, y) + 1);
+        func!(g = f.at(x, y - 1) + f.at(x, y + 1));
+        let graph = |g_sched: FuncSchedule, f_sched: FuncSchedule| {
+            let mut sched = Schedule::new();
+            sched.add_source(&input, FuncSchedule::by_row());
+            sched.add_func(&f, f_sched);
+            sched.add_func(&g, g_sched);
+            Graph::new("graph", vec![f.clone(), g.clone()], sched)
+        };
+        let split = || FuncSchedule::by_row().split(y, "yo", "yi", 4);
+        let compute = || FuncSchedule::by_row().compute_at(&g, "yi");
+        // Each iteration of yi would write to the storage allocated for f in yo
+        match graph(split().parallel("yi"), compute().store_at(&g, "yo")) {
+            Err(PrismError::InvalidSchedule { func, .. }) => assert_eq!(func, "f"),
+            _ => panic!("Expected an invalid schedule")
+        }
+        assert!(graph(split().parallel("yi"), compute().store_at(&g, "yi")).is_ok());
+        assert!(graph(split().parallel("yo"), compute().store_at(&g, "yo")).is_ok());
+    }
+
+    #[test]
+    fn test_self_reference() {
+        let (x, y) = (Var::X, Var::Y);
+        let f = Func::new("f", Func::new("f", Definition::Const(0)).at(x, y) + 1);
+        let sched = schedule(&[&f]);
+        let error = Graph::new("graph", vec![f], sched).err();
+        assert_eq!(error, Some(PrismError::SelfReference("f".to_string())));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let (x, y) = (Var::X, Var::Y);
+        let f = Func::new("f", Func::new("g", Definition::Const(0)).at(x, y) + 1);
+        let g = Func::new("g", f.at(x, y) * 2);
+        let sched = schedule(&[&f, &g]);
+        let error = Graph::new("graph", vec![f, g], sched).err();
+        assert_eq!(error, Some(PrismError::Cycle(vec!["f".to_string(), "g".to_string()])));
+    }
 }
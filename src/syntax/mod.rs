@@ -1,12 +1,18 @@
 //! The syntax used to represent image processing pipelines.
 
 pub use self::ast::*;
+pub use self::bounds::*;
 pub use self::dsl::*;
 pub use self::graph::*;
+pub use self::parse::*;
 pub use self::pretty_print::*;
+pub use self::schedule::*;
 
 mod ast;
+mod bounds;
 #[macro_use]
 mod dsl;
 mod graph;
+mod parse;
 mod pretty_print;
+mod schedule;
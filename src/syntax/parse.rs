@@ -0,0 +1,962 @@
+//! A parser for pipelines written as text. Funcs use the syntax produced by
+//! `PrettyPrint`, and are combined with declarations of a pipeline's inputs,
+//! params, outputs and schedules:
+//!
+//! ```text
+//! // Comments run to the end of the line
+//! input image
+//! input depth: u16
+//! param offset
+//!
+//! blur_h(x, y) = ((image(x - 1, y, clamp) + image(x, y)) + image(x + 1, y, clamp)) / 3
+//! blur_v(x, y) = (blur_h(x, y - 1) + blur_h(x, y) + blur_h(x, y + 1)) / 3 + offset
+//! scaled(x, y): u16 = depth(x, y) * blur_v(x, y)
+//!
+//! output blur_v
+//! schedule blur_h = by_row().compute_at(blur_v, yo)
+//! schedule blur_v = by_row().split(y, yo, yi, 8).vectorize(x, 8).parallel(yo)
+//! ```
+//!
+//! Statements are not separated by anything other than whitespace. Inputs and
+//! params must be declared before they are used, but funcs may be defined in any
+//! order. If no outputs are declared then the outputs are the funcs that are not
+//! read by any other func, as for `Graph::new`.
+//!
+//! Inputs and funcs have element type u8 unless another of `u16`, `i16`, `i32` or
+//! `f32` is given after their name. Writing `saturating` before the type of a func,
+//! as in `f(x, y): saturating u8 = ...`, gives the func a saturating store policy.
+//!
+//! Expressions are written as follows.
+//!
+//! | Syntax | Meaning |
+//! |---|---|
+//! | `image(x, y, clamp)` | An access with a boundary condition of `zero`, `constant(c)`, `clamp`, `mirror` or `wrap`. Accesses without one read from inputs as `Source::at` does, and from funcs as `Func::at` does |
+//! | `0.5`, `1e-3` | Literals containing a decimal point or an exponent are f32s |
+//! | `+`, `-`, `*`, `/`, `%`, `<<`, `>>`, `&`, `^`, `\|` | Operators, with the same precedence as in Rust. Integer quotients and remainders by zero are zero |
+//! | `min(a, b)`, `max(a, b)`, `abs(a)`, `absd(a, b)` | Minimum, maximum, absolute value and absolute difference |
+//! | `f32(a)` | Casts `a` to a type, which may be any element type |
+//! | `saturating_u8(a)` | Clamps `a` to the range of a type rather than wrapping it |
+//! | `clamp(v, lo, hi)` | Clamps `v` to lie between `lo` and `hi` |
+//! | `select(cond, a, b)` | `a` where `cond` holds and `b` elsewhere, without branching |
+//!
+//! The conditions of a `select` compare expressions using `==`, `<`, `<=`, `>` or `>=`,
+//! and combine comparisons using `&&`, `||` and `!`.
+//!
+//! Schedules are written as chains of calls to the `FuncSchedule` builder methods,
+//! and funcs or inputs with no schedule are computed by row at root.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto
+};
+use crate::{
+    error::PrismError,
+    syntax::{ast::*, graph::Graph, schedule::*}
+};
+
+/// The funcs, declarations and schedules read by `parse_pipeline`.
+#[derive(Clone)]
+pub struct Pipeline {
+    pub inputs: Vec<Source>,
+    pub params: Vec<Param>,
+    /// The funcs in the order they were defined.
+    pub funcs: Vec<Func>,
+    /// The declared outputs, which may be empty.
+    pub outputs: Vec<String>,
+    pub schedule: Schedule
+}
+
+impl Pipeline {
+    /// Creates a graph computing this pipeline's outputs.
+    pub fn graph(&self, name: &str) -> Result<Graph, PrismError> {
+        if self.outputs.is_empty() {
+            Graph::new(name, self.funcs.clone(), self.schedule.clone())
+        } else {
+            let outputs: Vec<&str> = self.outputs.iter().map(|o| o.as_str()).collect();
+            Graph::with_outputs(name, self.funcs.clone(), &outputs, self.schedule.clone())
+        }
+    }
+}
+
+/// Parses a pipeline written in the syntax described in the module documentation.
+pub fn parse_pipeline(text: &str) -> Result<Pipeline, PrismError> {
+    let mut parser = Parser::new(text, true)?;
+    let mut funcs: Vec<(Func, Position)> = vec![];
+    let mut outputs = vec![];
+    let mut schedules: Vec<(String, FuncSchedule, Position)> = vec![];
+
+    while *parser.peek() != Token::Eof {
+        let position = parser.position();
+        let declaration = match (parser.peek(), parser.peek_at(1)) {
+            (Token::Ident(keyword), Token::Ident(_)) => Some(keyword.clone()),
+            _ => None
+        };
+        match declaration.as_deref() {
+            Some("input") => {
+                parser.next();
+                let name = parser.declare()?;
+                let ty = if parser.eat(":") { parser.ty()? } else { Type::U8 };
+                parser.inputs.push(name.clone());
+                parser.input_types.insert(name, ty);
+            },
+            Some("param") => {
+                parser.next();
+                let name = parser.declare()?;
+                parser.params.push(name);
+            },
+            Some("output") => {
+                parser.next();
+                outputs.push(parser.ident()?);
+            },
+            Some("schedule") => {
+                parser.next();
+                let position = parser.position();
+                let name = parser.ident()?;
+                parser.expect("=")?;
+                schedules.push((name, parser.schedule()?, position));
+            },
+            _ => {
+                let func = parser.func()?;
+                if parser.is_declared(&func.name) || funcs.iter().any(|f| f.0.name == func.name) {
+                    return Err(position.error(format!("{} is already defined", func.name)));
+                }
+                funcs.push((func, position));
+            }
+        }
+    }
+
+    let func_names: HashSet<&String> = funcs.iter().map(|f| &f.0.name).collect();
+    for (source, position) in &parser.accessed {
+        if !func_names.contains(source) && !parser.inputs.contains(source) {
+            return Err(position.error(format!("{} is not a func or a declared input", source)));
+        }
+    }
+
+    let mut schedule = Schedule::new();
+    for (name, sched, position) in schedules {
+        if !func_names.contains(&name) && !parser.inputs.contains(&name) {
+            return Err(position.error(format!("Cannot schedule {} as it is not a func or an input", name)));
+        }
+        if schedule.func_schedules.contains_key(&name) {
+            return Err(position.error(format!("{} is scheduled more than once", name)));
+        }
+        schedule.func_schedules.insert(name, sched);
+    }
+    let names = funcs.iter().map(|f| &f.0.name).chain(parser.inputs.iter());
+    for name in names {
+        if !schedule.func_schedules.contains_key(name) {
+            schedule.func_schedules.insert(name.clone(), FuncSchedule::by_row());
+        }
+    }
+
+    Ok(Pipeline {
+        inputs: parser.inputs.iter().map(|i| Source::new(i).with_type(parser.input_types[i])).collect(),
+        params: parser.params.iter().map(|p| Param::new(p)).collect(),
+        funcs: funcs.into_iter().map(|f| f.0).collect(),
+        outputs,
+        schedule
+    })
+}
+
+/// Parses a single func definition, such as `f(x, y) = g(x + 1, y) * 2`. Any
+/// name not followed by arguments is treated as a param, and accesses without
+/// a boundary condition have none.
+pub fn parse_func(text: &str) -> Result<Func, PrismError> {
+    let mut parser = Parser::new(text, false)?;
+    let func = parser.func()?;
+    parser.expect_end()?;
+    Ok(func)
+}
+
+/// Parses the right hand side of a func definition, as for `parse_func`.
+pub fn parse_definition(text: &str) -> Result<Definition, PrismError> {
+    let mut parser = Parser::new(text, false)?;
+    let definition = parser.definition()?;
+    parser.expect_end()?;
+    Ok(definition)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    /// Integer literals are unsigned, and may be one larger than `i32::MAX`
+    /// so that `i32::MIN` can be written.
+    Int(i64),
+    /// Float literals are also unsigned.
+    Float(f32),
+    Symbol(&'static str),
+    Eof
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Ident(name) => name.clone(),
+            Token::Int(value) => value.to_string(),
+            Token::Float(value) => format!("{:?}", value),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+            Token::Eof => String::from("end of input")
+        }
+    }
+}
+
+/// Longer symbols are listed first, so that they take precedence over their prefixes.
+const SYMBOLS: &[&str] = &[
+    "==", "<<", ">>", "<=", ">=", "&&", "||", "<", ">", "(", ")", "{", "}", ",", ".", ":", "=", "+", "-", "*",
+    "/", "%", "&", "|", "^", "!"
+];
+
+/// A line and column in the text being parsed.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    line: usize,
+    column: usize
+}
+
+impl Position {
+    fn error(self, message: String) -> PrismError {
+        PrismError::Parse { line: self.line, column: self.column, message }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(Token, Position)>, PrismError> {
+    let mut tokens = vec![];
+    let mut position = Position { line: 1, column: 1 };
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = if c.is_whitespace() {
+            (None, c.len_utf8())
+        } else if rest.starts_with("//") {
+            (None, rest.find('\n').unwrap_or(rest.len()))
+        } else if c.is_ascii_digit() {
+            let (len, is_float) = number_length(rest);
+            if is_float {
+                match rest[..len].parse::<f32>() {
+                    Ok(value) if value.is_finite() => (Some(Token::Float(value)), len),
+                    _ => return Err(position.error(format!("{} does not fit in an f32", &rest[..len])))
+                }
+            } else {
+                match rest[..len].parse::<i64>() {
+                    Ok(value) if value <= i32::MAX as i64 + 1 => (Some(Token::Int(value)), len),
+                    _ => return Err(position.error(format!("{} does not fit in an i32", &rest[..len])))
+                }
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+            (Some(Token::Ident(rest[..len].to_string())), len)
+        } else {
+            match SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+                Some(symbol) => (Some(Token::Symbol(symbol)), symbol.len()),
+                None => return Err(position.error(format!("Unexpected character '{}'", c)))
+            }
+        };
+        if let Some(token) = token {
+            tokens.push((token, position));
+        }
+        for c in rest[..len].chars() {
+            if c == '\n' {
+                position = Position { line: position.line + 1, column: 1 };
+            } else {
+                position.column += 1;
+            }
+        }
+        rest = &rest[len..];
+    }
+    tokens.push((Token::Eof, position));
+    Ok(tokens)
+}
+
+/// Returns the length of the numeric literal at the start of `text`, and whether it is a
+/// float. Floats have a fractional part, an exponent, or both, as in `1.5`, `2e-3` or `1.5e3`.
+fn number_length(text: &str) -> (usize, bool) {
+    let digits = |start: usize| text[start..].find(|c: char| !c.is_ascii_digit()).map_or(text.len(), |n| start + n);
+    let starts_with_digit = |start: usize| text[start..].starts_with(|c: char| c.is_ascii_digit());
+    let mut len = digits(0);
+    let mut is_float = false;
+    if text[len..].starts_with('.') && starts_with_digit(len + 1) {
+        len = digits(len + 1);
+        is_float = true;
+    }
+    if text[len..].starts_with(['e', 'E']) {
+        let sign = if text[len + 1..].starts_with(['-', '+']) { 1 } else { 0 };
+        if starts_with_digit(len + 1 + sign) {
+            len = digits(len + 1 + sign);
+            is_float = true;
+        }
+    }
+    (len, is_float)
+}
+
+/// Returns the element type with the given name, if there is one.
+fn type_named(name: &str) -> Option<Type> {
+    match name {
+        "u8" => Some(Type::U8),
+        "u16" => Some(Type::U16),
+        "i16" => Some(Type::I16),
+        "i32" => Some(Type::I32),
+        "f32" => Some(Type::F32),
+        _ => None
+    }
+}
+
+/// Returns the element type named by `name` with a `saturating_` prefix, if there is one.
+fn saturating_type_named(name: &str) -> Option<Type> {
+    name.strip_prefix("saturating_").and_then(type_named)
+}
+
+/// Returns the binary operator written as a function with the given name, if there is one.
+fn function_named(name: &str) -> Option<BinaryOp> {
+    [BinaryOp::Min, BinaryOp::Max, BinaryOp::AbsDiff].iter().cloned().find(|op| op.symbol() == name)
+}
+
+struct Parser {
+    tokens: Vec<(Token, Position)>,
+    /// The index of the next token to consume.
+    index: usize,
+    /// Whether inputs and params must be declared before they are used.
+    check_declarations: bool,
+    inputs: Vec<String>,
+    /// The element type of each input.
+    input_types: HashMap<String, Type>,
+    params: Vec<String>,
+    /// The source and position of each access parsed so far.
+    accessed: Vec<(String, Position)>
+}
+
+impl Parser {
+    fn new(text: &str, check_declarations: bool) -> Result<Parser, PrismError> {
+        Ok(Parser {
+            tokens: tokenize(text)?,
+            index: 0,
+            check_declarations,
+            inputs: vec![],
+            input_types: HashMap::new(),
+            params: vec![],
+            accessed: vec![]
+        })
+    }
+
+    fn peek(&self) -> &Token {
+        self.peek_at(0)
+    }
+
+    /// Returns the token `offset` places after the next token, or `Token::Eof`
+    /// if there are not enough tokens remaining.
+    fn peek_at(&self, offset: usize) -> &Token {
+        let index = std::cmp::min(self.index + offset, self.tokens.len() - 1);
+        &self.tokens[index].0
+    }
+
+    fn position(&self) -> Position {
+        self.tokens[self.index].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.peek().clone();
+        if token != Token::Eof {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, PrismError> {
+        let message = format!("Expected {} but found {}", expected, self.peek().describe());
+        Err(self.position().error(message))
+    }
+
+    /// Consumes the next token if it is `symbol`.
+    fn eat(&mut self, symbol: &str) -> bool {
+        match self.peek() {
+            Token::Symbol(s) if *s == symbol => {
+                self.index += 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), PrismError> {
+        if self.eat(symbol) { Ok(()) } else { self.unexpected(&format!("'{}'", symbol)) }
+    }
+
+    fn expect_end(&self) -> Result<(), PrismError> {
+        if *self.peek() == Token::Eof { Ok(()) } else { self.unexpected("end of input") }
+    }
+
+    fn ident(&mut self) -> Result<String, PrismError> {
+        match self.peek().clone() {
+            Token::Ident(name) => {
+                self.index += 1;
+                Ok(name)
+            },
+            _ => self.unexpected("a name")
+        }
+    }
+
+    /// Consumes the next token if it is the identifier `keyword`.
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Token::Ident(name) if name == keyword => {
+                self.index += 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.inputs.iter().chain(self.params.iter()).any(|n| n == name)
+    }
+
+    /// Parses the name of a new input or param.
+    fn declare(&mut self) -> Result<String, PrismError> {
+        let position = self.position();
+        let name = self.ident()?;
+        if self.is_declared(&name) {
+            return Err(position.error(format!("{} is already declared", name)));
+        }
+        Ok(name)
+    }
+
+    /// Parses an integer literal, which may be negative.
+    fn int(&mut self) -> Result<i32, PrismError> {
+        let position = self.position();
+        let negative = self.eat("-");
+        let value = match self.peek() {
+            Token::Int(value) => if negative { -*value } else { *value },
+            _ => return self.unexpected("an integer")
+        };
+        self.index += 1;
+        if value > i32::MAX as i64 {
+            return Err(position.error(format!("{} does not fit in an i32", value)));
+        }
+        Ok(value as i32)
+    }
+
+    /// Parses a float literal, which may be negative.
+    fn float(&mut self) -> Result<f32, PrismError> {
+        let negative = self.eat("-");
+        match self.peek() {
+            Token::Float(value) => {
+                let value = if negative { -*value } else { *value };
+                self.index += 1;
+                Ok(value)
+            },
+            _ => self.unexpected("a float")
+        }
+    }
+
+    /// Parses a non-negative integer literal.
+    fn factor(&mut self) -> Result<u32, PrismError> {
+        let position = self.position();
+        let value = self.int()?;
+        if value < 0 {
+            return Err(position.error(format!("Expected a non-negative integer but found {}", value)));
+        }
+        Ok(value as u32)
+    }
+
+    fn func(&mut self) -> Result<Func, PrismError> {
+        let name = self.ident()?;
+        self.expect("(")?;
+        if !self.eat_keyword("x") {
+            return self.unexpected("x");
+        }
+        self.expect(",")?;
+        if !self.eat_keyword("y") {
+            return self.unexpected("y");
+        }
+        self.expect(")")?;
+        let (ty, store_policy) = if self.eat(":") {
+            let store_policy = if self.eat_keyword("saturating") { StorePolicy::Saturate } else { StorePolicy::Wrap };
+            (self.ty()?, store_policy)
+        } else {
+            (Type::U8, StorePolicy::Wrap)
+        };
+        self.expect("=")?;
+        Ok(Func::new(&name, self.definition()?).with_type(ty).with_store_policy(store_policy))
+    }
+
+    /// Parses the name of an element type.
+    fn ty(&mut self) -> Result<Type, PrismError> {
+        let position = self.position();
+        let name = self.ident()?;
+        type_named(&name).ok_or_else(|| position.error(format!("Unknown type {}", name)))
+    }
+
+    /// Parses an expression. Binary operators have the same precedence as in Rust,
+    /// and operators of equal precedence associate to the left.
+    fn definition(&mut self) -> Result<Definition, PrismError> {
+        self.binary(&[("|", BinaryOp::BitOr)], Parser::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Definition, PrismError> {
+        self.binary(&[("^", BinaryOp::BitXor)], Parser::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Definition, PrismError> {
+        self.binary(&[("&", BinaryOp::BitAnd)], Parser::shift)
+    }
+
+    fn shift(&mut self) -> Result<Definition, PrismError> {
+        self.binary(&[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)], Parser::sum)
+    }
+
+    /// Parses a sequence of operands, each parsed by `operand`, separated by any of `ops`.
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        operand: fn(&mut Parser) -> Result<Definition, PrismError>
+    ) -> Result<Definition, PrismError> {
+        let mut definition = operand(self)?;
+        while let Some((_, op)) = ops.iter().find(|(symbol, _)| self.eat(symbol)) {
+            definition = Definition::Binary(*op, Box::new(definition), Box::new(operand(self)?));
+        }
+        Ok(definition)
+    }
+
+    /// Parses a sum of products.
+    fn sum(&mut self) -> Result<Definition, PrismError> {
+        let mut definition = self.product()?;
+        loop {
+            if self.eat("+") {
+                definition = Definition::Add(Box::new(definition), Box::new(self.product()?));
+            } else if self.eat("-") {
+                definition = Definition::Sub(Box::new(definition), Box::new(self.product()?));
+            } else {
+                return Ok(definition);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Definition, PrismError> {
+        let mut definition = self.primary()?;
+        loop {
+            if self.eat("*") {
+                definition = Definition::Mul(Box::new(definition), Box::new(self.primary()?));
+            } else if self.eat("/") {
+                definition = Definition::Div(Box::new(definition), Box::new(self.primary()?));
+            } else if self.eat("%") {
+                let rhs = Box::new(self.primary()?);
+                definition = Definition::Binary(BinaryOp::Rem, Box::new(definition), rhs);
+            } else {
+                return Ok(definition);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Definition, PrismError> {
+        if self.eat("(") {
+            let definition = self.definition()?;
+            self.expect(")")?;
+            return Ok(definition);
+        }
+        if self.eat_keyword("if") {
+            return self.condition();
+        }
+        let position = self.position();
+        match (self.peek().clone(), self.peek_at(1)) {
+            (Token::Symbol("-"), Token::Float(_)) | (Token::Float(_), _) => Ok(Definition::FloatConst(self.float()?)),
+            (Token::Symbol("-"), _) | (Token::Int(_), _) => Ok(Definition::Const(self.int()?)),
+            (Token::Ident(name), Token::Symbol("(")) if type_named(&name).is_some() => {
+                self.index += 2;
+                let [definition] = self.arguments()?;
+                Ok(Definition::Cast(type_named(&name).unwrap(), Box::new(definition)))
+            },
+            (Token::Ident(name), Token::Symbol("(")) if saturating_type_named(&name).is_some() => {
+                self.index += 2;
+                let [definition] = self.arguments()?;
+                Ok(Definition::SaturatingCast(saturating_type_named(&name).unwrap(), Box::new(definition)))
+            },
+            (Token::Ident(name), Token::Symbol("(")) if name == "clamp" => {
+                self.index += 2;
+                let [value, lo, hi] = self.arguments()?;
+                Ok(Definition::Clamp(Box::new(value), Box::new(lo), Box::new(hi)))
+            },
+            (Token::Ident(name), Token::Symbol("(")) if name == "select" => {
+                self.index += 2;
+                let cond = self.bool_expr()?;
+                self.expect(",")?;
+                let [if_true, if_false] = self.arguments()?;
+                Ok(Definition::Select(Box::new(cond), Box::new(if_true), Box::new(if_false)))
+            },
+            (Token::Ident(name), Token::Symbol("(")) if name == "abs" => {
+                self.index += 2;
+                let [definition] = self.arguments()?;
+                Ok(Definition::Abs(Box::new(definition)))
+            },
+            (Token::Ident(name), Token::Symbol("(")) if function_named(&name).is_some() => {
+                self.index += 2;
+                let [l, r] = self.arguments()?;
+                Ok(Definition::Binary(function_named(&name).unwrap(), Box::new(l), Box::new(r)))
+            },
+            (Token::Ident(name), Token::Symbol("(")) => {
+                self.index += 2;
+                self.access(name, position)
+            },
+            (Token::Ident(name), _) => {
+                if name == "x" || name == "y" {
+                    return Err(position.error(format!("{} can only be used in the coordinates of an access", name)));
+                }
+                if self.check_declarations && !self.params.contains(&name) {
+                    return Err(position.error(format!("{} is not a declared param", name)));
+                }
+                self.index += 1;
+                Ok(Definition::Param(name))
+            },
+            _ => self.unexpected("an expression")
+        }
+    }
+
+    /// Parses the `N` comma separated arguments of a function, and the closing parenthesis.
+    fn arguments<const N: usize>(&mut self) -> Result<[Definition; N], PrismError> {
+        let mut arguments = vec![];
+        for i in 0..N {
+            if i > 0 {
+                self.expect(",")?;
+            }
+            arguments.push(self.definition()?);
+        }
+        self.expect(")")?;
+        Ok(arguments.try_into().unwrap())
+    }
+
+    /// Parses the remainder of a condition, after the `if` keyword.
+    fn condition(&mut self) -> Result<Definition, PrismError> {
+        let lhs = self.definition()?;
+        let cmp = self.comparison()?;
+        let rhs = self.definition()?;
+        self.expect("{")?;
+        let if_true = self.definition()?;
+        self.expect("}")?;
+        if !self.eat_keyword("else") {
+            return self.unexpected("else");
+        }
+        self.expect("{")?;
+        let if_false = self.definition()?;
+        self.expect("}")?;
+        Ok(Definition::Cond(Condition::new(cmp, lhs, rhs, if_true, if_false)))
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, PrismError> {
+        let cmp = match self.peek() {
+            Token::Symbol("==") => Comparison::EQ,
+            Token::Symbol(">") => Comparison::GT,
+            Token::Symbol(">=") => Comparison::GTE,
+            Token::Symbol("<") => Comparison::LT,
+            Token::Symbol("<=") => Comparison::LTE,
+            _ => return self.unexpected("a comparison")
+        };
+        self.index += 1;
+        Ok(cmp)
+    }
+
+    /// Parses a boolean expression. As in Rust, `!` binds more tightly than `&&`,
+    /// which binds more tightly than `||`.
+    fn bool_expr(&mut self) -> Result<BoolExpr, PrismError> {
+        let mut expr = self.bool_and()?;
+        while self.eat("||") {
+            expr = BoolExpr::Or(Box::new(expr), Box::new(self.bool_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn bool_and(&mut self) -> Result<BoolExpr, PrismError> {
+        let mut expr = self.bool_primary()?;
+        while self.eat("&&") {
+            expr = BoolExpr::And(Box::new(expr), Box::new(self.bool_primary()?));
+        }
+        Ok(expr)
+    }
+
+    fn bool_primary(&mut self) -> Result<BoolExpr, PrismError> {
+        if self.eat("!") {
+            return Ok(BoolExpr::Not(Box::new(self.bool_primary()?)));
+        }
+        // A parenthesis may start either a boolean expression or the left hand
+        // side of a comparison, so try the former and backtrack if it fails
+        let (index, accessed) = (self.index, self.accessed.len());
+        if self.eat("(") {
+            if let Ok(expr) = self.bool_expr() {
+                if self.eat(")") {
+                    return Ok(expr);
+                }
+            }
+            self.index = index;
+            self.accessed.truncate(accessed);
+        }
+        let lhs = self.definition()?;
+        let cmp = self.comparison()?;
+        let rhs = self.definition()?;
+        Ok(BoolExpr::Compare(cmp, Box::new(lhs), Box::new(rhs)))
+    }
+
+    /// Parses the arguments of an access, after the opening parenthesis.
+    fn access(&mut self, source: String, position: Position) -> Result<Definition, PrismError> {
+        let x = self.var_expr()?;
+        self.expect(",")?;
+        let y = self.var_expr()?;
+        let boundary = if self.eat(",") { Some(self.boundary()?) } else { None };
+        self.expect(")")?;
+        let boundary = match boundary {
+            None if self.inputs.contains(&source) => Some(Boundary::Zero),
+            _ => boundary
+        };
+        // Accesses to funcs are given the type of the func when the graph is created
+        let ty = self.input_types.get(&source).cloned().unwrap_or(Type::U8);
+        self.accessed.push((source.clone(), position));
+        Ok(Definition::Access(Access { source, x, y, boundary, ty }))
+    }
+
+    fn boundary(&mut self) -> Result<Boundary, PrismError> {
+        let position = self.position();
+        let boundary = match self.ident()?.as_str() {
+            "zero" => Boundary::Zero,
+            "constant" => {
+                self.expect("(")?;
+                let value = self.int()?;
+                self.expect(")")?;
+                Boundary::Constant(value)
+            },
+            "clamp" => Boundary::Clamp,
+            "mirror" => Boundary::Mirror,
+            "wrap" => Boundary::Wrap,
+            name => return Err(position.error(format!("Unknown boundary condition {}", name)))
+        };
+        Ok(boundary)
+    }
+
+    fn var_expr(&mut self) -> Result<VarExpr, PrismError> {
+        let mut expr = self.var_product()?;
+        loop {
+            if self.eat("+") {
+                expr = VarExpr::Add(Box::new(expr), Box::new(self.var_product()?));
+            } else if self.eat("-") {
+                expr = VarExpr::Sub(Box::new(expr), Box::new(self.var_product()?));
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn var_product(&mut self) -> Result<VarExpr, PrismError> {
+        let mut expr = self.var_primary()?;
+        while self.eat("*") {
+            expr = VarExpr::Mul(Box::new(expr), Box::new(self.var_primary()?));
+        }
+        Ok(expr)
+    }
+
+    fn var_primary(&mut self) -> Result<VarExpr, PrismError> {
+        if self.eat("(") {
+            let expr = self.var_expr()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        if self.eat_keyword("x") {
+            return Ok(VarExpr::Var(Var::X));
+        }
+        if self.eat_keyword("y") {
+            return Ok(VarExpr::Var(Var::Y));
+        }
+        match self.peek() {
+            Token::Symbol("-") | Token::Int(_) => Ok(VarExpr::Const(self.int()?)),
+            _ => self.unexpected("x, y or an integer")
+        }
+    }
+
+    /// Parses a chain of calls to `FuncSchedule` methods, starting
+    /// from `by_row()` or `by_column()`.
+    fn schedule(&mut self) -> Result<FuncSchedule, PrismError> {
+        let position = self.position();
+        let mut sched = match self.ident()?.as_str() {
+            "by_row" => FuncSchedule::by_row(),
+            "by_column" => FuncSchedule::by_column(),
+            _ => return Err(position.error(String::from("Expected by_row or by_column")))
+        };
+        self.expect("(")?;
+        self.expect(")")?;
+        while self.eat(".") {
+            let position = self.position();
+            let method = self.ident()?;
+            self.expect("(")?;
+            let result = match method.as_str() {
+                "compute_root" => Ok(sched.compute_root()),
+                "compute_inline" => Ok(sched.compute_inline()),
+                "store_root" => Ok(sched.store_root()),
+                "compute_at" | "store_at" => {
+                    let func = self.ident()?;
+                    self.expect(",")?;
+                    let level = LoopLevel::At { func, var: LoopVar::new(&self.ident()?) };
+                    if method == "compute_at" {
+                        sched.compute_level = level;
+                    } else {
+                        sched.store_level = Some(level);
+                    }
+                    Ok(sched)
+                },
+                "split" => {
+                    let names = self.names(3)?;
+                    self.expect(",")?;
+                    sched.try_split(names[0].as_str(), names[1].as_str(), names[2].as_str(), self.factor()?)
+                },
+                "tile" => {
+                    let n = self.names(6)?;
+                    self.expect(",")?;
+                    let width = self.factor()?;
+                    self.expect(",")?;
+                    let height = self.factor()?;
+                    sched.try_tile(
+                        n[0].as_str(), n[1].as_str(), n[2].as_str(), n[3].as_str(), n[4].as_str(), n[5].as_str(),
+                        width, height
+                    )
+                },
+                "vectorize" => {
+                    let var = self.ident()?;
+                    self.expect(",")?;
+                    sched.try_vectorize(var.as_str(), self.factor()?)
+                },
+                "parallel" => sched.try_parallel(self.ident()?.as_str()),
+                "unroll" => {
+                    let var = self.ident()?;
+                    self.expect(",")?;
+                    sched.try_unroll(var.as_str(), self.factor()?)
+                },
+                _ => return Err(position.error(format!("Unknown schedule method {}", method)))
+            };
+            sched = result.map_err(|message| position.error(message))?;
+            self.expect(")")?;
+        }
+        Ok(sched)
+    }
+
+    /// Parses `count` comma separated names.
+    fn names(&mut self, count: usize) -> Result<Vec<String>, PrismError> {
+        let mut names = vec![self.ident()?];
+        while names.len() < count {
+            self.expect(",")?;
+            names.push(self.ident()?);
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::{dsl::select, pretty_print::*};
+
+    fn assert_round_trip(func: &Func) {
+        let printed = func.pretty_print();
+        let parsed = parse_func(&printed).unwrap_or_else(|e| panic!("{}: {}", printed, e));
+        assert_eq!(parsed.pretty_print(), printed);
+    }
+
+    #[test]
+    fn test_parse_func_round_trip() {
+        let (x, y) = (Var::X, Var::Y);
+        source!(g);
+        param!(p);
+        func!(f = (g.at(x + 1, 2 * (y - 1)) + g.at(x - 1, y) * 3) / -2);
+        assert_round_trip(&f);
+        func!(h = f.at_with_boundary(x, y + 1, Boundary::Constant(-7)) - &p);
+        assert_round_trip(&h);
+        let cond = Condition::new(Comparison::GTE, g.at(x, y) + 1, Definition::Param(p.name.clone()), f.at(x, y), Definition::Const(0));
+        assert_round_trip(&Func::new("k", Definition::Cond(cond)));
+        func!(m = (g.at(x, y) * 0.25 + -1.5e-7).to_int() - (g.at(x, y) * 1e20).to_float() / 3.0);
+        assert_round_trip(&m);
+        let n = Func::new("n", (g.at(x, y).saturating_cast::<i16>() * 300).clamp(-2, 7).cast::<u16>())
+            .with_type(Type::I32)
+            .with_store_policy(StorePolicy::Saturate);
+        assert_round_trip(&n);
+        let a = g.at(x, y);
+        let r = ((a.clone() & 15) | (a.clone() >> 2)) ^ (a.clone() << 3) % 7;
+        func!(q = r + a.clone().min(2).max(&p) - (a.clone() - 128).abs().absd(a * 0.5));
+        assert_round_trip(&q);
+        let a = g.at(x, y);
+        let cond = (a.clone().gt(3).and(!a.clone().le(&p))).or(!(a.clone() * 2).equals(a.clone() - 1).or(a.clone().not_equals(4)));
+        func!(s = select(cond, a.clone(), select(a.clone().lt(1.5), 0, a)));
+        assert_round_trip(&s);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let parsed = parse_definition("a(x, y) | 1 ^ 2 & 3 << 4 + 5 * 6 % 7").unwrap();
+        assert_eq!(parsed.pretty_print(), "a(x, y) | (1 ^ (2 & (3 << (4 + ((5 * 6) % 7)))))");
+        let parsed = parse_definition("min(a(x, y), 2) - abs(3) >> absd(4, 5)").unwrap();
+        assert_eq!(parsed.pretty_print(), "(min(a(x, y), 2) - abs(3)) >> absd(4, 5)");
+        let parsed = parse_definition("select(!(1 > 2) || (3 + 4) * 5 == 6 && 7 < 8, 9, 10)").unwrap();
+        assert_eq!(parsed.pretty_print(), "select(!(1 > 2) || ((((3 + 4) * 5) == 6) && (7 < 8)), 9, 10)");
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let text = "
+            // A separable blur
+            input image
+            param offset
+
+            blur_v(x, y) = (blur_h(x, y - 1) + blur_h(x, y) + blur_h(x, y + 1)) / 3 + offset
+            blur_h(x, y) = (image(x - 1, y, clamp) + image(x, y) + image(x + 1, y, clamp)) / 3
+
+            output blur_v
+            schedule blur_h = by_row().compute_at(blur_v, yo)
+            schedule blur_v = by_row().split(y, yo, yi, 8).vectorize(x, 8).parallel(yo)
+        ";
+        let pipeline = parse_pipeline(text).unwrap();
+        assert_eq!(pipeline.inputs, vec![Source::new("image")]);
+        assert_eq!(pipeline.params, vec![Param::new("offset")]);
+        assert_eq!(
+            pipeline.funcs[1].pretty_print(),
+            "blur_h(x, y) = ((image(x - 1, y, clamp) + image(x, y)) + image(x + 1, y, clamp)) / 3"
+        );
+        let blur_v = &pipeline.funcs[0];
+        assert_eq!(
+            pipeline.schedule.func_schedules["blur_v"],
+            FuncSchedule::by_row().split(Var::Y, "yo", "yi", 8).vectorize(Var::X, 8).parallel("yo")
+        );
+        assert_eq!(pipeline.schedule.func_schedules["blur_h"], FuncSchedule::by_row().compute_at(blur_v, "yo"));
+        assert_eq!(pipeline.schedule.func_schedules["image"], FuncSchedule::by_row());
+
+        let graph = pipeline.graph("blur").unwrap();
+        assert_eq!(graph.outputs(), &["blur_v".to_string()]);
+        assert_eq!(graph.params(), &["offset".to_string()]);
+
+        let empty = parse_pipeline("input image").unwrap();
+        assert_eq!(empty.graph("empty").err(), Some(PrismError::NoOutputs));
+    }
+
+    #[test]
+    fn test_parse_element_types() {
+        let text = "
+            input image: i16
+            gradient(x, y): f32 = image(x + 1, y) - image(x, y)
+            output gradient
+        ";
+        let pipeline = parse_pipeline(text).unwrap();
+        assert_eq!(pipeline.inputs, vec![Source::new("image").with_type(Type::I16)]);
+        assert_eq!(pipeline.funcs[0].pretty_print(), "gradient(x, y): f32 = image(x + 1, y) - image(x, y)");
+        let graph = pipeline.graph("gradient").unwrap();
+        assert_eq!(graph.element_type("image"), Some(Type::I16));
+        assert_eq!(graph.element_type("gradient"), Some(Type::F32));
+        assert!(parse_pipeline("input image: u7").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text| match parse_pipeline(text) {
+            Err(PrismError::Parse { line, column, .. }) => (line, column),
+            _ => panic!("Expected a parse error for {}", text)
+        };
+        // Undeclared param
+        assert_eq!(error("input a\nf(x, y) = a(x, y) + p"), (2, 21));
+        // Unknown source
+        assert_eq!(error("f(x, y) = g(x, y)"), (1, 11));
+        // Missing closing brace
+        assert_eq!(error("f(x, y) = if 1 > 2 {3 else {4}"), (1, 23));
+        // Invalid schedule
+        assert_eq!(error("f(x, y) = 1\nschedule f = by_row().vectorize(y, 4)"), (2, 23));
+        // Float too large for an f32
+        assert_eq!(error("f(x, y) = 1e39"), (1, 11));
+    }
+}
@@ -0,0 +1,429 @@
+//! Schedules specify how the funcs in a `Graph` are computed.
+
+use std::{
+    collections::HashMap,
+    fmt
+};
+use crate::syntax::ast::*;
+
+#[derive(Clone)]
+pub struct Schedule {
+    /// Schedules indexed by function name.
+    pub(crate) func_schedules: HashMap<String, FuncSchedule>
+}
+
+impl Schedule {
+    pub fn new() -> Schedule {
+        Schedule { func_schedules: HashMap::new() }
+    }
+
+    pub fn add_func(&mut self, func: &Func, sched: FuncSchedule) {
+        self.func_schedules.insert(func.name.to_string(), sched);
+    }
+
+    pub fn add_source(&mut self, func: &Source, sched: FuncSchedule) {
+        self.func_schedules.insert(func.name.to_string(), sched);
+    }
+
+    pub fn get_func_schedule(&self, func: &Func) -> &FuncSchedule {
+        self.func_schedules.get(&func.name).unwrap()
+    }
+
+    pub fn get_source_schedule(&self, func: &Source) -> &FuncSchedule {
+        self.func_schedules.get(&func.name).unwrap()
+    }
+}
+
+/// A variable in a func's loop nest. This is either one of the
+/// pure variables `Var::X` and `Var::Y` or a variable introduced
+/// by splitting another loop variable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LoopVar {
+    pub(crate) name: String
+}
+
+impl LoopVar {
+    pub fn new(name: &str) -> LoopVar {
+        LoopVar { name: name.to_string() }
+    }
+
+    /// Returns the pure variable with this name, if there is one.
+    pub fn as_var(&self) -> Option<Var> {
+        match self.name.as_str() {
+            "x" => Some(Var::X),
+            "y" => Some(Var::Y),
+            _ => None
+        }
+    }
+}
+
+impl From<Var> for LoopVar {
+    fn from(var: Var) -> LoopVar {
+        LoopVar::new(&var.to_string())
+    }
+}
+
+impl From<&str> for LoopVar {
+    fn from(name: &str) -> LoopVar {
+        LoopVar::new(name)
+    }
+}
+
+impl fmt::Display for LoopVar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Replaces the loop over `var` by two nested loops, with
+/// `var = outer * factor + inner`. The inner loop runs over
+/// `factor` values, except on the final iteration of the outer
+/// loop when the extent of `var` is not a multiple of `factor`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Split {
+    pub(crate) var: LoopVar,
+    pub(crate) outer: LoopVar,
+    pub(crate) inner: LoopVar,
+    pub(crate) factor: u32
+}
+
+/// A point in a pipeline's loop nests at which a func can be computed or stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoopLevel {
+    /// Outside of all loops.
+    Root,
+    /// Inside the loop over `var` in the loop nest of `func`.
+    At { func: String, var: LoopVar },
+    /// Not computed separately. The func's definition is substituted
+    /// into each of its consumers and no storage is allocated for it.
+    Inline
+}
+
+impl LoopLevel {
+    pub fn at<V: Into<LoopVar>>(func: &Func, var: V) -> LoopLevel {
+        LoopLevel::At { func: func.name.clone(), var: var.into() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuncSchedule {
+    /// The variables to loop over, from outermost to innermost.
+    pub(crate) variables: Vec<LoopVar>,
+    /// The splits used to create the loop variables, in the
+    /// order they were applied.
+    pub(crate) splits: Vec<Split>,
+    /// Determines how this func's loops nest inside those of its consumers.
+    /// Each time the loop at this level is entered we compute the region of
+    /// this func required by the remaining iterations of its consumer.
+    pub(crate) compute_level: LoopLevel,
+    /// The point in the loop nest where storage for this func is allocated.
+    /// Defaults to `compute_level`.
+    pub(crate) store_level: Option<LoopLevel>,
+    /// The innermost loop variable and the number of its values to compute
+    /// at once, if this func's innermost loop is vectorized.
+    pub(crate) vectorized: Option<(LoopVar, u32)>,
+    /// Loop variables whose iterations are run concurrently on a pool of threads.
+    pub(crate) parallel: Vec<LoopVar>,
+    /// Loop variables whose loop bodies are replicated, and the number of copies
+    /// of the body to emit per iteration.
+    pub(crate) unrolled: Vec<(LoopVar, u32)>
+}
+
+impl FuncSchedule {
+    /// By default the y variable is iterated in the outer loop
+    pub fn by_row() -> FuncSchedule {
+        FuncSchedule::with_variables(vec![Var::Y.into(), Var::X.into()])
+    }
+
+    /// Iterates over the x variable in the outer loop
+    pub fn by_column() -> FuncSchedule {
+        FuncSchedule::with_variables(vec![Var::X.into(), Var::Y.into()])
+    }
+
+    fn with_variables(variables: Vec<LoopVar>) -> FuncSchedule {
+        FuncSchedule {
+            variables,
+            splits: vec![],
+            compute_level: LoopLevel::Root,
+            store_level: None,
+            vectorized: None,
+            parallel: vec![],
+            unrolled: vec![]
+        }
+    }
+
+    /// Computes the whole required region of this func before any of its consumers
+    /// are computed. This is the default.
+    pub fn compute_root(mut self) -> FuncSchedule {
+        self.compute_level = LoopLevel::Root;
+        self
+    }
+
+    /// Computes this func inside the loop over `var` of `consumer`, which must be
+    /// one of `consumer`'s loop variables. Each iteration of this loop computes
+    /// only the region of this func that is needed by that iteration.
+    pub fn compute_at<V: Into<LoopVar>>(mut self, consumer: &Func, var: V) -> FuncSchedule {
+        self.compute_level = LoopLevel::at(consumer, var);
+        self
+    }
+
+    /// Substitutes the definition of this func into each of its consumers,
+    /// so that its values are recomputed wherever they are used.
+    pub fn compute_inline(mut self) -> FuncSchedule {
+        self.compute_level = LoopLevel::Inline;
+        self
+    }
+
+    /// Allocates storage for this func before any loops are entered.
+    pub fn store_root(mut self) -> FuncSchedule {
+        self.store_level = Some(LoopLevel::Root);
+        self
+    }
+
+    /// Allocates storage for this func inside the loop over `var` of `consumer`.
+    /// This must be at or outside the level at which this func is computed.
+    pub fn store_at<V: Into<LoopVar>>(mut self, consumer: &Func, var: V) -> FuncSchedule {
+        self.store_level = Some(LoopLevel::at(consumer, var));
+        self
+    }
+
+    pub(crate) fn store_level(&self) -> &LoopLevel {
+        self.store_level.as_ref().unwrap_or(&self.compute_level)
+    }
+
+    /// Splits the loop over `var` into an outer loop over `outer` and an
+    /// inner loop over `inner`, which replace `var` in the loop nest.
+    /// Panics if `var` is not a current loop variable, if either new name
+    /// is already in use or if `factor` is zero.
+    pub fn split<V, O, I>(self, var: V, outer: O, inner: I, factor: u32) -> FuncSchedule
+    where
+        V: Into<LoopVar>,
+        O: Into<LoopVar>,
+        I: Into<LoopVar>
+    {
+        self.try_split(var, outer, inner, factor).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As for `split`, but returns an error rather than panicking.
+    pub(crate) fn try_split<V, O, I>(mut self, var: V, outer: O, inner: I, factor: u32) -> Result<FuncSchedule, String>
+    where
+        V: Into<LoopVar>,
+        O: Into<LoopVar>,
+        I: Into<LoopVar>
+    {
+        let (var, outer, inner) = (var.into(), outer.into(), inner.into());
+        if factor == 0 {
+            return Err(format!("Cannot split {} by a factor of zero", var));
+        }
+        if let Some((vectorized, _)) = &self.vectorized {
+            if *vectorized == var {
+                return Err(format!("Cannot split {} as it has been vectorized", var));
+            }
+        }
+        if self.parallel.contains(&var) {
+            return Err(format!("Cannot split {} as it has been parallelized", var));
+        }
+        if self.unroll_factor(&var) != 1 {
+            return Err(format!("Cannot split {} as it has been unrolled", var));
+        }
+        let position = match self.variables.iter().position(|v| *v == var) {
+            Some(p) => p,
+            None => return Err(format!("Cannot split {} as it is not a loop variable", var))
+        };
+        for name in &[&outer, &inner] {
+            if outer == inner || self.is_defined(name) {
+                return Err(format!("Cannot split {} into {} and {} as {} is already in use", var, outer, inner, name));
+            }
+        }
+        self.variables.splice(position..position + 1, vec![outer.clone(), inner.clone()]);
+        self.splits.push(Split { var, outer, inner, factor });
+        Ok(self)
+    }
+
+    /// Splits `x` and `y` by `width` and `height` respectively, and reorders
+    /// the resulting loops so that a `width` by `height` tile is computed for
+    /// each iteration of the outer loops `yo` and `xo`, i.e. the loop nest is
+    /// `yo, xo, yi, xi` from outermost to innermost.
+    // The arguments mirror Halide's `tile`, and naming each variable at the
+    // call site reads better than grouping them into a struct
+    #[allow(clippy::too_many_arguments)]
+    pub fn tile<V, W>(
+        self,
+        x: V,
+        y: V,
+        xo: W,
+        yo: W,
+        xi: W,
+        yi: W,
+        width: u32,
+        height: u32
+    ) -> FuncSchedule
+    where
+        V: Into<LoopVar>,
+        W: Into<LoopVar>
+    {
+        self.try_tile(x, y, xo, yo, xi, yi, width, height).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As for `tile`, but returns an error rather than panicking.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn try_tile<V, W>(
+        self,
+        x: V,
+        y: V,
+        xo: W,
+        yo: W,
+        xi: W,
+        yi: W,
+        width: u32,
+        height: u32
+    ) -> Result<FuncSchedule, String>
+    where
+        V: Into<LoopVar>,
+        W: Into<LoopVar>
+    {
+        let (xo, yo, xi, yi) = (xo.into(), yo.into(), xi.into(), yi.into());
+        let mut sched = self
+            .try_split(x, xo.clone(), xi.clone(), width)?
+            .try_split(y, yo.clone(), yi.clone(), height)?;
+        let tiled = [yo, xo, yi, xi];
+        let position = sched.variables.iter().position(|v| tiled.contains(v)).unwrap();
+        sched.variables.retain(|v| !tiled.contains(v));
+        sched.variables.splice(position..position, tiled.iter().cloned());
+        Ok(sched)
+    }
+
+    /// Computes `width` consecutive values of `var` at once using SIMD instructions.
+    /// `var` must be the innermost loop variable, and must either be a pure variable
+    /// or the inner variable of a split. Iterations near the boundary of the image that
+    /// require bounds checks, and any remainder when the number of other iterations is
+    /// not a multiple of `width`, are computed one at a time.
+    pub fn vectorize<V: Into<LoopVar>>(self, var: V, width: u32) -> FuncSchedule {
+        self.try_vectorize(var, width).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As for `vectorize`, but returns an error rather than panicking.
+    pub(crate) fn try_vectorize<V: Into<LoopVar>>(mut self, var: V, width: u32) -> Result<FuncSchedule, String> {
+        let var = var.into();
+        if width == 0 {
+            return Err(format!("Cannot vectorize {} with a width of zero", var));
+        }
+        if self.variables.last() != Some(&var) {
+            return Err(format!("Cannot vectorize {} as it is not the innermost loop variable", var));
+        }
+        self.vectorized = Some((var, width));
+        Ok(self)
+    }
+
+    /// Runs the iterations of the loop over `var` concurrently on a pool of
+    /// worker threads. Iterations must write to disjoint regions of any buffer
+    /// they share, so funcs computed inside this loop must also be stored
+    /// inside it, and graphs whose funcs are not are rejected. An innermost
+    /// parallel loop is neither vectorized nor partitioned to avoid bounds checks.
+    pub fn parallel<V: Into<LoopVar>>(self, var: V) -> FuncSchedule {
+        self.try_parallel(var).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As for `parallel`, but returns an error rather than panicking.
+    pub(crate) fn try_parallel<V: Into<LoopVar>>(mut self, var: V) -> Result<FuncSchedule, String> {
+        let var = var.into();
+        if !self.variables.contains(&var) {
+            return Err(format!("Cannot parallelize {} as it is not a loop variable", var));
+        }
+        if !self.parallel.contains(&var) {
+            self.parallel.push(var);
+        }
+        Ok(self)
+    }
+
+    /// Whether the loop over `var` is run in parallel.
+    pub(crate) fn is_parallel(&self, var: &LoopVar) -> bool {
+        self.parallel.contains(var)
+    }
+
+    /// Emits `factor` copies of the body of the loop over `var` per iteration,
+    /// followed by a loop over any remaining iterations. Parallel loops are not
+    /// unrolled. If the innermost loop is vectorized then only iterations that
+    /// are not computed using vector instructions are unrolled.
+    pub fn unroll<V: Into<LoopVar>>(self, var: V, factor: u32) -> FuncSchedule {
+        self.try_unroll(var, factor).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// As for `unroll`, but returns an error rather than panicking.
+    pub(crate) fn try_unroll<V: Into<LoopVar>>(mut self, var: V, factor: u32) -> Result<FuncSchedule, String> {
+        let var = var.into();
+        if factor == 0 {
+            return Err(format!("Cannot unroll {} by a factor of zero", var));
+        }
+        if !self.variables.contains(&var) {
+            return Err(format!("Cannot unroll {} as it is not a loop variable", var));
+        }
+        self.unrolled.retain(|(v, _)| *v != var);
+        self.unrolled.push((var, factor));
+        Ok(self)
+    }
+
+    /// The number of copies of the body of the loop over `var` to emit per
+    /// iteration. This is 1 unless `var` has been unrolled.
+    pub(crate) fn unroll_factor(&self, var: &LoopVar) -> u32 {
+        self.unrolled.iter().find(|(v, _)| v == var).map_or(1, |(_, f)| *f)
+    }
+
+    /// Returns the split that replaced `var`, if `var` has been split.
+    pub(crate) fn split_of(&self, var: &LoopVar) -> Option<&Split> {
+        self.splits.iter().find(|s| s.var == *var)
+    }
+
+    /// Returns the split that introduced `var`, if `var` is not a pure variable.
+    pub(crate) fn split_creating(&self, var: &LoopVar) -> Option<&Split> {
+        self.splits.iter().find(|s| s.outer == *var || s.inner == *var)
+    }
+
+    /// Whether `var` is a pure variable or has been introduced by a split.
+    fn is_defined(&self, var: &LoopVar) -> bool {
+        var.as_var().is_some() || self.split_creating(var).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(sched: &FuncSchedule) -> Vec<String> {
+        sched.variables.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn test_split() {
+        let sched = FuncSchedule::by_row().split(Var::Y, "yo", "yi", 4);
+        assert_eq!(names(&sched), vec!["yo", "yi", "x"]);
+        let sched = sched.split("yi", "yio", "yii", 2);
+        assert_eq!(names(&sched), vec!["yo", "yio", "yii", "x"]);
+    }
+
+    #[test]
+    fn test_tile() {
+        let by_row = FuncSchedule::by_row().tile(Var::X, Var::Y, "xo", "yo", "xi", "yi", 8, 4);
+        assert_eq!(names(&by_row), vec!["yo", "xo", "yi", "xi"]);
+        let by_column = FuncSchedule::by_column().tile(Var::X, Var::Y, "xo", "yo", "xi", "yi", 8, 4);
+        assert_eq!(names(&by_column), vec!["yo", "xo", "yi", "xi"]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_vectorize_outer_variable() {
+        let _ = FuncSchedule::by_row().vectorize(Var::Y, 8);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_split_parallel_variable() {
+        let _ = FuncSchedule::by_row().parallel(Var::Y).split(Var::Y, "yo", "yi", 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_split_reused_name() {
+        let _ = FuncSchedule::by_row().split(Var::Y, "yo", "x", 4);
+    }
+}
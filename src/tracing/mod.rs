@@ -1,12 +1,14 @@
 //! Functions for recording reads from and writes to images and visualizing
 //! image processing pipelines.
 
-pub use self::global_trace::*;
 pub use self::replay::*;
 pub use self::trace::*;
+pub use self::trace_context::*;
 pub use self::trace_image::*;
+pub use self::tracer::*;
 
-mod global_trace;
 mod replay;
 mod trace;
-mod trace_image;
\ No newline at end of file
+mod trace_context;
+mod trace_image;
+mod tracer;
\ No newline at end of file
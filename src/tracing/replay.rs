@@ -1,6 +1,7 @@
 //! Functions for creating animations visualising traces of image reads and writes
 
 use std::{
+    cmp::min,
     collections::HashMap,
     convert::AsRef,
     io::Result,
@@ -138,7 +139,7 @@ pub fn replay(trace: &Trace) -> Vec<RgbImage> {
         active_regions: HashMap::new()
     };
 
-    for action in trace.actions.borrow().iter() {
+    for (_, action) in trace.actions.borrow().iter() {
         match action {
             Action::Read(id, x, y) => {
                 let (id, x, y) = (*id, *x, *y);
@@ -211,16 +212,16 @@ pub fn create_gif_palette() -> GifPalette {
         else if p == [255u8, 255u8, 0] {
             255
         }
-        else if p[0] == p[1] && p[1] == p[2] && p[0] <= 250  {
-            // Round down to even values in each channel
-            p[0] / 2
+        else if p[0] == p[1] && p[1] == p[2] {
+            // Round down to even values in each channel. Values above 250 are shown as 250
+            min(p[0], 250) / 2
         }
         else if p[0] == p[1] {
             // Check if this is a blue-tinted version of an accepted greyscale value
             let t = compute_tint(p[0]);
             let b = p[0] + t;
-            if b == p[2] && p[0] <= 250 {
-                p[0] / 2 + 126
+            if b == p[2] {
+                min(p[0], 250) / 2 + 126
             } else {
                 panic!("Invalid trace image RGB value {:?}", p)
             }
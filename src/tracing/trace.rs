@@ -1,8 +1,8 @@
 //! A `Trace` is a record of actions performed on images, to use for debugging or
 //! generating animated replays.
 
-use std::cell::RefCell;
-use crate::image::GrayImage;
+use std::cell::{Cell, RefCell};
+use crate::{image::GrayImage, tracing::Tracer};
 
 /// Used to highlight an image region when generating visualisations.
 /// Currently only used in the hand-written blur3x3 examples and doesn't
@@ -32,9 +32,29 @@ pub enum Action {
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct TraceId(pub usize);
 
+/// Identifies the thread that performed an action. The main thread has id 0,
+/// and the worker threads used to run parallel loops are numbered from 1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WorkerId(pub usize);
+
+thread_local! {
+    static CURRENT_WORKER: Cell<WorkerId> = Cell::new(WorkerId(0));
+}
+
+/// Returns the id of the worker running on the current thread.
+pub fn current_worker() -> WorkerId {
+    CURRENT_WORKER.with(|w| w.get())
+}
+
+/// Sets the id recorded for all actions subsequently traced on the current thread.
+pub fn set_current_worker(worker: WorkerId) {
+    CURRENT_WORKER.with(|w| w.set(worker));
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Trace {
-    pub actions: RefCell<Vec<Action>>,
+    /// Each action performed, along with the worker that performed it.
+    pub actions: RefCell<Vec<(WorkerId, Action)>>,
     pub initial_images: RefCell<Vec<GrayImage>>
 }
 
@@ -46,25 +66,32 @@ impl Trace {
         }
     }
 
-    pub fn create_trace_id(&self, initial_image: &GrayImage) -> TraceId {
+    fn push(&self, action: Action) {
+        self.actions.borrow_mut().push((current_worker(), action));
+    }
+}
+
+/// Records every action, along with the initial contents of each image.
+impl Tracer for Trace {
+    fn create_trace_id(&self, initial_image: &GrayImage) -> TraceId {
         let id = TraceId(self.initial_images.borrow().len());
         self.initial_images.borrow_mut().push(initial_image.clone());
         id
     }
 
-    pub fn trace_get(&self, id: TraceId, x: usize, y: usize) {
-        self.actions.borrow_mut().push(Action::Read(id, x, y));
+    fn read(&self, id: TraceId, x: usize, y: usize) {
+        self.push(Action::Read(id, x, y));
     }
 
-    pub fn trace_set(&self, id: TraceId, x: usize, y: usize, c: u8) {
-        self.actions.borrow_mut().push(Action::Write(id, x, y, c));
+    fn write(&self, id: TraceId, x: usize, y: usize, c: u8) {
+        self.push(Action::Write(id, x, y, c));
     }
 
-    pub fn trace_clear(&self, id: TraceId) {
-        self.actions.borrow_mut().push(Action::Clear(id));
+    fn clear(&self, id: TraceId) {
+        self.push(Action::Clear(id));
     }
 
-    pub fn trace_active(&self, id: TraceId, x: usize, y: usize, width: usize, height: usize) {
-        self.actions.borrow_mut().push(Action::Active(id, ActiveRegion { x, y, width, height }));
+    fn active(&self, id: TraceId, region: ActiveRegion) {
+        self.push(Action::Active(id, region));
     }
 }
@@ -0,0 +1,77 @@
+//! The state used to trace a single run of generated code. Code generated with tracing
+//! instrumentation is passed a pointer to a `TraceContext`, which it passes back to
+//! `log_read` and `log_write` along with the integer id of the buffer accessed.
+//! These report each access to the `Tracer` held by the context.
+
+use std::sync::Mutex;
+use libc::c_void;
+use crate::{image::*, tracing::*};
+
+pub struct TraceContext<'a> {
+    /// Parallel loops may log from several threads at once.
+    tracer: Mutex<&'a mut (dyn Tracer + Send)>,
+    /// The trace id and dimensions of the trace image for each buffer,
+    /// indexed by the buffer ids used in the generated code.
+    buffers: Vec<(TraceId, usize, usize)>
+}
+
+impl<'a> TraceContext<'a> {
+    /// Registers each of `images` with `tracer`. `images[i]` is the initial
+    /// contents of the buffer with id `i` in the generated code.
+    pub fn new(tracer: &'a mut (dyn Tracer + Send), images: &[&GrayImage]) -> TraceContext<'a> {
+        let buffers = images
+            .iter()
+            .map(|image| {
+                let (width, height) = image.dimensions();
+                (tracer.create_trace_id(image), width, height)
+            })
+            .collect();
+        TraceContext { tracer: Mutex::new(tracer), buffers }
+    }
+
+    /// The pointer to pass to generated code.
+    pub fn as_ptr(&self) -> *const c_void {
+        self as *const TraceContext as *const c_void
+    }
+
+    /// Looks up the trace id for the given buffer, returning `None` if (x, y) lies
+    /// outside its trace image. Funcs computed within the loops of their consumers
+    /// may be evaluated at locations outside the image being traced.
+    fn trace_target(&self, buffer: i32, x: i32, y: i32) -> Option<TraceId> {
+        let (id, width, height) = self.buffers[buffer as usize];
+        if x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height {
+            Some(id)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reports a read to the tracer of `context`. Does nothing if `context` is null.
+///
+/// # Safety
+///
+/// `context` must be null or point to a live `TraceContext`, as passed to generated code,
+/// and `buffer` must be the id of one of its buffers.
+#[no_mangle]
+pub unsafe extern "C" fn log_read(context: *const TraceContext, buffer: i32, x: i32, y: i32) {
+    if let Some(context) = context.as_ref() {
+        if let Some(id) = context.trace_target(buffer, x, y) {
+            context.tracer.lock().unwrap().read(id, x as usize, y as usize);
+        }
+    }
+}
+
+/// Reports a write to the tracer of `context`. Does nothing if `context` is null.
+///
+/// # Safety
+///
+/// As for `log_read`.
+#[no_mangle]
+pub unsafe extern "C" fn log_write(context: *const TraceContext, buffer: i32, x: i32, y: i32, c: u8) {
+    if let Some(context) = context.as_ref() {
+        if let Some(id) = context.trace_target(buffer, x, y) {
+            context.tracer.lock().unwrap().write(id, x as usize, y as usize, c);
+        }
+    }
+}
@@ -1,40 +1,46 @@
-//! Image and Factory implementations that record all reads and writes to a shared `Trace`.
+//! Image and Factory implementations that report all reads and writes to a shared `Tracer`.
 
 use std::rc::Rc;
 use crate::{image::*, tracing::*};
 
-pub struct TraceImageFactory {
-    pub trace: Rc<Trace>
+pub struct TraceImageFactory<T: Tracer = Trace> {
+    pub trace: Rc<T>
 }
 
-impl Factory for TraceImageFactory {
-    type Image = TraceImage;
+impl<T: Tracer> Factory for TraceImageFactory<T> {
+    type Image = TraceImage<T>;
 
-    fn create_image(&mut self, width: usize, height: usize) -> TraceImage {
+    fn create_image(&mut self, width: usize, height: usize) -> TraceImage<T> {
         TraceImage::new(self.trace.clone(), width, height)
     }
 }
 
-impl TraceImageFactory {
-    pub fn new() -> TraceImageFactory {
+impl TraceImageFactory<Trace> {
+    pub fn new() -> TraceImageFactory<Trace> {
+        TraceImageFactory::with_tracer(Trace::new())
+    }
+}
+
+impl<T: Tracer> TraceImageFactory<T> {
+    pub fn with_tracer(tracer: T) -> TraceImageFactory<T> {
         TraceImageFactory {
-            trace: Rc::new(Trace::new())
+            trace: Rc::new(tracer)
         }
     }
 
-    pub fn create_from_image(&mut self, image: &GrayImage) -> TraceImage {
+    pub fn create_from_image(&mut self, image: &GrayImage) -> TraceImage<T> {
         TraceImage::from_image(self.trace.clone(), image)
     }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
-pub struct TraceImage {
+pub struct TraceImage<T: Tracer = Trace> {
     image: GrayImage,
     trace_id: TraceId,
-    trace: Rc<Trace>
+    trace: Rc<T>
 }
 
-impl Image<u8> for TraceImage {
+impl<T: Tracer> Image<u8> for TraceImage<T> {
     fn width(&self) -> usize {
         self.image.width()
     }
@@ -44,17 +50,17 @@ impl Image<u8> for TraceImage {
     }
 
     fn get(&self, x: usize, y: usize) -> u8 {
-        self.trace.trace_get(self.trace_id, x, y);
+        self.trace.read(self.trace_id, x, y);
         self.image.get(x, y)
     }
 
     fn set(&mut self, x: usize, y: usize, c: u8) {
-        self.trace.trace_set(self.trace_id, x, y, c);
+        self.trace.write(self.trace_id, x, y, c);
         self.image.set(x, y, c);
     }
 
     fn clear(&mut self) {
-        self.trace.trace_clear(self.trace_id);
+        self.trace.clear(self.trace_id);
         self.image.clear();
     }
 
@@ -63,16 +69,16 @@ impl Image<u8> for TraceImage {
     }
 
     fn active(&self, x: usize, y: usize, active_width: usize, active_height: usize) {
-        self.trace.trace_active(self.trace_id, x, y, active_width, active_height);
+        self.trace.active(self.trace_id, ActiveRegion { x, y, width: active_width, height: active_height });
     }
 }
 
-impl TraceImage {
-    pub fn new(trace: Rc<Trace>, width: usize, height: usize) -> TraceImage {
+impl<T: Tracer> TraceImage<T> {
+    pub fn new(trace: Rc<T>, width: usize, height: usize) -> TraceImage<T> {
         Self::from_image(trace, &GrayImage::new(width, height))
     }
 
-    pub fn from_image(trace: Rc<Trace>, image: &GrayImage) -> TraceImage {
+    pub fn from_image(trace: Rc<T>, image: &GrayImage) -> TraceImage<T> {
         TraceImage {
             image: image.clone(),
             trace_id: trace.create_trace_id(image),
@@ -0,0 +1,250 @@
+//! The `Tracer` trait, which receives the reads and writes made by traced images and
+//! generated code, and some implementations of it which don't record every action.
+
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io::{BufWriter, Result, Write},
+    ops::Range,
+    path::Path
+};
+use crate::{image::GrayImage, tracing::*};
+
+/// Receives each action performed on a set of traced images.
+pub trait Tracer {
+    /// Registers a new image with the given initial contents and returns its id.
+    fn create_trace_id(&self, initial_image: &GrayImage) -> TraceId;
+
+    /// A pixel in an image has been read from.
+    fn read(&self, id: TraceId, x: usize, y: usize);
+
+    /// A pixel in an image has been written to.
+    fn write(&self, id: TraceId, x: usize, y: usize, c: u8);
+
+    /// The contents of an image have been cleared.
+    fn clear(&self, id: TraceId);
+
+    /// An area of an image is now "active". See `ActiveRegion`.
+    fn active(&self, id: TraceId, region: ActiveRegion);
+}
+
+/// The number of each kind of action performed on an image.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ActionCounts {
+    pub reads: usize,
+    pub writes: usize,
+    pub clears: usize
+}
+
+/// Counts the actions performed on each image, without recording them.
+#[derive(Debug, Default)]
+pub struct CountingTracer {
+    counts: RefCell<Vec<ActionCounts>>
+}
+
+impl CountingTracer {
+    pub fn new() -> CountingTracer {
+        CountingTracer::default()
+    }
+
+    /// The counts for a single image.
+    pub fn counts(&self, id: TraceId) -> ActionCounts {
+        self.counts.borrow()[id.0]
+    }
+
+    /// The sums of the counts for all images.
+    pub fn total(&self) -> ActionCounts {
+        self.counts.borrow().iter().fold(ActionCounts::default(), |acc, c| ActionCounts {
+            reads: acc.reads + c.reads,
+            writes: acc.writes + c.writes,
+            clears: acc.clears + c.clears
+        })
+    }
+
+    fn update(&self, id: TraceId, f: impl FnOnce(&mut ActionCounts)) {
+        f(&mut self.counts.borrow_mut()[id.0]);
+    }
+}
+
+impl Tracer for CountingTracer {
+    fn create_trace_id(&self, _: &GrayImage) -> TraceId {
+        let mut counts = self.counts.borrow_mut();
+        counts.push(ActionCounts::default());
+        TraceId(counts.len() - 1)
+    }
+
+    fn read(&self, id: TraceId, _: usize, _: usize) {
+        self.update(id, |c| c.reads += 1);
+    }
+
+    fn write(&self, id: TraceId, _: usize, _: usize, _: u8) {
+        self.update(id, |c| c.writes += 1);
+    }
+
+    fn clear(&self, id: TraceId) {
+        self.update(id, |c| c.clears += 1);
+    }
+
+    fn active(&self, _: TraceId, _: ActiveRegion) {}
+}
+
+/// Forwards to another tracer only the actions whose index, counting from zero
+/// in the order they are performed, lies within a given window. This allows
+/// replaying a small part of a long running pipeline.
+#[derive(Debug)]
+pub struct WindowedTracer<T: Tracer> {
+    pub inner: T,
+    window: Range<usize>,
+    count: Cell<usize>
+}
+
+impl<T: Tracer> WindowedTracer<T> {
+    pub fn new(inner: T, window: Range<usize>) -> WindowedTracer<T> {
+        WindowedTracer { inner, window, count: Cell::new(0) }
+    }
+
+    /// Returns true if the next action should be forwarded.
+    fn next_in_window(&self) -> bool {
+        let index = self.count.get();
+        self.count.set(index + 1);
+        self.window.contains(&index)
+    }
+}
+
+impl<T: Tracer> Tracer for WindowedTracer<T> {
+    fn create_trace_id(&self, initial_image: &GrayImage) -> TraceId {
+        self.inner.create_trace_id(initial_image)
+    }
+
+    fn read(&self, id: TraceId, x: usize, y: usize) {
+        if self.next_in_window() {
+            self.inner.read(id, x, y);
+        }
+    }
+
+    fn write(&self, id: TraceId, x: usize, y: usize, c: u8) {
+        if self.next_in_window() {
+            self.inner.write(id, x, y, c);
+        }
+    }
+
+    fn clear(&self, id: TraceId) {
+        if self.next_in_window() {
+            self.inner.clear(id);
+        }
+    }
+
+    fn active(&self, id: TraceId, region: ActiveRegion) {
+        if self.next_in_window() {
+            self.inner.active(id, region);
+        }
+    }
+}
+
+/// Writes each action to a stream as soon as it's performed, in the same
+/// format as the `Debug` representation of the entries of `Trace::actions`.
+pub struct TraceWriter<W: Write> {
+    writer: RefCell<W>,
+    image_count: Cell<usize>,
+    /// The first error returned by `writer`. No further writes are attempted after an error.
+    error: RefCell<Option<std::io::Error>>
+}
+
+impl TraceWriter<BufWriter<File>> {
+    /// Creates a writer which streams actions to the given file.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<TraceWriter<BufWriter<File>>> {
+        Ok(TraceWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> TraceWriter<W> {
+        TraceWriter {
+            writer: RefCell::new(writer),
+            image_count: Cell::new(0),
+            error: RefCell::new(None)
+        }
+    }
+
+    /// Flushes and returns the underlying writer, or the first error encountered when writing.
+    pub fn finish(self) -> Result<W> {
+        if let Some(e) = self.error.into_inner() {
+            return Err(e);
+        }
+        let mut writer = self.writer.into_inner();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn push(&self, action: Action) {
+        if self.error.borrow().is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.writer.borrow_mut(), "{:?}", (current_worker(), action)) {
+            *self.error.borrow_mut() = Some(e);
+        }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn create_trace_id(&self, _: &GrayImage) -> TraceId {
+        let id = TraceId(self.image_count.get());
+        self.image_count.set(id.0 + 1);
+        id
+    }
+
+    fn read(&self, id: TraceId, x: usize, y: usize) {
+        self.push(Action::Read(id, x, y));
+    }
+
+    fn write(&self, id: TraceId, x: usize, y: usize, c: u8) {
+        self.push(Action::Write(id, x, y, c));
+    }
+
+    fn clear(&self, id: TraceId) {
+        self.push(Action::Clear(id));
+    }
+
+    fn active(&self, id: TraceId, region: ActiveRegion) {
+        self.push(Action::Active(id, region));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace_actions<T: Tracer>(tracer: &T) {
+        let image = GrayImage::new(2, 2);
+        let (a, b) = (tracer.create_trace_id(&image), tracer.create_trace_id(&image));
+        tracer.read(a, 0, 1);
+        tracer.write(b, 1, 1, 7);
+        tracer.read(a, 1, 0);
+        tracer.clear(b);
+        tracer.write(b, 0, 0, 3);
+    }
+
+    #[test]
+    fn test_tracers_agree_with_trace() {
+        let trace = Trace::new();
+        trace_actions(&trace);
+        let actions: Vec<(WorkerId, Action)> = trace.actions.borrow().clone();
+
+        let counter = CountingTracer::new();
+        trace_actions(&counter);
+        assert_eq!(counter.counts(TraceId(0)), ActionCounts { reads: 2, writes: 0, clears: 0 });
+        assert_eq!(counter.counts(TraceId(1)), ActionCounts { reads: 0, writes: 2, clears: 1 });
+        assert_eq!(counter.total(), ActionCounts { reads: 2, writes: 2, clears: 1 });
+
+        let windowed = WindowedTracer::new(Trace::new(), 1..3);
+        trace_actions(&windowed);
+        assert_eq!(*windowed.inner.actions.borrow(), actions[1..3].to_vec());
+        assert_eq!(windowed.inner.initial_images.borrow().len(), 2);
+
+        let writer = TraceWriter::new(vec![]);
+        trace_actions(&writer);
+        let text = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let expected: String = actions.iter().map(|a| format!("{:?}\n", a)).collect();
+        assert_eq!(text, expected);
+    }
+}